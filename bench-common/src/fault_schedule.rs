@@ -0,0 +1,159 @@
+//! The fault schedule mounted in every SV1 and SV2 proxy of a configuration, so one file puts
+//! both sides of a comparison under the same conditions. Every entry names the proxy types it
+//! applies to in `proxies`, along with the [`Conditions`] shared by both formats, and the fault
+//! in the format of these proxies.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+/// When, in which direction and how often a fault is injected: in `direction` (`upstream` or
+/// `downstream`, both when missing), with `probability`, between `from_s` and `until_s` seconds
+/// after the proxy started.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Conditions {
+    #[serde(default)]
+    pub direction: Option<String>,
+    #[serde(default = "always")]
+    pub probability: f64,
+    #[serde(default)]
+    pub from_s: u64,
+    #[serde(default)]
+    pub until_s: Option<u64>,
+}
+
+fn always() -> f64 {
+    1.0
+}
+
+impl Conditions {
+    pub fn active(&self, elapsed: Duration) -> bool {
+        elapsed >= Duration::from_secs(self.from_s)
+            && self
+                .until_s
+                .iter()
+                .all(|until| elapsed < Duration::from_secs(*until))
+    }
+
+    pub fn matches(&self, direction: &str) -> bool {
+        self.direction.iter().all(|d| d == direction)
+    }
+}
+
+/// The rules of `proxy_type` in the schedule at `FAULT_SCHEDULE`, none when it isn't set.
+/// Panics on a schedule that can't be read or parsed.
+pub fn from_env<R: DeserializeOwned>(proxy_type: &str) -> Vec<R> {
+    // Empty when the compose file passes an unset FAULT_SCHEDULE through
+    let Some(path) = env::var("FAULT_SCHEDULE")
+        .ok()
+        .filter(|path| !path.is_empty())
+    else {
+        return Vec::new();
+    };
+    let schedule = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Can't read FAULT_SCHEDULE {}: {}", path, e));
+    parse(&schedule, proxy_type)
+        .unwrap_or_else(|e| panic!("Invalid FAULT_SCHEDULE {}: {}", path, e))
+}
+
+/// The rules of `proxy_type` in `schedule`. The entries of the other proxies are in their own
+/// format, so they're only checked for `proxies`.
+pub fn parse<R: DeserializeOwned>(schedule: &str, proxy_type: &str) -> Result<Vec<R>, String> {
+    let entries: Vec<Value> = serde_json::from_str(schedule).map_err(|e| e.to_string())?;
+    let mut rules = Vec::new();
+    for mut entry in entries {
+        let proxies = entry
+            .as_object_mut()
+            .and_then(|entry| entry.remove("proxies"))
+            .ok_or_else(|| format!("entry without proxies: {}", entry))?;
+        let proxies = proxies
+            .as_array()
+            .ok_or_else(|| format!("proxies must be an array: {}", entry))?;
+        if !proxies.iter().any(|proxy| proxy == proxy_type) {
+            continue;
+        }
+        rules.push(serde_json::from_value(entry.clone()).map_err(|e| format!("{}: {}", entry, e))?);
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(tag = "fault", rename_all = "snake_case", deny_unknown_fields)]
+    enum Fault {
+        Delay { ms: u64 },
+        Drop {},
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Rule {
+        #[serde(default)]
+        message: Option<String>,
+        #[serde(flatten)]
+        conditions: Conditions,
+        #[serde(flatten)]
+        fault: Fault,
+    }
+
+    #[test]
+    fn only_the_entries_of_the_proxy_are_parsed() {
+        let schedule = r#"[
+            {"proxies": ["tp-pool"], "message": "SetNewPrevHash", "from_s": 10, "until_s": 20,
+             "fault": "delay", "ms": 500},
+            {"proxies": ["pool-miner"], "method": "mining.submit", "fault": "duplicate"}
+        ]"#;
+        let rules: Vec<Rule> = parse(schedule, "tp-pool").unwrap();
+        assert_eq!(
+            rules,
+            vec![Rule {
+                message: Some("SetNewPrevHash".to_string()),
+                conditions: Conditions {
+                    direction: None,
+                    probability: 1.0,
+                    from_s: 10,
+                    until_s: Some(20),
+                },
+                fault: Fault::Delay { ms: 500 },
+            }]
+        );
+        assert!(parse::<Rule>(schedule, "jdc-translator")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn entries_must_list_their_proxies() {
+        let schedule = r#"[{"method": "mining.submit", "fault": "drop", "probability": 0.05}]"#;
+        assert!(parse::<Rule>(schedule, "pool-translator").is_err());
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let schedule = r#"[{"proxies": ["tp-pool"], "fault": "drop", "rate": 0.05}]"#;
+        assert!(parse::<Rule>(schedule, "tp-pool").is_err());
+        let schedule = r#"[{"proxies": ["tp-pool"], "fault": "drop", "probability": 0.05}]"#;
+        let rules: Vec<Rule> = parse(schedule, "tp-pool").unwrap();
+        assert_eq!(rules[0].conditions.probability, 0.05);
+    }
+
+    #[test]
+    fn conditions_window() {
+        let conditions = Conditions {
+            direction: Some("upstream".to_string()),
+            probability: 1.0,
+            from_s: 10,
+            until_s: Some(20),
+        };
+        assert!(!conditions.active(Duration::from_secs(9)));
+        assert!(conditions.active(Duration::from_secs(10)));
+        assert!(!conditions.active(Duration::from_secs(20)));
+        assert!(conditions.matches("upstream"));
+        assert!(!conditions.matches("downstream"));
+    }
+}
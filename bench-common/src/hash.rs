@@ -0,0 +1,108 @@
+use sha2::{Digest, Sha256};
+
+// Target of difficulty 1 (0xffff * 2^208)
+const DIFFICULTY_1_TARGET: f64 = 2.695_953_529_101_131e67;
+
+pub fn sha256d(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(Sha256::digest(data)).into()
+}
+
+/// Whether a hash, read as a little endian number, is at most the little endian target.
+pub fn meets(hash: &[u8; 32], target: &[u8; 32]) -> bool {
+    hash.iter().rev().cmp(target.iter().rev()).is_le()
+}
+
+/// Little endian target of the compact `bits` of a header.
+pub fn target(bits: u32) -> [u8; 32] {
+    let exponent = (bits >> 24) as usize;
+    let mantissa = (bits & 0x007f_ffff).to_le_bytes();
+    let mut target = [0u8; 32];
+    for (index, byte) in mantissa[..3].iter().enumerate() {
+        // The mantissa is multiplied by 256^(exponent - 3)
+        if let Some(position) = (index + exponent).checked_sub(3) {
+            if position < 32 {
+                target[position] = *byte;
+            }
+        }
+    }
+    target
+}
+
+/// Difficulty of a little endian target.
+pub fn difficulty(target: &[u8; 32]) -> f64 {
+    let value = target
+        .iter()
+        .rev()
+        .fold(0.0, |value, byte| value * 256.0 + *byte as f64);
+    DIFFICULTY_1_TARGET / value
+}
+
+/// Little endian target of a share difficulty, the highest one when it overflows 256 bits.
+pub fn difficulty_target(difficulty: f64) -> [u8; 32] {
+    let mut value = DIFFICULTY_1_TARGET / difficulty;
+    let mut target = [0u8; 32];
+    if value >= 256f64.powi(32) {
+        return [0xff; 32];
+    }
+    for position in (0..32).rev() {
+        let unit = 256f64.powi(position as i32);
+        let byte = (value / unit).floor().min(255.0);
+        target[position] = byte as u8;
+        value -= byte * unit;
+    }
+    target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENESIS_HEADER: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+    const GENESIS_HASH: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+
+    #[test]
+    fn genesis_block_meets_its_target() {
+        let mut header = hex::decode(GENESIS_HEADER).unwrap();
+        let mut hash = sha256d(&header);
+        let bits = u32::from_le_bytes(header[72..76].try_into().unwrap());
+        assert_eq!(bits, 0x1d00ffff);
+        assert!(meets(&hash, &target(bits)));
+        hash.reverse();
+        assert_eq!(hex::encode(hash), GENESIS_HASH);
+
+        // Any other nonce misses it
+        header[76] ^= 1;
+        assert!(!meets(&sha256d(&header), &target(bits)));
+    }
+
+    #[test]
+    fn difficulty_1_target() {
+        let mut expected = [0u8; 32];
+        expected[26] = 0xff;
+        expected[27] = 0xff;
+        assert_eq!(target(0x1d00ffff), expected);
+        assert_eq!(difficulty_target(1.0), expected);
+        assert_eq!(difficulty(&expected), 1.0);
+    }
+
+    #[test]
+    fn meets_compares_every_byte() {
+        let target = target(0x1d00ffff);
+        let mut hash = target;
+        assert!(meets(&hash, &target));
+        hash[0] = 1;
+        assert!(!meets(&hash, &target));
+        hash[26] = 0xfe;
+        assert!(meets(&hash, &target));
+    }
+
+    #[test]
+    fn difficulty_target_round_trips() {
+        for difficulty_value in [0.001, 1.0, 1024.0, 1e12] {
+            let target = difficulty_target(difficulty_value);
+            let error = (difficulty(&target) - difficulty_value).abs() / difficulty_value;
+            assert!(error < 1e-9);
+        }
+        assert_eq!(difficulty_target(1e-80), [0xff; 32]);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::hash::{meets, sha256d, target};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc::UnboundedSender, watch};
+
+// Hashes between two checks for new work, and between two pauses keeping to the hashrate
+const MAX_BATCH: u64 = 50_000;
+
+/// How the merkle root of the header is obtained.
+#[derive(Clone)]
+pub enum Merkle {
+    // Standard job: the root is given, only the header is rolled
+    Root([u8; 32]),
+    // Extended job: the coinbase is built around the extranonce of the device
+    Coinbase {
+        // The coinbase up to the extranonce of the device, the extranonce prefix included
+        prefix: Vec<u8>,
+        suffix: Vec<u8>,
+        path: Vec<[u8; 32]>,
+        extranonce_size: usize,
+    },
+}
+
+/// What a device hashes: an active job, the hashes in header byte order.
+#[derive(Clone)]
+pub struct Work<J> {
+    // What the shares of the work are submitted for
+    pub job: J,
+    pub version: u32,
+    // Bits of the version the device may roll, 0 when the job doesn't allow it
+    pub version_mask: u32,
+    pub prev_hash: [u8; 32],
+    pub merkle: Merkle,
+    pub time: u32,
+    pub bits: u32,
+    // Little endian
+    pub target: [u8; 32],
+}
+
+/// A header meeting the target of the channel.
+pub struct Share<J> {
+    pub job: J,
+    pub extranonce: Vec<u8>,
+    pub time: u32,
+    pub nonce: u32,
+    pub version: u32,
+    // Whether it also meets the target of the network
+    pub block: bool,
+}
+
+/// Hashes the work of a device at `hashrate` hashes per second until the work channel closes,
+/// sending the shares found and counting the hashes done.
+pub fn run<J: Clone>(
+    mut work: watch::Receiver<Option<Work<J>>>,
+    shares: UnboundedSender<Share<J>>,
+    hashrate: f64,
+    hashes: prometheus::Counter,
+) {
+    let batch = ((hashrate / 10.0) as u64).clamp(1, MAX_BATCH);
+    let batch_duration = Duration::from_secs_f64(batch as f64 / hashrate);
+    loop {
+        let Some(current) = work.borrow_and_update().clone() else {
+            if work.has_changed().is_err() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+            continue;
+        };
+        let network_target = target(current.bits);
+        let rolls = 1u64 << current.version_mask.count_ones();
+        let chunks = (u32::MAX as u64 + 1).div_ceil(batch);
+        // The version is rolled on every batch, then the nonces, then the extranonce (the time
+        // when the job leaves no extranonce to the device)
+        let mut merkle_root = None;
+        for step in 0.. {
+            let started = Instant::now();
+            let roll = step % rolls;
+            let chunk = (step / rolls) % chunks;
+            let counter = step / rolls / chunks;
+            let (extranonce, time) = match &current.merkle {
+                Merkle::Coinbase {
+                    extranonce_size, ..
+                } if *extranonce_size > 0 => (extranonce(counter, *extranonce_size), current.time),
+                _ => (Vec::new(), current.time.wrapping_add(counter as u32)),
+            };
+            if roll == 0 && chunk == 0 {
+                merkle_root = None;
+            }
+            let root = *merkle_root.get_or_insert_with(|| match &current.merkle {
+                Merkle::Root(root) => *root,
+                Merkle::Coinbase {
+                    prefix,
+                    suffix,
+                    path,
+                    ..
+                } => {
+                    let mut coinbase = prefix.clone();
+                    coinbase.extend_from_slice(&extranonce);
+                    coinbase.extend_from_slice(suffix);
+                    path.iter().fold(sha256d(&coinbase), |root, branch| {
+                        let mut pair = root.to_vec();
+                        pair.extend_from_slice(branch);
+                        sha256d(&pair)
+                    })
+                }
+            });
+            let version =
+                (current.version & !current.version_mask) | spread(roll, current.version_mask);
+            let mut header = [0u8; 80];
+            header[0..4].copy_from_slice(&version.to_le_bytes());
+            header[4..36].copy_from_slice(&current.prev_hash);
+            header[36..68].copy_from_slice(&root);
+            header[68..72].copy_from_slice(&time.to_le_bytes());
+            header[72..76].copy_from_slice(&current.bits.to_le_bytes());
+            let first = chunk * batch;
+            let end = (first + batch).min(u32::MAX as u64 + 1);
+            for nonce in first..end {
+                header[76..80].copy_from_slice(&(nonce as u32).to_le_bytes());
+                let hash = sha256d(&header);
+                if meets(&hash, &current.target) {
+                    let share = Share {
+                        job: current.job.clone(),
+                        extranonce: extranonce.clone(),
+                        time,
+                        nonce: nonce as u32,
+                        version,
+                        block: meets(&hash, &network_target),
+                    };
+                    if shares.send(share).is_err() {
+                        return;
+                    }
+                }
+            }
+            hashes.inc_by((end - first) as f64);
+            if let Some(pause) = batch_duration.checked_sub(started.elapsed()) {
+                std::thread::sleep(pause);
+            }
+            match work.has_changed() {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+fn extranonce(counter: u64, size: usize) -> Vec<u8> {
+    let bytes = counter.to_be_bytes();
+    let mut extranonce = vec![0u8; size];
+    let copied = size.min(bytes.len());
+    extranonce[size - copied..].copy_from_slice(&bytes[bytes.len() - copied..]);
+    extranonce
+}
+
+// Spreads the bits of `roll` over the bits set in `mask`
+fn spread(roll: u64, mask: u32) -> u32 {
+    let mut bits = 0u32;
+    let mut next = 0;
+    for position in 0..32 {
+        if mask & (1 << position) != 0 {
+            if roll & (1 << next) != 0 {
+                bits |= 1 << position;
+            }
+            next += 1;
+        }
+    }
+    bits
+}
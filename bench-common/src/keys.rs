@@ -0,0 +1,9 @@
+//! Authority keys and certificate validity `ProxyBuilder` uses by default. Every SV2 endpoint
+//! of the benchmark accepting noise connections uses them too, so the roles configured to talk
+//! to a proxy keep working unchanged against a tap, a replay or the mock template provider.
+
+use std::time::Duration;
+
+pub const PUB_KEY: &str = "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72";
+pub const SEC_KEY: &str = "mkDLTBBRxdBv998612qipDYoTK3YUrqLe8uWw7gu3iXbSrn2n";
+pub const CERT_VALIDITY: Duration = Duration::from_secs(10000);
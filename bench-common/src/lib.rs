@@ -0,0 +1,5 @@
+pub mod fault_schedule;
+pub mod hash;
+pub mod hasher;
+pub mod keys;
+pub mod rpc_auth;
@@ -0,0 +1,183 @@
+//! Credentials of the proxies calling the RPC of a node.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+// Credentials of the nodes in the docker compose files
+const DEFAULT_USER: &str = "username";
+const DEFAULT_PASSWORD: &str = "password";
+
+/// How to authenticate to a node, every field can be given directly or read from a file.
+#[derive(Deserialize, Debug)]
+pub struct CredentialsConfig {
+    // bitcoind `.cookie` file, read again on every call since the node rewrites it on restart
+    pub cookie_file: Option<String>,
+    pub user: Option<String>,
+    pub user_file: Option<String>,
+    pub password: Option<String>,
+    pub password_file: Option<String>,
+}
+
+impl CredentialsConfig {
+    fn from_env() -> Self {
+        Self {
+            cookie_file: env::var("RPC_COOKIE_FILE").ok(),
+            user: env::var("RPC_USER").ok(),
+            user_file: env::var("RPC_USER_FILE").ok(),
+            password: env::var("RPC_PASSWORD").ok(),
+            password_file: env::var("RPC_PASSWORD_FILE").ok(),
+        }
+    }
+
+    fn credentials(self) -> Option<Credentials> {
+        if let Some(cookie_file) = self.cookie_file {
+            return Some(Credentials::Cookie(cookie_file));
+        }
+        let user = self
+            .user
+            .or_else(|| self.user_file.map(|file| secret(&file)))?;
+        let password = self
+            .password
+            .or_else(|| self.password_file.map(|file| secret(&file)))
+            .unwrap_or_else(|| panic!("No RPC password given for user {}", user));
+        Some(Credentials::Password(basic(&format!(
+            "{}:{}",
+            user, password
+        ))))
+    }
+}
+
+fn secret(file: &str) -> String {
+    fs::read_to_string(file)
+        .unwrap_or_else(|e| panic!("Can't read secret file {}: {}", file, e))
+        .trim_end()
+        .to_string()
+}
+
+fn basic(user_password: &str) -> String {
+    format!("Basic {}", STANDARD.encode(user_password))
+}
+
+enum Credentials {
+    Cookie(String),
+    // Ready to use `Authorization` header value
+    Password(String),
+    Default(String),
+}
+
+/// Credentials for the node at `server`. They come, in order, from the entry for `server` in the
+/// JSON file at `RPC_AUTH_FILE`, from the `RPC_COOKIE_FILE` or `RPC_USER` and `RPC_PASSWORD`
+/// variables (`RPC_USER_FILE` and `RPC_PASSWORD_FILE` to read them from secret files), or are the
+/// ones of the docker compose nodes.
+pub struct RpcCredentials {
+    credentials: Credentials,
+}
+
+impl RpcCredentials {
+    pub fn from_env(server: &str) -> Self {
+        let mut upstreams: HashMap<String, CredentialsConfig> = match env::var("RPC_AUTH_FILE") {
+            Ok(path) => {
+                let config = fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("Can't read RPC_AUTH_FILE {}: {}", path, e));
+                serde_json::from_str(&config)
+                    .unwrap_or_else(|e| panic!("Invalid RPC_AUTH_FILE {}: {}", path, e))
+            }
+            Err(_) => HashMap::new(),
+        };
+        let config = upstreams
+            .remove(server)
+            .or_else(|| upstreams.remove(server.trim_end_matches('/')))
+            .unwrap_or_else(CredentialsConfig::from_env);
+        let credentials = Self::new(config);
+        log::info!(
+            "Authenticating to {} with {} credentials",
+            server,
+            credentials.source()
+        );
+        credentials
+    }
+
+    fn new(config: CredentialsConfig) -> Self {
+        let credentials = config.credentials().unwrap_or_else(|| {
+            Credentials::Default(basic(&format!("{}:{}", DEFAULT_USER, DEFAULT_PASSWORD)))
+        });
+        Self { credentials }
+    }
+
+    /// Returns the `Authorization` header value to add, with where it comes from.
+    pub fn authorization(&self) -> Option<(String, &'static str)> {
+        let value = match &self.credentials {
+            Credentials::Cookie(file) => match fs::read_to_string(file) {
+                Ok(cookie) => basic(cookie.trim_end()),
+                Err(e) => {
+                    log::error!("Can't read the RPC cookie file {}: {}", file, e);
+                    return None;
+                }
+            },
+            Credentials::Password(value) | Credentials::Default(value) => value.clone(),
+        };
+        Some((value, self.source()))
+    }
+
+    fn source(&self) -> &'static str {
+        match self.credentials {
+            Credentials::Cookie(_) => "cookie",
+            Credentials::Password(_) => "password",
+            Credentials::Default(_) => "default",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(user: Option<&str>, password: Option<&str>) -> CredentialsConfig {
+        CredentialsConfig {
+            cookie_file: None,
+            user: user.map(str::to_string),
+            user_file: None,
+            password: password.map(str::to_string),
+            password_file: None,
+        }
+    }
+
+    #[test]
+    fn passwords_and_defaults() {
+        let credentials = RpcCredentials::new(config(Some("pool"), Some("secret")));
+        assert_eq!(
+            credentials.authorization(),
+            Some(("Basic cG9vbDpzZWNyZXQ=".to_string(), "password"))
+        );
+        let credentials = RpcCredentials::new(config(None, None));
+        assert_eq!(
+            credentials.authorization(),
+            Some(("Basic dXNlcm5hbWU6cGFzc3dvcmQ=".to_string(), "default"))
+        );
+    }
+
+    #[test]
+    fn cookies_are_read_on_every_call() {
+        let cookie_file = env::temp_dir().join(format!("rpc-auth-{}.cookie", std::process::id()));
+        let credentials = RpcCredentials::new(CredentialsConfig {
+            cookie_file: Some(cookie_file.to_string_lossy().into_owned()),
+            ..config(None, None)
+        });
+        assert_eq!(credentials.authorization(), None);
+        fs::write(&cookie_file, "__cookie__:abc\n").unwrap();
+        assert_eq!(
+            credentials.authorization(),
+            Some(("Basic X19jb29raWVfXzphYmM=".to_string(), "cookie"))
+        );
+        fs::write(&cookie_file, "__cookie__:def").unwrap();
+        assert_eq!(
+            credentials.authorization(),
+            Some(("Basic X19jb29raWVfXzpkZWY=".to_string(), "cookie"))
+        );
+        fs::remove_file(cookie_file).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+use serde_json::{json, Value};
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Instant, SystemTime};
+use warp::Filter;
+
+const DEFAULT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+const DEFAULT_MAX_FILES: usize = 10;
+// Lines waiting for the writer thread, beyond which they're dropped rather than holding the
+// forwarding back
+const MAX_PENDING_LINES: usize = 65_536;
+
+struct CaptureFile {
+    dir: PathBuf,
+    prefix: String,
+    max_bytes: u64,
+    max_files: usize,
+    file: Option<BufWriter<File>>,
+    written: u64,
+    sequence: u64,
+}
+
+impl CaptureFile {
+    // Writes the lines as they come, flushing whenever none is waiting
+    fn run(mut self, lines: Receiver<Vec<u8>>) {
+        while let Ok(line) = lines.recv() {
+            let mut result = self.write_line(&line);
+            while result.is_ok() {
+                match lines.try_recv() {
+                    Ok(line) => result = self.write_line(&line),
+                    Err(_) => break,
+                }
+            }
+            if let Some(file) = self.file.as_mut() {
+                result = result.and_then(|()| file.flush());
+            }
+            if let Err(e) = result {
+                log::error!("Failed to write capture: {}", e);
+            }
+        }
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        if self.file.is_none() || self.written + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(line)?;
+            self.written += line.len() as u64;
+        }
+        Ok(())
+    }
+
+    // Opens the next capture file and drops the oldest ones beyond `max_files`
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        self.sequence += 1;
+        let path = self
+            .dir
+            .join(format!("{}-{:04}.jsonl", self.prefix, self.sequence));
+        log::info!("Writing capture to {}", path.display());
+        self.file = Some(BufWriter::new(File::create(path)?));
+        self.written = 0;
+
+        // Several proxies may share the same capture directory
+        let mut files = list_files(&self.dir);
+        files.retain(|(name, _)| name.starts_with(&self.prefix));
+        while files.len() > self.max_files {
+            let (name, _) = files.remove(0);
+            if let Err(e) = fs::remove_file(self.dir.join(&name)) {
+                log::error!("Failed to remove capture file {}: {}", name, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Size-rotated newline-delimited JSON files the proxies record their traffic to. Enabled by
+/// setting `CAPTURE_DIR`; `CAPTURE_MAX_BYTES` and `CAPTURE_MAX_FILES` bound the size of every
+/// file and how many of them are kept. The files are written by a thread of their own.
+#[derive(Clone)]
+pub struct CaptureFiles {
+    dir: PathBuf,
+    lines: SyncSender<Vec<u8>>,
+    started: Instant,
+}
+
+impl CaptureFiles {
+    /// Files are named after `prefix` and the time the proxy started.
+    pub fn from_env(prefix: &str) -> Option<Self> {
+        let dir = PathBuf::from(env::var("CAPTURE_DIR").ok()?);
+        let max_bytes = env::var("CAPTURE_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_BYTES);
+        let max_files = env::var("CAPTURE_MAX_FILES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_FILES);
+        if let Err(e) = fs::create_dir_all(&dir) {
+            log::error!("Capture disabled, can't create {}: {}", dir.display(), e);
+            return None;
+        }
+        let file = CaptureFile {
+            dir: dir.clone(),
+            prefix: format!("{}-{}", prefix, now_millis()),
+            max_bytes,
+            max_files,
+            file: None,
+            written: 0,
+            sequence: 0,
+        };
+        let (lines, receiver) = sync_channel(MAX_PENDING_LINES);
+        thread::spawn(move || file.run(receiver));
+        Some(Self {
+            dir,
+            lines,
+            started: Instant::now(),
+        })
+    }
+
+    pub fn dir(&self) -> PathBuf {
+        self.dir.clone()
+    }
+
+    /// Writes a message of `connection` going in `direction`, timestamped, along with the
+    /// `fields` describing the message.
+    pub fn record(&self, connection: u64, direction: &str, fields: Value) {
+        let mut entry = json!({
+            "connection": connection,
+            "direction": direction,
+            "monotonic_us": self.started.elapsed().as_micros() as u64,
+            "unix_ms": now_millis() as u64,
+        });
+        if let (Some(entry), Value::Object(fields)) = (entry.as_object_mut(), fields) {
+            entry.extend(fields);
+        }
+        let mut line = entry.to_string().into_bytes();
+        line.push(b'\n');
+        match self.lines.try_send(line) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => log::warn!("Capture writer behind, dropping a line"),
+            Err(TrySendError::Disconnected(_)) => log::error!("Capture writer stopped"),
+        }
+    }
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis()
+}
+
+fn list_files(dir: &Path) -> Vec<(String, u64)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<(String, u64)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(".jsonl"))
+        .filter_map(|entry| {
+            let size = entry.metadata().ok()?.len();
+            Some((entry.file_name().to_string_lossy().to_string(), size))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// `GET /capture` lists the capture files, `GET /capture/<file>` downloads one of them.
+pub fn routes(
+    files: Option<CaptureFiles>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let dir = files.map(|files| files.dir());
+    let list_dir = dir.clone();
+    let list = warp::path("capture")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || {
+            let files: Vec<Value> = list_dir
+                .as_deref()
+                .map(list_files)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(name, size)| {
+                    json!({ "name": name, "size": size, "url": format!("/capture/{}", name) })
+                })
+                .collect();
+            warp::reply::json(&files)
+        });
+    // With capture disabled downloads point to a missing directory and always 404
+    let download = warp::path("capture").and(warp::fs::dir(
+        dir.unwrap_or_else(|| PathBuf::from("/nonexistent")),
+    ));
+    list.or(download)
+}
@@ -0,0 +1,377 @@
+use chrono::{DateTime, SecondsFormat};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const MAX_SAMPLES: usize = 5;
+
+struct Rule {
+    name: &'static str,
+    description: &'static str,
+    patterns: &'static [&'static str],
+}
+
+// Every log line is checked against each rule, so a single line can show up under more than
+// one rule (e.g. an `ERROR` line reporting a `SubmitSharesError`).
+const RULES: &[Rule] = &[
+    Rule {
+        name: "panic",
+        description: "Rust panics and aborted threads",
+        patterns: &["panicked at"],
+    },
+    Rule {
+        name: "error_level",
+        description: "Lines logged at ERROR level",
+        patterns: &["ERROR"],
+    },
+    Rule {
+        name: "submit_shares_error",
+        description: "Shares rejected by the SV2 upstream",
+        patterns: &["SubmitSharesError"],
+    },
+    Rule {
+        name: "failed_to_transfer",
+        description: "SV1 custom proxy failing to forward traffic",
+        patterns: &["Failed to transfer"],
+    },
+    Rule {
+        name: "sri_connection_error",
+        description: "Known SRI roles connection and handshake errors",
+        patterns: &[
+            "Shutting down noise stream reader",
+            "Disconnecting from client",
+            "Disconnected from client",
+            "Impossible to",
+            "UpstreamClosed",
+            "DownstreamClosed",
+            "Irrecoverable error",
+        ],
+    },
+    Rule {
+        name: "connection_reset",
+        description: "Connections reset or broken by the peer",
+        patterns: &[
+            "Connection reset",
+            "connection reset",
+            "Broken pipe",
+            "broken pipe",
+            "Connection refused",
+            "connection refused",
+        ],
+    },
+    Rule {
+        name: "reconnect",
+        description: "Roles reconnecting or falling back to another upstream",
+        patterns: &["reconnect", "Reconnect", "fallback"],
+    },
+];
+
+#[derive(Serialize, Debug, Default)]
+pub struct Occurrences {
+    pub count: usize,
+    pub first_seen: Option<String>,
+    pub last_seen: Option<String>,
+    pub samples: Vec<String>,
+}
+
+impl Occurrences {
+    fn record(&mut self, timestamp: &str, line: &str) {
+        self.count += 1;
+        match &self.first_seen {
+            Some(first) if first.as_str() <= timestamp => {}
+            _ => self.first_seen = Some(timestamp.to_string()),
+        }
+        match &self.last_seen {
+            Some(last) if last.as_str() >= timestamp => {}
+            _ => self.last_seen = Some(timestamp.to_string()),
+        }
+        if self.samples.len() < MAX_SAMPLES {
+            self.samples.push(line.to_string());
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct RuleFindings {
+    pub rule: &'static str,
+    pub description: &'static str,
+    pub total: Occurrences,
+    pub containers: BTreeMap<String, Occurrences>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Findings {
+    pub log_label: String,
+    pub scanned_containers: Vec<String>,
+    pub scanned_lines: usize,
+    pub rules: Vec<RuleFindings>,
+}
+
+impl Findings {
+    pub fn new(log_label: &str) -> Self {
+        Self {
+            log_label: log_label.to_string(),
+            scanned_containers: Vec::new(),
+            scanned_lines: 0,
+            rules: RULES
+                .iter()
+                .map(|rule| RuleFindings {
+                    rule: rule.name,
+                    description: rule.description,
+                    total: Occurrences::default(),
+                    containers: BTreeMap::new(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Scans the `(timestamp, line)` entries fetched from Loki for a single container.
+    pub fn scan(&mut self, container: &str, logs: &[(String, String)]) {
+        self.scanned_containers.push(container.to_string());
+        for (timestamp, line) in logs {
+            self.scanned_lines += 1;
+            let line = strip_ansi_codes(line);
+            let timestamp = format_timestamp(timestamp);
+            for (rule, findings) in RULES.iter().zip(self.rules.iter_mut()) {
+                if rule.patterns.iter().any(|pattern| line.contains(pattern)) {
+                    findings.total.record(&timestamp, &line);
+                    findings
+                        .containers
+                        .entry(container.to_string())
+                        .or_default()
+                        .record(&timestamp, &line);
+                }
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Renders the findings as a markdown document meant to be read before the raw logs.
+    pub fn summary(&self) -> String {
+        let mut summary = String::new();
+        writeln!(summary, "# Log findings for `{}`\n", self.log_label).unwrap();
+        writeln!(
+            summary,
+            "Scanned {} lines from {} containers: {}\n",
+            self.scanned_lines,
+            self.scanned_containers.len(),
+            self.scanned_containers.join(", ")
+        )
+        .unwrap();
+
+        writeln!(
+            summary,
+            "| Rule | Description | Count | First seen | Last seen |"
+        )
+        .unwrap();
+        writeln!(
+            summary,
+            "|------|-------------|-------|------------|-----------|"
+        )
+        .unwrap();
+        for findings in &self.rules {
+            writeln!(
+                summary,
+                "| {} | {} | {} | {} | {} |",
+                findings.rule,
+                findings.description,
+                findings.total.count,
+                findings.total.first_seen.as_deref().unwrap_or("-"),
+                findings.total.last_seen.as_deref().unwrap_or("-")
+            )
+            .unwrap();
+        }
+
+        for findings in self.rules.iter().filter(|f| f.total.count > 0) {
+            writeln!(summary, "\n## {}\n", findings.rule).unwrap();
+            for (container, occurrences) in &findings.containers {
+                writeln!(
+                    summary,
+                    "### {} ({} matches, first {}, last {})\n",
+                    container,
+                    occurrences.count,
+                    occurrences.first_seen.as_deref().unwrap_or("-"),
+                    occurrences.last_seen.as_deref().unwrap_or("-")
+                )
+                .unwrap();
+                writeln!(summary, "```").unwrap();
+                for sample in &occurrences.samples {
+                    writeln!(summary, "{}", sample).unwrap();
+                }
+                writeln!(summary, "```").unwrap();
+            }
+        }
+
+        summary
+    }
+}
+
+// Loki returns nanosecond unix timestamps as strings
+fn format_timestamp(timestamp: &str) -> String {
+    timestamp
+        .parse::<i64>()
+        .map(DateTime::from_timestamp_nanos)
+        .map(|datetime| datetime.to_rfc3339_opts(SecondsFormat::Millis, true))
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+// Proxies log with `default_write_style_or("always")`, so lines carry terminal color codes
+fn strip_ansi_codes(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One second apart from 2024-01-01T00:00:00Z
+    fn logs(lines: &[&str]) -> Vec<(String, String)> {
+        lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let nanos = (1_704_067_200 + i as i64) * 1_000_000_000;
+                (nanos.to_string(), line.to_string())
+            })
+            .collect()
+    }
+
+    fn counts(findings: &Findings) -> BTreeMap<&'static str, usize> {
+        findings
+            .rules
+            .iter()
+            .filter(|rule| rule.total.count > 0)
+            .map(|rule| (rule.rule, rule.total.count))
+            .collect()
+    }
+
+    fn rule<'a>(findings: &'a Findings, name: &str) -> &'a RuleFindings {
+        findings
+            .rules
+            .iter()
+            .find(|rule| rule.rule == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn every_rule_matches_its_lines() {
+        let cases = [
+            ("thread 'main' panicked at src/main.rs:10:5", "panic"),
+            ("2024-01-01 ERROR pool: invalid job", "error_level"),
+            (
+                "received SubmitSharesError for channel 1",
+                "submit_shares_error",
+            ),
+            ("Failed to transfer data: eof", "failed_to_transfer"),
+            ("Shutting down noise stream reader!", "sri_connection_error"),
+            ("Impossible to connect to upstream", "sri_connection_error"),
+            ("read failed: Connection reset by peer", "connection_reset"),
+            (
+                "write failed: Broken pipe (os error 32)",
+                "connection_reset",
+            ),
+            ("Trying to reconnect to the pool", "reconnect"),
+            ("Switching to the fallback upstream", "reconnect"),
+        ];
+        for (line, expected) in cases {
+            let mut findings = Findings::new("test");
+            findings.scan("pool", &logs(&[line]));
+            assert_eq!(
+                counts(&findings),
+                BTreeMap::from([(expected, 1)]),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn unrelated_lines_match_no_rule() {
+        let mut findings = Findings::new("test");
+        findings.scan(
+            "pool",
+            &logs(&["INFO new template received", "share accepted for job 3"]),
+        );
+        assert_eq!(findings.scanned_lines, 2);
+        assert!(counts(&findings).is_empty());
+    }
+
+    #[test]
+    fn a_line_counts_under_every_rule_it_matches() {
+        let mut findings = Findings::new("test");
+        findings.scan("jdc", &logs(&["ERROR SubmitSharesError: invalid share"]));
+        assert_eq!(
+            counts(&findings),
+            BTreeMap::from([("error_level", 1), ("submit_shares_error", 1)])
+        );
+    }
+
+    #[test]
+    fn color_codes_are_stripped() {
+        let mut findings = Findings::new("test");
+        findings.scan(
+            "proxy",
+            &logs(&["\u{1b}[31mERROR\u{1b}[0m proxy: Broken pipe"]),
+        );
+        let errors = rule(&findings, "error_level");
+        assert_eq!(errors.total.samples, vec!["ERROR proxy: Broken pipe"]);
+        assert_eq!(rule(&findings, "connection_reset").total.count, 1);
+    }
+
+    #[test]
+    fn occurrences_by_container() {
+        let mut findings = Findings::new("test");
+        let lines = ["ERROR one"; 7];
+        findings.scan("pool", &logs(&lines));
+        findings.scan("translator", &logs(&["ERROR two"]));
+
+        let errors = rule(&findings, "error_level");
+        assert_eq!(errors.total.count, 8);
+        assert_eq!(errors.total.samples.len(), MAX_SAMPLES);
+        assert_eq!(
+            errors.total.first_seen.as_deref(),
+            Some("2024-01-01T00:00:00.000Z")
+        );
+        assert_eq!(
+            errors.total.last_seen.as_deref(),
+            Some("2024-01-01T00:00:06.000Z")
+        );
+        assert_eq!(errors.containers["pool"].count, 7);
+        assert_eq!(errors.containers["translator"].count, 1);
+        assert_eq!(findings.scanned_containers, vec!["pool", "translator"]);
+    }
+
+    #[test]
+    fn summary_lists_the_matching_rules() {
+        let mut findings = Findings::new("run-1");
+        findings.scan(
+            "pool",
+            &logs(&["thread 'main' panicked at src/main.rs:1:1"]),
+        );
+        let summary = findings.summary();
+        assert!(summary.starts_with("# Log findings for `run-1`"));
+        assert!(summary.contains("| panic | Rust panics and aborted threads | 1 |"));
+        assert!(summary.contains(
+            "| reconnect | Roles reconnecting or falling back to another upstream | 0 | - | - |"
+        ));
+        assert!(summary.contains("## panic"));
+        assert!(!summary.contains("## reconnect"));
+        assert!(summary.contains("### pool (1 matches"));
+    }
+}
@@ -0,0 +1,161 @@
+use bollard::container::{InspectContainerOptions, ListContainersOptions};
+use bollard::models::ContainerSummary;
+use bollard::Docker;
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Serialize, Debug)]
+pub struct ContainerInfo {
+    pub name: String,
+    pub id: String,
+    pub image: String,
+    pub state: String,
+    pub status: String,
+    pub started_at: Option<String>,
+    pub uptime_seconds: Option<i64>,
+    pub restart_count: i64,
+    pub health: String,
+    pub logs_url: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RunMetadata {
+    pub log_label: String,
+    pub configuration: Option<&'static str>,
+    pub containers: usize,
+    pub running: usize,
+    pub healthy: usize,
+    pub unhealthy: usize,
+    pub restarts: i64,
+    pub started_at: Option<String>,
+    pub logs_url: &'static str,
+}
+
+/// Lists the containers carrying `log_label`; `all` also includes stopped containers.
+pub async fn list_containers(
+    log_label: &str,
+    all: bool,
+) -> Result<Vec<ContainerSummary>, Box<dyn std::error::Error>> {
+    info!("Getting containers with label: {}", log_label);
+
+    let docker = Docker::connect_with_local_defaults()?;
+    let mut filters = HashMap::new();
+    filters.insert("label".to_string(), vec![log_label.to_string()]);
+
+    let options = Some(ListContainersOptions::<String> {
+        all,
+        filters,
+        ..Default::default()
+    });
+
+    Ok(docker.list_containers(options).await?)
+}
+
+pub fn container_name(container: &ContainerSummary) -> Option<String> {
+    container.names.as_ref().and_then(|names| {
+        names
+            .first()
+            .map(|name| name.trim_start_matches('/').to_string())
+    })
+}
+
+/// Lists the containers matched by `log_label`, enriched with the restart count and health
+/// status that only `docker inspect` reports.
+pub async fn get_inventory(
+    log_label: &str,
+) -> Result<Vec<ContainerInfo>, Box<dyn std::error::Error>> {
+    let docker = Docker::connect_with_local_defaults()?;
+    let containers = list_containers(log_label, true).await?;
+
+    let mut inventory = Vec::with_capacity(containers.len());
+    for container in containers {
+        let Some(name) = container_name(&container) else {
+            continue;
+        };
+        let mut info = ContainerInfo {
+            logs_url: format!("/logs/{}", name),
+            name,
+            id: container.id.unwrap_or_default(),
+            image: container.image.unwrap_or_default(),
+            state: container.state.unwrap_or_default(),
+            status: container.status.unwrap_or_default(),
+            started_at: None,
+            uptime_seconds: None,
+            restart_count: 0,
+            health: "none".to_string(),
+        };
+
+        match docker
+            .inspect_container(&info.id, None::<InspectContainerOptions>)
+            .await
+        {
+            Ok(details) => {
+                info.restart_count = details.restart_count.unwrap_or_default();
+                if let Some(state) = details.state {
+                    if let Some(health) = state.health.and_then(|health| health.status) {
+                        info.health = health.to_string();
+                    }
+                    if state.running == Some(true) {
+                        info.uptime_seconds = state
+                            .started_at
+                            .as_deref()
+                            .and_then(|started_at| DateTime::parse_from_rfc3339(started_at).ok())
+                            .map(|started_at| (Utc::now() - started_at.to_utc()).num_seconds());
+                    }
+                    info.started_at = state.started_at;
+                }
+            }
+            Err(e) => {
+                error!("Failed to inspect container {}: {}", info.name, e);
+            }
+        }
+
+        inventory.push(info);
+    }
+    inventory.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(inventory)
+}
+
+pub fn run_metadata(log_label: &str, inventory: &[ContainerInfo]) -> RunMetadata {
+    let names: Vec<&str> = inventory.iter().map(|c| c.name.as_str()).collect();
+    RunMetadata {
+        log_label: log_label.to_string(),
+        configuration: detect_configuration(&names, log_label),
+        containers: inventory.len(),
+        running: inventory.iter().filter(|c| c.state == "running").count(),
+        healthy: inventory.iter().filter(|c| c.health == "healthy").count(),
+        unhealthy: inventory.iter().filter(|c| c.health == "unhealthy").count(),
+        restarts: inventory.iter().map(|c| c.restart_count).sum(),
+        started_at: inventory
+            .iter()
+            .filter(|c| c.state == "running")
+            .filter_map(|c| c.started_at.clone())
+            .min(),
+        logs_url: "/",
+    }
+}
+
+// Config A is the only one running a Job Declarator, config C the only one proxying the
+// pool <-> translator connection. Fall back to the compose label when neither is up.
+fn detect_configuration(names: &[&str], log_label: &str) -> Option<&'static str> {
+    if names
+        .iter()
+        .any(|name| matches!(*name, "sv2-jds" | "sv2-jdc" | "sv2-tp-jdc-proxy"))
+    {
+        Some("A")
+    } else if names
+        .iter()
+        .any(|name| matches!(*name, "sv2-pool-translator-proxy" | "sv2-tp-pool-proxy"))
+    {
+        Some("C")
+    } else if log_label.ends_with("config-a") {
+        Some("A")
+    } else if log_label.ends_with("config-c") {
+        Some("C")
+    } else {
+        None
+    }
+}
@@ -1,10 +1,11 @@
-use bollard::container::ListContainersOptions;
-use bollard::Docker;
+mod findings;
+mod inventory;
+
 use dotenv::dotenv;
+use findings::Findings;
 use log::{error, info};
 use reqwest::Client;
 use serde::Deserialize;
-use std::collections::HashMap;
 use std::env;
 use std::fmt::Write;
 use tar::Builder;
@@ -36,12 +37,112 @@ async fn main() {
     let log_label = format!("logging={}", log_label);
     info!("Starting server with LOG_LABEL: {}", log_label);
 
+    let logs_label = log_label.clone();
     let route = warp::path::end().and(warp::get()).and_then(move || {
-        let log_label = log_label.clone();
+        let log_label = logs_label.clone();
         async move { fetch_and_package_logs(&log_label).await }
     });
 
-    warp::serve(route).run(([0, 0, 0, 0], 7420)).await;
+    let containers_label = log_label.clone();
+    let containers_route = warp::path("containers")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(move || {
+            let log_label = containers_label.clone();
+            async move { get_containers_inventory(&log_label).await }
+        });
+
+    let run_label = log_label.clone();
+    let run_route = warp::path("run")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(move || {
+            let log_label = run_label.clone();
+            async move { get_run_metadata(&log_label).await }
+        });
+
+    let container_logs_route =
+        warp::path!("logs" / String)
+            .and(warp::get())
+            .and_then(move |container: String| {
+                let log_label = log_label.clone();
+                async move { fetch_container_logs(&log_label, &container).await }
+            });
+
+    let routes = route
+        .or(containers_route)
+        .or(run_route)
+        .or(container_logs_route);
+
+    warp::serve(routes).run(([0, 0, 0, 0], 7420)).await;
+}
+
+async fn get_containers_inventory(log_label: &str) -> Result<impl Reply, Rejection> {
+    info!("Listing containers for label: {}", log_label);
+
+    match inventory::get_inventory(log_label).await {
+        Ok(inventory) => Ok(warp::reply::json(&inventory).into_response()),
+        Err(e) => {
+            error!("Error listing containers: {}", e);
+            Ok(internal_server_error(e))
+        }
+    }
+}
+
+async fn get_run_metadata(log_label: &str) -> Result<impl Reply, Rejection> {
+    info!("Building run metadata for label: {}", log_label);
+
+    match inventory::get_inventory(log_label).await {
+        Ok(inventory) => {
+            let metadata = inventory::run_metadata(log_label, &inventory);
+            Ok(warp::reply::json(&metadata).into_response())
+        }
+        Err(e) => {
+            error!("Error building run metadata: {}", e);
+            Ok(internal_server_error(e))
+        }
+    }
+}
+
+async fn fetch_container_logs(log_label: &str, container: &str) -> Result<impl Reply, Rejection> {
+    info!("Fetching logs for container: {}", container);
+
+    // Only serve logs of the containers that belong to this benchmark run
+    let containers = match get_containers(log_label).await {
+        Ok(containers) => containers,
+        Err(e) => {
+            error!("Error listing containers: {}", e);
+            return Ok(internal_server_error(e));
+        }
+    };
+    if !containers.iter().any(|name| name == container) {
+        return Ok(Response::builder()
+            .status(warp::http::StatusCode::NOT_FOUND)
+            .body(Body::from(format!("Unknown container: {}", container)))
+            .unwrap());
+    }
+
+    match fetch_logs(&Client::new(), container).await {
+        Ok(logs) => Ok(Response::builder()
+            .header(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}.log\"", container),
+            )
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(Body::from(format_logs(logs)))
+            .unwrap()),
+        Err(e) => {
+            error!("Failed to fetch logs for container {}: {}", container, e);
+            Ok(internal_server_error(e))
+        }
+    }
+}
+
+fn internal_server_error(e: Box<dyn std::error::Error>) -> Response<Body> {
+    Response::builder()
+        .status(warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+        .body(Body::from(e.to_string()))
+        .unwrap()
 }
 
 async fn fetch_and_package_logs(log_label: &str) -> Result<impl Reply, Rejection> {
@@ -90,6 +191,8 @@ We’ve added a feature that allows you to download logs of all containers direc
 2. Look for the 'Download Logs' button and click on it.
 3. The logs will be downloaded as a .tar file.
 
+Before going through every container log, have a look at `findings-summary.md`: it lists panics, errors, rejected shares, connection resets and reconnects found in the logs, with counts, first/last occurrence and sample lines per container. The same data is available in machine readable form in `findings.json`.
+
 To check logs of the containers and if facing any issues and want help, kindly share the logs in the [benchmarking channel on Discord](https://discord.com/channels/950687892169195530/1107964065936060467).
 "#;
         let mut header = tar::Header::new_gnu();
@@ -98,10 +201,13 @@ To check logs of the containers and if facing any issues and want help, kindly s
         header.set_cksum();
         tar_builder.append_data(&mut header, "README.md", readme_content.as_bytes())?;
 
+        let mut findings = Findings::new(log_label);
         for container in containers {
             info!("Fetching logs for container: {}", container);
             match fetch_logs(&client, &container).await {
-                Ok(logs) => {
+                Ok(entries) => {
+                    findings.scan(&container, &entries);
+                    let logs = format_logs(entries);
                     let mut header = tar::Header::new_gnu();
                     header.set_size(logs.len() as u64);
                     header.set_mode(0o644);
@@ -120,6 +226,24 @@ To check logs of the containers and if facing any issues and want help, kindly s
             }
         }
 
+        let findings_json = findings.to_json()?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(findings_json.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar_builder.append_data(&mut header, "findings.json", findings_json.as_bytes())?;
+
+        let findings_summary = findings.summary();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(findings_summary.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar_builder.append_data(
+            &mut header,
+            "findings-summary.md",
+            findings_summary.as_bytes(),
+        )?;
+
         tar_builder.finish()?;
     }
     info!("Logs successfully packaged into tar file.");
@@ -129,27 +253,11 @@ To check logs of the containers and if facing any issues and want help, kindly s
 }
 
 async fn get_containers(log_label: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    info!("Getting containers with label: {}", log_label);
-
-    let docker = Docker::connect_with_local_defaults()?;
-    let mut filters = HashMap::new();
-    filters.insert("label".to_string(), vec![log_label.to_string()]);
-
-    let options = Some(ListContainersOptions::<String> {
-        filters,
-        ..Default::default()
-    });
-
-    let containers = docker.list_containers(options).await?;
+    // Loki keeps the logs of the stopped containers too, the inventory links to them
+    let containers = inventory::list_containers(log_label, true).await?;
     let container_names: Vec<String> = containers
-        .into_iter()
-        .filter_map(|container| {
-            container.names.and_then(|names| {
-                names
-                    .first()
-                    .map(|name| name.trim_start_matches('/').to_string())
-            })
-        })
+        .iter()
+        .filter_map(inventory::container_name)
         .collect();
 
     Ok(container_names)
@@ -158,7 +266,7 @@ async fn get_containers(log_label: &str) -> Result<Vec<String>, Box<dyn std::err
 async fn fetch_logs(
     client: &Client,
     container: &str,
-) -> Result<String, Box<dyn std::error::Error>> {
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
     let url = format!(
         "http://loki:3100/loki/api/v1/query_range?query={{container=\"{}\"}}&limit=100000000",
         container
@@ -175,13 +283,14 @@ async fn fetch_logs(
 
     logs.sort_by(|a, b| a.0.cmp(&b.0));
 
-    let formatted_logs: String = logs
-        .into_iter()
+    info!("Fetched logs for container: {}", container);
+    Ok(logs)
+}
+
+fn format_logs(logs: Vec<(String, String)>) -> String {
+    logs.into_iter()
         .fold(String::new(), |mut acc, (_, message)| {
             writeln!(&mut acc, "{}", message).unwrap();
             acc
-        });
-
-    info!("Fetched logs for container: {}", container);
-    Ok(formatted_logs)
+        })
 }
@@ -0,0 +1,4 @@
+pub mod recorder;
+pub mod schema;
+
+pub use schema::Measurement;
@@ -0,0 +1,179 @@
+use crate::schema::{self, labels_text, Measurement};
+use log::{error, info};
+use prometheus::core::Collector;
+use prometheus::proto::{MetricFamily, MetricType};
+use prometheus::Gauge;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// How often the metrics are read when MEASUREMENTS_INTERVAL_MS isn't set
+const DEFAULT_INTERVAL_MS: u64 = 100;
+
+// Values recorded by `set`, written by the recording thread
+static EVENTS: OnceLock<Sender<Measurement>> = OnceLock::new();
+// Metrics recorded by `set`, which the recording thread doesn't read from the registry
+static EVENT_METRICS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Sets a gauge measuring an event (a latency, a template value) and records the value with
+/// the time of the event. Every value is recorded, even when the gauge is set twice in a row
+/// or to the value it already had.
+pub fn set(gauge: &Gauge, value: f64) {
+    let Some(events) = EVENTS.get() else {
+        gauge.set(value);
+        return;
+    };
+    // Before setting the gauge, so that the recording thread never reads this value
+    for desc in gauge.desc() {
+        EVENT_METRICS.lock().unwrap().insert(desc.fq_name.clone());
+    }
+    gauge.set(value);
+    let timestamp_ms = now_ms();
+    for (metric, labels, value) in samples(&gauge.collect()) {
+        let _ = events.send(Measurement {
+            timestamp_ms,
+            proxy: String::new(),
+            metric,
+            labels,
+            value,
+        });
+    }
+}
+
+/// Records every value taken by the metrics of the process in the SQLite file at
+/// `MEASUREMENTS_FILE`, when it is set. The gauges set through `set` are recorded as the events
+/// happen. The rest of the default registry (counters, gauges of a state) is read every
+/// `MEASUREMENTS_INTERVAL_MS` milliseconds and the series whose value changed are appended.
+pub fn start(proxy: &str) {
+    let path = match env::var("MEASUREMENTS_FILE") {
+        Ok(path) if !path.is_empty() => path,
+        _ => return,
+    };
+    let interval = env::var("MEASUREMENTS_INTERVAL_MS")
+        .ok()
+        .and_then(|interval| interval.parse().ok())
+        .unwrap_or(DEFAULT_INTERVAL_MS);
+    let mut connection = match schema::open(&path) {
+        Ok(connection) => connection,
+        Err(e) => {
+            error!("Can't open the measurements file {}: {}", path, e);
+            return;
+        }
+    };
+    info!(
+        "Recording the measurements in {} every {}ms",
+        path, interval
+    );
+    let (sender, receiver) = mpsc::channel();
+    if EVENTS.set(sender).is_err() {
+        error!("The measurements are already recorded");
+        return;
+    }
+    let proxy = proxy.to_string();
+    thread::spawn(move || {
+        // Last value recorded of every series, by metric and labels
+        let mut last: HashMap<(String, String), f64> = HashMap::new();
+        loop {
+            let timestamp_ms = now_ms();
+            let families = prometheus::gather();
+            let mut changed: Vec<Measurement> = receiver.try_iter().collect();
+            let event_metrics = EVENT_METRICS.lock().unwrap().clone();
+            changed.extend(
+                samples(&families)
+                    .into_iter()
+                    .filter(|(metric, _, _)| !event_metrics.contains(metric))
+                    .filter(|(metric, labels, value)| {
+                        last.insert((metric.clone(), labels_text(labels)), *value) != Some(*value)
+                    })
+                    .map(|(metric, labels, value)| Measurement {
+                        timestamp_ms,
+                        proxy: String::new(),
+                        metric,
+                        labels,
+                        value,
+                    }),
+            );
+            for measurement in &mut changed {
+                measurement.proxy = proxy.clone();
+            }
+            if !changed.is_empty() {
+                if let Err(e) = schema::insert(&mut connection, &changed) {
+                    error!("Can't record {} measurements: {}", changed.len(), e);
+                }
+            }
+            thread::sleep(Duration::from_millis(interval));
+        }
+    });
+}
+
+// Every sample of the families, named like in the Prometheus exposition format
+fn samples(families: &[MetricFamily]) -> Vec<(String, BTreeMap<String, String>, f64)> {
+    let mut samples = Vec::new();
+    for family in families {
+        let name = family.get_name();
+        for metric in family.get_metric() {
+            let labels: BTreeMap<String, String> = metric
+                .get_label()
+                .iter()
+                .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+                .collect();
+            match family.get_field_type() {
+                MetricType::COUNTER => {
+                    samples.push((name.to_string(), labels, metric.get_counter().get_value()))
+                }
+                MetricType::GAUGE => {
+                    samples.push((name.to_string(), labels, metric.get_gauge().get_value()))
+                }
+                MetricType::UNTYPED => {
+                    samples.push((name.to_string(), labels, metric.get_untyped().get_value()))
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    for bucket in histogram.get_bucket() {
+                        let mut labels = labels.clone();
+                        labels.insert("le".to_string(), bucket.get_upper_bound().to_string());
+                        samples.push((
+                            format!("{}_bucket", name),
+                            labels,
+                            bucket.get_cumulative_count() as f64,
+                        ));
+                    }
+                    samples.push((
+                        format!("{}_sum", name),
+                        labels.clone(),
+                        histogram.get_sample_sum(),
+                    ));
+                    samples.push((
+                        format!("{}_count", name),
+                        labels,
+                        histogram.get_sample_count() as f64,
+                    ));
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    samples.push((
+                        format!("{}_sum", name),
+                        labels.clone(),
+                        summary.get_sample_sum(),
+                    ));
+                    samples.push((
+                        format!("{}_count", name),
+                        labels,
+                        summary.get_sample_count() as f64,
+                    ));
+                }
+            }
+        }
+    }
+    samples
+}
+
+fn now_ms() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as f64
+}
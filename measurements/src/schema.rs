@@ -0,0 +1,162 @@
+use rusqlite::{params, Connection};
+use std::collections::BTreeMap;
+
+/// Version of the schema, stored in the `metadata` table of every file.
+pub const SCHEMA_VERSION: &str = "1";
+
+// One row per value taken by a series. `labels` is a JSON object with sorted keys, so the
+// same series always has the same labels text.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS measurements (
+    timestamp_ms REAL NOT NULL,
+    proxy TEXT NOT NULL,
+    metric TEXT NOT NULL,
+    labels TEXT NOT NULL,
+    value REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS measurements_metric ON measurements (metric, timestamp_ms);
+CREATE TABLE IF NOT EXISTS metadata (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+";
+
+/// A value taken by a metric of a proxy.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Measurement {
+    // Milliseconds since the epoch
+    pub timestamp_ms: f64,
+    // PROXY_TYPE of the proxy
+    pub proxy: String,
+    pub metric: String,
+    pub labels: BTreeMap<String, String>,
+    pub value: f64,
+}
+
+/// Opens (or creates) a measurements file.
+pub fn open(path: &str) -> rusqlite::Result<Connection> {
+    let connection = Connection::open(path)?;
+    // The file may be read while the proxy records
+    connection.pragma_update(None, "journal_mode", "WAL")?;
+    connection.busy_timeout(std::time::Duration::from_secs(5))?;
+    connection.execute_batch(SCHEMA)?;
+    connection.execute(
+        "INSERT OR IGNORE INTO metadata (key, value) VALUES ('schema_version', ?1)",
+        params![SCHEMA_VERSION],
+    )?;
+    let version: String = connection.query_row(
+        "SELECT value FROM metadata WHERE key = 'schema_version'",
+        [],
+        |row| row.get(0),
+    )?;
+    if version != SCHEMA_VERSION {
+        return Err(rusqlite::Error::InvalidParameterName(format!(
+            "{} has schema version {}, expected {}",
+            path, version, SCHEMA_VERSION
+        )));
+    }
+    Ok(connection)
+}
+
+pub fn insert(connection: &mut Connection, measurements: &[Measurement]) -> rusqlite::Result<()> {
+    let transaction = connection.transaction()?;
+    {
+        let mut statement = transaction.prepare_cached(
+            "INSERT INTO measurements (timestamp_ms, proxy, metric, labels, value) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for measurement in measurements {
+            statement.execute(params![
+                measurement.timestamp_ms,
+                measurement.proxy,
+                measurement.metric,
+                labels_text(&measurement.labels),
+                measurement.value,
+            ])?;
+        }
+    }
+    transaction.commit()
+}
+
+/// The measurements of a file in time order, of one metric or of all of them.
+pub fn read(connection: &Connection, metric: Option<&str>) -> rusqlite::Result<Vec<Measurement>> {
+    let mut statement = connection.prepare(
+        "SELECT timestamp_ms, proxy, metric, labels, value FROM measurements
+         WHERE ?1 IS NULL OR metric = ?1 ORDER BY timestamp_ms, rowid",
+    )?;
+    let rows = statement.query_map(params![metric], |row| {
+        let labels: String = row.get(3)?;
+        Ok(Measurement {
+            timestamp_ms: row.get(0)?,
+            proxy: row.get(1)?,
+            metric: row.get(2)?,
+            labels: serde_json::from_str(&labels).unwrap_or_default(),
+            value: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+pub fn labels_text(labels: &BTreeMap<String, String>) -> String {
+    serde_json::to_string(labels).expect("Labels are always serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn measurement(
+        timestamp_ms: f64,
+        metric: &str,
+        labels: &[(&str, &str)],
+        value: f64,
+    ) -> Measurement {
+        Measurement {
+            timestamp_ms,
+            proxy: "pool-translator".to_string(),
+            metric: metric.to_string(),
+            labels: labels
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            value,
+        }
+    }
+
+    #[test]
+    fn reads_back_the_inserted_measurements() {
+        let dir = std::env::temp_dir().join(format!("measurements-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("round-trip.sqlite");
+        let path = path.to_str().unwrap();
+
+        // The same value twice in a row, and two values in the same millisecond, are all kept
+        let measurements = vec![
+            measurement(1_000.0, "sv2_prev_hash_to_job_latency", &[], 12.5),
+            measurement(1_000.0, "sv2_prev_hash_to_job_latency", &[], 12.5),
+            measurement(1_000.0, "sv2_prev_hash_to_job_latency", &[], 3.0),
+            measurement(
+                1_500.0,
+                "sv2_channel_nominal_hash_rate",
+                &[("user", "miner"), ("channel_id", "1")],
+                1e12,
+            ),
+        ];
+        let mut connection = open(path).unwrap();
+        insert(&mut connection, &measurements[..2]).unwrap();
+        insert(&mut connection, &measurements[2..]).unwrap();
+        drop(connection);
+
+        let connection = open(path).unwrap();
+        assert_eq!(read(&connection, None).unwrap(), measurements);
+        assert_eq!(
+            read(&connection, Some("sv2_prev_hash_to_job_latency")).unwrap(),
+            measurements[..3]
+        );
+        assert!(read(&connection, Some("sv1_new_job_latency"))
+            .unwrap()
+            .is_empty());
+        drop(connection);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,410 @@
+//! A scriptable SV1 pool: it sends `mining.notify` on a schedule, accepts or rejects the submits
+//! according to rules and can issue `mining.set_difficulty` and `client.reconnect`. The proxies'
+//! integration tests run it in process, the `mock-sv1-pool` binary serves it as a local target.
+
+use rand::RngCore;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+
+// Coinbase transaction around the extranonces: a single input whose script starts with a height
+// push, a single 50 BTC output to an empty key hash
+const COINBASE_INPUT: &str =
+    "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff";
+const COINBASE_HEIGHT: &str = "03010000";
+const COINBASE_SUFFIX: &str =
+    "ffffffff0100f2052a010000001976a914000000000000000000000000000000000000000088ac00000000";
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Something the pool does to a connection once the miner is authorized.
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// A job on top of `prev_hash`, in the byte order of `mining.notify`
+    Notify {
+        prev_hash: String,
+        clean_jobs: bool,
+    },
+    /// A job with `clean_jobs` on top of a new random block
+    NewBlock,
+    /// A job on top of the current block
+    NewJob,
+    SetDifficulty(f64),
+    Reconnect {
+        host: String,
+        port: u16,
+        wait_s: u64,
+    },
+    /// Closes the connection
+    Disconnect,
+}
+
+#[derive(Clone, Debug)]
+pub struct Step {
+    // Waited before the event, from the previous step (or the authorization)
+    pub delay: Duration,
+    pub event: Event,
+}
+
+impl Step {
+    pub fn after(delay: Duration, event: Event) -> Self {
+        Self { delay, event }
+    }
+}
+
+/// Submits the pool rejects, all the others are accepted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reject {
+    All,
+    /// Every `n`th submit of a connection
+    Every(u64),
+    /// Submits for a job sent before the last job with `clean_jobs`
+    Stale,
+    /// Submits for a job the connection never received
+    UnknownJob,
+}
+
+/// How the pool behaves, the schedule is played for every connection.
+#[derive(Clone, Debug)]
+pub struct Script {
+    pub extranonce1: Vec<u8>,
+    pub extranonce2_size: usize,
+    pub version: u32,
+    pub version_rolling_mask: u32,
+    pub nbits: u32,
+    // Sent after the authorization, and every time the miner suggests one
+    pub difficulty: f64,
+    pub reject: Vec<Reject>,
+    pub steps: Vec<Step>,
+    // Step the schedule loops back to after the last one, it ends there otherwise
+    pub repeat_from: Option<usize>,
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Self {
+            extranonce1: vec![0, 0, 0, 1],
+            extranonce2_size: 4,
+            version: 0x2000_0000,
+            version_rolling_mask: 0x1fff_e000,
+            nbits: 0x1d00_ffff,
+            difficulty: 1.0,
+            reject: Vec::new(),
+            steps: Vec::new(),
+            repeat_from: None,
+        }
+    }
+}
+
+/// What the pool saw, over all the connections.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Stats {
+    pub connections: u64,
+    pub submits: u64,
+    pub accepted: u64,
+    pub rejected: u64,
+}
+
+/// A running mock pool, it stops accepting connections when dropped.
+pub struct MockPool {
+    address: SocketAddr,
+    stats: Arc<Mutex<Stats>>,
+    accept: JoinHandle<()>,
+}
+
+impl MockPool {
+    /// Listens on `address`, `127.0.0.1:0` picks a free port.
+    pub async fn start(address: &str, script: Script) -> io::Result<Self> {
+        let listener = TcpListener::bind(address).await?;
+        let address = listener.local_addr()?;
+        let stats = Arc::new(Mutex::new(Stats::default()));
+        let script = Arc::new(script);
+        let accept = tokio::spawn({
+            let stats = stats.clone();
+            async move {
+                loop {
+                    let (stream, peer) = match listener.accept().await {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            log::error!("Failed to accept a connection: {}", e);
+                            sleep(ACCEPT_RETRY_DELAY).await;
+                            continue;
+                        }
+                    };
+                    log::info!("Miner connected from {}", peer);
+                    stats.lock().unwrap().connections += 1;
+                    let session = Session {
+                        script: script.clone(),
+                        stats: stats.clone(),
+                        state: Arc::new(Mutex::new(State::default())),
+                    };
+                    tokio::spawn(async move {
+                        match session.run(stream).await {
+                            Ok(()) => log::info!("Miner {} disconnected", peer),
+                            Err(e) => log::warn!("Connection with {} failed: {}", peer, e),
+                        }
+                    });
+                }
+            }
+        });
+        log::info!("Mock SV1 pool listening on {}", address);
+        Ok(Self {
+            address,
+            stats,
+            accept,
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn stats(&self) -> Stats {
+        *self.stats.lock().unwrap()
+    }
+}
+
+impl Drop for MockPool {
+    fn drop(&mut self) {
+        self.accept.abort();
+    }
+}
+
+#[derive(Default)]
+struct State {
+    prev_hash: String,
+    // Incremented by every job with clean_jobs
+    block: u64,
+    next_job: u64,
+    // Block of the jobs sent during the current and the previous block
+    jobs: HashMap<String, u64>,
+    submits: u64,
+}
+
+// Messages to the miner, None closes the connection
+type Outgoing = UnboundedSender<Option<Value>>;
+
+struct Session {
+    script: Arc<Script>,
+    stats: Arc<Mutex<Stats>>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Session {
+    async fn run(self, stream: TcpStream) -> io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let (sender, mut receiver) = unbounded_channel::<Option<Value>>();
+        let mut writing = tokio::spawn(async move {
+            while let Some(Some(message)) = receiver.recv().await {
+                writer
+                    .write_all(format!("{}\n", message).as_bytes())
+                    .await?;
+            }
+            writer.shutdown().await
+        });
+        let mut schedule = None;
+        let result = tokio::select! {
+            read = self.read(reader, &sender, &mut schedule) => read,
+            written = &mut writing => written.unwrap_or(Ok(())),
+        };
+        if let Some(schedule) = schedule {
+            schedule.abort();
+        }
+        writing.abort();
+        result
+    }
+
+    async fn read(
+        &self,
+        reader: OwnedReadHalf,
+        sender: &Outgoing,
+        schedule: &mut Option<JoinHandle<()>>,
+    ) -> io::Result<()> {
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            let Ok(request) = serde_json::from_str::<Value>(&line) else {
+                log::warn!("Invalid message from the miner: {}", line);
+                continue;
+            };
+            let id = request["id"].clone();
+            let params = &request["params"];
+            let result = match request["method"].as_str().unwrap_or_default() {
+                "mining.configure" => json!({
+                    "version-rolling": true,
+                    "version-rolling.mask": format!("{:08x}", self.script.version_rolling_mask),
+                }),
+                "mining.subscribe" => json!([
+                    [["mining.set_difficulty", "1"], ["mining.notify", "1"]],
+                    hex::encode(&self.script.extranonce1),
+                    self.script.extranonce2_size,
+                ]),
+                "mining.authorize" => {
+                    respond(sender, id, json!(true), Value::Null);
+                    if schedule.is_none() {
+                        send(sender, set_difficulty(self.script.difficulty));
+                        *schedule = Some(tokio::spawn(play(
+                            self.script.clone(),
+                            self.state.clone(),
+                            sender.clone(),
+                        )));
+                    }
+                    continue;
+                }
+                "mining.submit" => {
+                    let job_id = params[1].as_str().unwrap_or_default();
+                    match self.submit(job_id) {
+                        None => respond(sender, id, json!(true), Value::Null),
+                        Some(error) => respond(sender, id, Value::Null, error),
+                    }
+                    continue;
+                }
+                "mining.suggest_difficulty" => {
+                    if let Some(difficulty) = params[0].as_f64() {
+                        send(sender, set_difficulty(difficulty));
+                    }
+                    Value::Null
+                }
+                _ => Value::Null,
+            };
+            if !id.is_null() {
+                respond(sender, id, result, Value::Null);
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the error of a rejected submit
+    fn submit(&self, job_id: &str) -> Option<Value> {
+        let mut state = self.state.lock().unwrap();
+        state.submits += 1;
+        let job_block = state.jobs.get(job_id).copied();
+        let error = self.script.reject.iter().find_map(|rule| match rule {
+            Reject::All => Some(json!([23, "Low difficulty share", null])),
+            Reject::Every(n) if state.submits.checked_rem(*n) == Some(0) => {
+                Some(json!([23, "Low difficulty share", null]))
+            }
+            Reject::Stale if job_block.is_some_and(|block| block < state.block) => {
+                Some(json!([21, "Stale job", null]))
+            }
+            Reject::UnknownJob if job_block.is_none() => Some(json!([21, "Job not found", null])),
+            _ => None,
+        });
+        let mut stats = self.stats.lock().unwrap();
+        stats.submits += 1;
+        match error {
+            Some(_) => stats.rejected += 1,
+            None => stats.accepted += 1,
+        }
+        error
+    }
+}
+
+// Plays the steps of the script to a connection
+async fn play(script: Arc<Script>, state: Arc<Mutex<State>>, sender: Outgoing) {
+    let mut index = 0;
+    while let Some(step) = script.steps.get(index) {
+        sleep(step.delay).await;
+        let message = match &step.event {
+            Event::Notify {
+                prev_hash,
+                clean_jobs,
+            } => Some(notify(
+                &script,
+                &mut state.lock().unwrap(),
+                Some(prev_hash.clone()),
+                *clean_jobs,
+            )),
+            Event::NewBlock => Some(notify(
+                &script,
+                &mut state.lock().unwrap(),
+                Some(random_prev_hash()),
+                true,
+            )),
+            Event::NewJob => Some(notify(&script, &mut state.lock().unwrap(), None, false)),
+            Event::SetDifficulty(difficulty) => Some(set_difficulty(*difficulty)),
+            Event::Reconnect { host, port, wait_s } => Some(json!({
+                "id": null,
+                "method": "client.reconnect",
+                "params": [host, port, wait_s],
+            })),
+            Event::Disconnect => None,
+        };
+        if sender.send(message).is_err() {
+            return;
+        }
+        index = match script.repeat_from {
+            _ if index + 1 < script.steps.len() => index + 1,
+            Some(from) => from,
+            None => return,
+        };
+    }
+}
+
+fn notify(
+    script: &Script,
+    state: &mut State,
+    prev_hash: Option<String>,
+    clean_jobs: bool,
+) -> Value {
+    if let Some(prev_hash) = prev_hash {
+        state.prev_hash = prev_hash;
+    }
+    if state.prev_hash.is_empty() {
+        state.prev_hash = "00".repeat(32);
+    }
+    if clean_jobs {
+        state.block += 1;
+        let block = state.block;
+        state.jobs.retain(|_, job_block| *job_block + 1 >= block);
+    }
+    let job_id = format!("{:x}", state.next_job);
+    state.next_job += 1;
+    state.jobs.insert(job_id.clone(), state.block);
+    let script_size =
+        COINBASE_HEIGHT.len() / 2 + script.extranonce1.len() + script.extranonce2_size;
+    let ntime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs() as u32;
+    json!({
+        "id": null,
+        "method": "mining.notify",
+        "params": [
+            job_id,
+            state.prev_hash,
+            format!("{}{:02x}{}", COINBASE_INPUT, script_size, COINBASE_HEIGHT),
+            COINBASE_SUFFIX,
+            [],
+            format!("{:08x}", script.version),
+            format!("{:08x}", script.nbits),
+            format!("{:08x}", ntime),
+            clean_jobs,
+        ],
+    })
+}
+
+fn set_difficulty(difficulty: f64) -> Value {
+    json!({"id": null, "method": "mining.set_difficulty", "params": [difficulty]})
+}
+
+fn random_prev_hash() -> String {
+    let mut prev_hash = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut prev_hash);
+    hex::encode(prev_hash)
+}
+
+fn respond(sender: &Outgoing, id: Value, result: Value, error: Value) {
+    send(sender, json!({"id": id, "result": result, "error": error}));
+}
+
+fn send(sender: &Outgoing, message: Value) {
+    // The connection is closing when the writer is gone
+    let _ = sender.send(Some(message));
+}
@@ -0,0 +1,65 @@
+use mock_sv1_pool::{Event, MockPool, Reject, Script, Step};
+use std::env;
+use tokio::time::Duration;
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(
+        env_logger::Env::default()
+            .default_filter_or("info")
+            .default_write_style_or("always"),
+    )
+    .init();
+    let address = env::var("ADDRESS").unwrap_or_else(|_| "0.0.0.0:3333".to_string());
+    let notify_interval = Duration::from_millis(
+        env::var("NOTIFY_INTERVAL_MS")
+            .ok()
+            .and_then(|interval| interval.parse().ok())
+            .unwrap_or(30_000),
+    );
+    let jobs_per_block: usize = env::var("JOBS_PER_BLOCK")
+        .ok()
+        .and_then(|jobs| jobs.parse().ok())
+        .unwrap_or(10)
+        .max(1);
+    let reject = env::var("REJECT")
+        .unwrap_or_else(|_| "stale,unknown-job".to_string())
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(|rule| match rule.split_once(':') {
+            _ if rule == "all" => Reject::All,
+            _ if rule == "stale" => Reject::Stale,
+            _ if rule == "unknown-job" => Reject::UnknownJob,
+            Some(("every", n)) => Reject::Every(n.parse().expect("Invalid REJECT every:<n>")),
+            _ => panic!(
+                "Unknown REJECT rule {}, expected all, stale, unknown-job or every:<n>",
+                rule
+            ),
+        })
+        .collect();
+
+    // A new block every JOBS_PER_BLOCK jobs, forever
+    let mut steps = vec![Step::after(Duration::ZERO, Event::NewBlock)];
+    steps.extend((1..jobs_per_block).map(|_| Step::after(notify_interval, Event::NewJob)));
+    steps.push(Step::after(notify_interval, Event::NewBlock));
+    let script = Script {
+        extranonce2_size: env::var("EXTRANONCE2_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(4),
+        difficulty: env::var("DIFFICULTY")
+            .ok()
+            .and_then(|difficulty| difficulty.parse().ok())
+            .unwrap_or(1.0),
+        reject,
+        steps,
+        repeat_from: Some(1),
+        ..Script::default()
+    };
+
+    let _pool = MockPool::start(&address, script)
+        .await
+        .expect("Failed to start the mock pool");
+    std::future::pending::<()>().await;
+}
@@ -0,0 +1,386 @@
+use bench_common::hash::{meets, sha256d, target};
+use prometheus::{register_counter_vec, register_gauge, CounterVec, Gauge};
+use rand::{Rng, RngCore};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, Notify};
+use tokio::time::{sleep_until, Duration, Instant};
+
+// Templates kept to check the solutions and answer RequestTransactionData
+const KEPT_TEMPLATES: usize = 100;
+const INITIAL_SUBSIDY: u64 = 5_000_000_000;
+const HALVING_INTERVAL: u32 = 210_000;
+const UPDATES_CAPACITY: usize = 64;
+
+pub struct Settings {
+    // Height of the first block mined on
+    pub start_height: u32,
+    pub nbits: u32,
+    pub template_interval: Duration,
+    // None when only the solutions find blocks
+    pub block_interval: Option<Duration>,
+    // Average fees added to the coinbase value by every template of a block
+    pub fees_per_template: u64,
+}
+
+/// A block template, the coinbase value is the only thing changing between two templates of a
+/// block.
+#[derive(Clone)]
+pub struct Template {
+    pub id: u64,
+    pub future: bool,
+    pub height: u32,
+    pub value: u64,
+    // Internal byte order
+    pub prev_hash: [u8; 32],
+}
+
+/// The tip the templates are built on.
+#[derive(Clone)]
+pub struct PrevHash {
+    pub template_id: u64,
+    // Internal byte order
+    pub hash: [u8; 32],
+    pub time: u32,
+    pub nbits: u32,
+}
+
+#[derive(Clone)]
+pub enum Update {
+    Template(Template),
+    // A future template and the tip activating it
+    Block(Template, PrevHash),
+}
+
+/// How a block solution was handled.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Solution {
+    Block,
+    // The header doesn't meet the target of the network
+    Invalid,
+    // The header isn't built on the tip
+    Stale,
+    UnknownTemplate,
+}
+
+impl Solution {
+    fn label(self) -> &'static str {
+        match self {
+            Solution::Block => "block",
+            Solution::Invalid => "invalid",
+            Solution::Stale => "stale",
+            Solution::UnknownTemplate => "unknown-template",
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ChainMetrics {
+    templates: CounterVec,
+    blocks: CounterVec,
+    solutions: CounterVec,
+    height: Gauge,
+}
+
+struct State {
+    height: u32,
+    fees: u64,
+    next_template: u64,
+    tip: PrevHash,
+    // The most recent last
+    templates: VecDeque<Template>,
+}
+
+/// A made up chain: a new template every `template_interval` with a little more fees, a new
+/// block every `block_interval` or as soon as a solution meets the target.
+#[derive(Clone)]
+pub struct Chain {
+    settings: Arc<Settings>,
+    state: Arc<Mutex<State>>,
+    updates: broadcast::Sender<Update>,
+    // Restarts the schedule when a solution found a block
+    found: Arc<Notify>,
+    metrics: ChainMetrics,
+}
+
+impl Chain {
+    pub fn new(settings: Settings) -> Self {
+        let metrics = ChainMetrics {
+            templates: register_counter_vec!(
+                "mock_tp_templates",
+                "Total number of templates made by the mock template provider",
+                &["future"]
+            )
+            .unwrap(),
+            blocks: register_counter_vec!(
+                "mock_tp_blocks",
+                "Total number of blocks of the mock template provider by how they were found",
+                &["source"]
+            )
+            .unwrap(),
+            solutions: register_counter_vec!(
+                "mock_tp_solutions",
+                "Total number of block solutions submitted to the mock template provider by result",
+                &["source", "result"]
+            )
+            .unwrap(),
+            height: register_gauge!(
+                "mock_tp_height",
+                "Height of the block the mock template provider makes templates for"
+            )
+            .unwrap(),
+        };
+        let state = State {
+            height: settings.start_height,
+            fees: 0,
+            next_template: 1,
+            tip: PrevHash {
+                template_id: 0,
+                hash: random_hash(),
+                time: now(),
+                nbits: settings.nbits,
+            },
+            templates: VecDeque::new(),
+        };
+        let chain = Self {
+            settings: Arc::new(settings),
+            state: Arc::new(Mutex::new(state)),
+            updates: broadcast::channel(UPDATES_CAPACITY).0,
+            found: Arc::new(Notify::new()),
+            metrics,
+        };
+        let tip = chain.state.lock().unwrap().tip.hash;
+        chain.new_block(tip, None);
+        chain
+    }
+
+    /// Makes the templates and blocks of the schedule, forever.
+    pub async fn run(self) {
+        let template_interval = self.settings.template_interval;
+        let block_interval = self.settings.block_interval;
+        let mut next_template = Instant::now() + template_interval;
+        let mut next_block = block_interval.map(|interval| Instant::now() + interval);
+        loop {
+            tokio::select! {
+                _ = sleep_until(next_template) => {
+                    self.new_template();
+                    next_template = Instant::now() + template_interval;
+                    continue;
+                }
+                _ = sleep_until(next_block.unwrap_or(next_template)), if next_block.is_some() => {
+                    self.new_block(random_hash(), Some("schedule"));
+                }
+                _ = self.found.notified() => {}
+            }
+            next_template = Instant::now() + template_interval;
+            next_block = block_interval.map(|interval| Instant::now() + interval);
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Update> {
+        self.updates.subscribe()
+    }
+
+    /// The last template and the tip it's built on, for a new downstream.
+    pub fn current(&self) -> (Template, PrevHash) {
+        let state = self.state.lock().unwrap();
+        let template = state
+            .templates
+            .back()
+            .cloned()
+            .expect("The chain starts with a template");
+        (template, state.tip.clone())
+    }
+
+    pub fn template(&self, id: u64) -> Option<Template> {
+        let state = self.state.lock().unwrap();
+        state
+            .templates
+            .iter()
+            .rev()
+            .find(|template| template.id == id)
+            .cloned()
+    }
+
+    /// Height and hash of the last block.
+    pub fn tip(&self) -> (u32, [u8; 32]) {
+        let state = self.state.lock().unwrap();
+        (state.height - 1, state.tip.hash)
+    }
+
+    /// Handles a `SubmitSolution` of a downstream, `coinbase` being the full coinbase.
+    pub fn submit_solution(
+        &self,
+        template_id: u64,
+        version: u32,
+        time: u32,
+        nonce: u32,
+        coinbase: &[u8],
+    ) -> Solution {
+        let Some(template) = self.template(template_id) else {
+            return self.solved("submit-solution", Solution::UnknownTemplate, None);
+        };
+        // The templates have no transaction but the coinbase
+        let Some(merkle_root) = txid(coinbase) else {
+            return self.solved("submit-solution", Solution::Invalid, None);
+        };
+        let mut header = Vec::with_capacity(80);
+        header.extend_from_slice(&version.to_le_bytes());
+        header.extend_from_slice(&template.prev_hash);
+        header.extend_from_slice(&merkle_root);
+        header.extend_from_slice(&time.to_le_bytes());
+        header.extend_from_slice(&self.settings.nbits.to_le_bytes());
+        header.extend_from_slice(&nonce.to_le_bytes());
+        self.submit_header(&header, "submit-solution")
+    }
+
+    /// Handles a `submitblock` RPC call, only the header of the block is checked.
+    pub fn submit_block(&self, block: &[u8]) -> Solution {
+        match block.get(..80) {
+            Some(header) => self.submit_header(header, "submitblock"),
+            None => self.solved("submitblock", Solution::Invalid, None),
+        }
+    }
+
+    fn submit_header(&self, header: &[u8], source: &str) -> Solution {
+        let tip = self.state.lock().unwrap().tip.hash;
+        let hash = sha256d(header);
+        let solution = if header[4..36] != tip {
+            Solution::Stale
+        } else if meets(&hash, &target(self.settings.nbits)) {
+            Solution::Block
+        } else {
+            Solution::Invalid
+        };
+        self.solved(source, solution, Some(hash))
+    }
+
+    fn solved(&self, source: &str, solution: Solution, hash: Option<[u8; 32]>) -> Solution {
+        self.metrics
+            .solutions
+            .with_label_values(&[source, solution.label()])
+            .inc();
+        match (solution, hash) {
+            (Solution::Block, Some(hash)) => {
+                log::info!("Block {} found through {}", display(&hash), source);
+                self.new_block(hash, Some(source));
+                self.found.notify_one();
+            }
+            _ => log::warn!("Solution through {} rejected: {:?}", source, solution),
+        }
+        solution
+    }
+
+    fn new_template(&self) {
+        let mut state = self.state.lock().unwrap();
+        let added = rand::thread_rng().gen_range(0..=2 * self.settings.fees_per_template);
+        state.fees += added;
+        let template = self.push(&mut state, false);
+        log::debug!("Template {} worth {}", template.id, template.value);
+        let _ = self.updates.send(Update::Template(template));
+    }
+
+    // A block on top of `hash`, `source` is None for the first one
+    fn new_block(&self, hash: [u8; 32], source: Option<&str>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(source) = source {
+            state.height += 1;
+            self.metrics.blocks.with_label_values(&[source]).inc();
+        }
+        state.fees = 0;
+        state.tip.hash = hash;
+        state.tip.time = now();
+        let template = self.push(&mut state, true);
+        state.tip.template_id = template.id;
+        self.metrics.height.set(state.height as f64);
+        log::info!(
+            "New block {} at height {}",
+            display(&hash),
+            state.height - 1
+        );
+        let _ = self
+            .updates
+            .send(Update::Block(template, state.tip.clone()));
+    }
+
+    fn push(&self, state: &mut State, future: bool) -> Template {
+        let template = Template {
+            id: state.next_template,
+            future,
+            height: state.height,
+            value: subsidy(state.height) + state.fees,
+            prev_hash: state.tip.hash,
+        };
+        state.next_template += 1;
+        state.templates.push_back(template.clone());
+        while state.templates.len() > KEPT_TEMPLATES {
+            state.templates.pop_front();
+        }
+        self.metrics
+            .templates
+            .with_label_values(&[if future { "true" } else { "false" }])
+            .inc();
+        template
+    }
+}
+
+fn subsidy(height: u32) -> u64 {
+    INITIAL_SUBSIDY
+        .checked_shr(height / HALVING_INTERVAL)
+        .unwrap_or(0)
+}
+
+/// A hash in internal byte order, as usually displayed.
+pub fn display(hash: &[u8; 32]) -> String {
+    let mut reversed = *hash;
+    reversed.reverse();
+    hex::encode(reversed)
+}
+
+// Id of a serialized transaction, without its witness when it has one
+fn txid(tx: &[u8]) -> Option<[u8; 32]> {
+    if tx.len() < 10 || tx[4] != 0 || tx[5] == 0 {
+        return Some(sha256d(tx));
+    }
+    let mut position = 6;
+    // Inputs (outpoint before the script, sequence after), then outputs (value before it)
+    for (before, after) in [(36, 4), (8, 0)] {
+        let (count, read) = var_int(tx.get(position..)?)?;
+        position += read;
+        for _ in 0..count {
+            let (script, read) = var_int(tx.get(position + before..)?)?;
+            position += before + read + script as usize + after;
+        }
+    }
+    let mut stripped = tx[..4].to_vec();
+    stripped.extend_from_slice(tx.get(6..position)?);
+    stripped.extend_from_slice(&tx[tx.len() - 4..]);
+    Some(sha256d(&stripped))
+}
+
+// A compact size and the bytes it took
+fn var_int(bytes: &[u8]) -> Option<(u64, usize)> {
+    let size = match bytes.first()? {
+        0xfd => 2,
+        0xfe => 4,
+        0xff => 8,
+        value => return Some((*value as u64, 1)),
+    };
+    let mut value = [0u8; 8];
+    value[..size].copy_from_slice(bytes.get(1..1 + size)?);
+    Some((u64::from_le_bytes(value), 1 + size))
+}
+
+fn random_hash() -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut hash);
+    hash
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs() as u32
+}
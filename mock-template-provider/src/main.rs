@@ -0,0 +1,96 @@
+use chain::{Chain, Settings};
+use prometheus::{Encoder, TextEncoder};
+use session::SessionMetrics;
+use std::env;
+use tokio::net::TcpListener;
+use tokio::time::Duration;
+use warp::Filter;
+mod chain;
+mod rpc;
+mod session;
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(
+        env_logger::Env::default()
+            .default_filter_or("info")
+            .default_write_style_or("always"),
+    )
+    .init();
+    let address = env::var("ADDRESS").unwrap_or_else(|_| "0.0.0.0:8442".to_string());
+    let rpc_address = env::var("RPC_ADDRESS").unwrap_or_else(|_| "0.0.0.0:18332".to_string());
+    let prometheus_exporter_address =
+        env::var("PROM_ADDRESS").unwrap_or_else(|_| "0.0.0.0:9100".to_string());
+    // Same names as the chain field of getblockchaininfo
+    let network = match env::var("NETWORK").as_deref() {
+        Ok("") | Err(_) => "main".to_string(),
+        Ok("testnet3") => "test".to_string(),
+        Ok(network) => network.to_string(),
+    };
+    let settings = Settings {
+        start_height: env::var("START_HEIGHT")
+            .ok()
+            .and_then(|height| height.parse().ok())
+            .filter(|height| *height > 0)
+            .unwrap_or(900_000),
+        nbits: env::var("NBITS")
+            .ok()
+            .map(|nbits| u32::from_str_radix(&nbits, 16).expect("Invalid NBITS"))
+            .unwrap_or(0x1d00_ffff),
+        template_interval: Duration::from_millis(
+            env::var("TEMPLATE_INTERVAL_MS")
+                .ok()
+                .and_then(|interval| interval.parse().ok())
+                .filter(|interval| *interval > 0)
+                .unwrap_or(30_000),
+        ),
+        // 0 leaves the blocks to the solutions
+        block_interval: env::var("BLOCK_INTERVAL_MS")
+            .ok()
+            .and_then(|interval| interval.parse().ok())
+            .or(Some(600_000))
+            .filter(|interval| *interval > 0)
+            .map(Duration::from_millis),
+        fees_per_template: env::var("FEES_PER_TEMPLATE")
+            .ok()
+            .and_then(|fees| fees.parse().ok())
+            .unwrap_or(100_000),
+    };
+
+    let chain = Chain::new(settings);
+    let metrics = SessionMetrics::register();
+    tokio::spawn(async move {
+        let metrics_route = warp::path("metrics").map(move || {
+            let encoder = TextEncoder::new();
+            let metric_families = prometheus::gather();
+            let mut buffer = Vec::new();
+            encoder.encode(&metric_families, &mut buffer).unwrap();
+            warp::http::Response::builder()
+                .header("Content-Type", encoder.format_type())
+                .body(buffer)
+        });
+        let addr: std::net::SocketAddr = prometheus_exporter_address
+            .parse()
+            .expect("Invalid address");
+        warp::serve(metrics_route).run(addr).await;
+    });
+    tokio::spawn(rpc::serve(
+        rpc_address.parse().expect("Invalid RPC_ADDRESS"),
+        chain.clone(),
+        network,
+    ));
+    tokio::spawn(chain.clone().run());
+
+    let listener = TcpListener::bind(&address)
+        .await
+        .expect("Impossible to bind the template provider address");
+    log::info!("Mock template provider listening on {}", address);
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                tokio::spawn(session::run(stream, peer, chain.clone(), metrics.clone()));
+            }
+            Err(e) => log::error!("Failed to accept a connection: {}", e),
+        }
+    }
+}
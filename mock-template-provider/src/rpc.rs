@@ -0,0 +1,69 @@
+use crate::chain::{self, Chain, Solution};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use warp::Filter;
+
+// Error code of bitcoind for the methods it doesn't know
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMETER: i64 = -8;
+
+/// Answers the few bitcoind RPC calls the other containers make: the healthcheck, the mempool
+/// of the job declarator server and `submitblock`. The credentials are not checked.
+pub async fn serve(address: SocketAddr, chain: Chain, network: String) {
+    let route = warp::post()
+        .and(warp::body::bytes())
+        .map(move |body: warp::hyper::body::Bytes| {
+            let response = match serde_json::from_slice::<Value>(&body) {
+                Ok(request) => handle(&chain, &network, &request),
+                Err(e) => json!({
+                    "result": null,
+                    "error": {"code": -32700, "message": e.to_string()},
+                    "id": null,
+                }),
+            };
+            warp::reply::json(&response)
+        });
+    warp::serve(route).run(address).await;
+}
+
+fn handle(chain: &Chain, network: &str, request: &Value) -> Value {
+    let method = request["method"].as_str().unwrap_or_default();
+    let params = &request["params"];
+    let result = match method {
+        "getblockchaininfo" => {
+            let (height, hash) = chain.tip();
+            Ok(json!({
+                "chain": network,
+                "blocks": height,
+                "headers": height,
+                "bestblockhash": chain::display(&hash),
+                "initialblockdownload": false,
+                "verificationprogress": 1.0,
+            }))
+        }
+        "getblockcount" => Ok(json!(chain.tip().0)),
+        "getbestblockhash" => Ok(json!(chain::display(&chain.tip().1))),
+        // The templates have no transaction
+        "getrawmempool" => match params[0].as_bool() {
+            Some(true) => Ok(json!({})),
+            _ => Ok(json!([])),
+        },
+        "submitblock" => match params[0].as_str().and_then(|block| hex::decode(block).ok()) {
+            Some(block) => match chain.submit_block(&block) {
+                Solution::Block => Ok(Value::Null),
+                Solution::Invalid => Ok(json!("high-hash")),
+                Solution::Stale | Solution::UnknownTemplate => Ok(json!("prev-blk-not-found")),
+            },
+            None => Err((INVALID_PARAMETER, "Block decode failed".to_string())),
+        },
+        _ => Err((METHOD_NOT_FOUND, "Method not found".to_string())),
+    };
+    match result {
+        Ok(result) => json!({"result": result, "error": null, "id": request["id"]}),
+        Err((code, message)) => json!({
+            "result": null,
+            "error": {"code": code, "message": message},
+            "id": request["id"],
+        }),
+    }
+}
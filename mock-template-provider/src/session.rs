@@ -0,0 +1,317 @@
+use crate::chain::{Chain, PrevHash, Template, Update};
+use bench_common::hash::{self, sha256d};
+use bench_common::keys;
+use codec_sv2::{HandshakeRole, Responder};
+use demand_easy_sv2::roles_logic_sv2::common_messages_sv2::{
+    Protocol, SetupConnectionError, SetupConnectionSuccess,
+};
+use demand_easy_sv2::roles_logic_sv2::parsers::{CommonMessages, TemplateDistribution};
+use demand_easy_sv2::roles_logic_sv2::template_distribution_sv2::{
+    NewTemplate, RequestTransactionDataError, RequestTransactionDataSuccess, SetNewPrevHash,
+};
+use demand_easy_sv2::{Frame_, PoolMessages, StdFrame};
+use demand_sv2_connection::noise_connection_tokio::Connection;
+use key_utils::{Secp256k1PublicKey, Secp256k1SecretKey};
+use log::{info, warn};
+use prometheus::{register_counter_vec, CounterVec};
+use std::net::SocketAddr;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+const SV2_VERSION: u16 = 2;
+const BLOCK_VERSION: u32 = 0x2000_0000;
+const COINBASE_TX_VERSION: u32 = 2;
+const COINBASE_TX_INPUT_SEQUENCE: u32 = 0xffff_ffff;
+// OP_RETURN OP_PUSHBYTES_36 and the BIP 141 commitment header
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+#[derive(Clone)]
+pub struct SessionMetrics {
+    messages: CounterVec,
+}
+
+impl SessionMetrics {
+    pub fn register() -> Self {
+        Self {
+            messages: register_counter_vec!(
+                "mock_tp_messages",
+                "Total number of template distribution messages of the mock template provider",
+                &["direction", "message_type"]
+            )
+            .unwrap(),
+        }
+    }
+}
+
+/// Serves the templates of the chain to one downstream until it disconnects.
+pub async fn run(stream: TcpStream, peer: SocketAddr, chain: Chain, metrics: SessionMetrics) {
+    let public: Secp256k1PublicKey = keys::PUB_KEY.parse().expect("Invalid pub key");
+    let secret: Secp256k1SecretKey = keys::SEC_KEY.parse().expect("Invalid sec key");
+    let responder = Responder::from_authority_kp(
+        &public.into_bytes(),
+        &secret.into_bytes(),
+        keys::CERT_VALIDITY,
+    )
+    .expect("invalid key pair");
+    let (receiver, sender, _, _) = match Connection::new::<'static, PoolMessages<'static>>(
+        stream,
+        HandshakeRole::Responder(responder),
+    )
+    .await
+    {
+        Ok(connection) => connection,
+        Err(e) => {
+            warn!("Noise handshake with {} failed: {:?}", peer, e);
+            return;
+        }
+    };
+    info!("Downstream {} connected", peer);
+    let mut session = Session {
+        chain,
+        metrics,
+        sender,
+    };
+    session.serve(receiver).await;
+    info!("Downstream {} disconnected", peer);
+}
+
+struct Session {
+    chain: Chain,
+    metrics: SessionMetrics,
+    sender: Sender<Frame_>,
+}
+
+impl Session {
+    async fn serve(&mut self, mut receiver: Receiver<Frame_>) {
+        // Subscribed once the downstream said how big its coinbase outputs are
+        let mut updates = None;
+        loop {
+            tokio::select! {
+                frame = receiver.recv() => {
+                    let Some(frame) = frame else { return };
+                    match self.receive(frame).await {
+                        Ok(true) if updates.is_none() => {
+                            updates = Some(self.chain.subscribe());
+                            if self.snapshot().await.is_err() {
+                                return;
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(()) => return,
+                    }
+                }
+                update = recv(&mut updates) => {
+                    let sent = match update {
+                        Ok(Update::Template(template)) => self.new_template(&template).await,
+                        Ok(Update::Block(template, prev_hash)) => {
+                            match self.new_template(&template).await {
+                                Ok(()) => self.set_new_prev_hash(&prev_hash).await,
+                                Err(()) => Err(()),
+                            }
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("Downstream too slow, {} updates skipped", skipped);
+                            self.snapshot().await
+                        }
+                        Err(RecvError::Closed) => return,
+                    };
+                    if sent.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    // Handles a frame of the downstream, returning whether it asked for templates
+    async fn receive(&mut self, frame: Frame_) -> Result<bool, ()> {
+        let Frame_::Sv2(mut frame) = frame else {
+            return Ok(false);
+        };
+        let Some(header) = frame.get_header() else {
+            return Ok(false);
+        };
+        let message_type = header.msg_type();
+        let mut payload = frame.payload().to_vec();
+        let message: PoolMessages<'_> = match (message_type, payload.as_mut_slice()).try_into() {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Invalid message {}: {:?}", message_type, e);
+                return Ok(false);
+            }
+        };
+        match message {
+            PoolMessages::Common(CommonMessages::SetupConnection(m)) => {
+                self.count("upstream", "SetupConnection");
+                let reply = if m.protocol == Protocol::TemplateDistributionProtocol
+                    && (m.min_version..=m.max_version).contains(&SV2_VERSION)
+                {
+                    CommonMessages::SetupConnectionSuccess(SetupConnectionSuccess {
+                        used_version: SV2_VERSION,
+                        flags: 0,
+                    })
+                } else {
+                    CommonMessages::SetupConnectionError(SetupConnectionError {
+                        flags: 0,
+                        error_code: "unsupported-protocol".to_string().try_into().unwrap(),
+                    })
+                };
+                self.send(PoolMessages::Common(reply)).await?;
+            }
+            PoolMessages::TemplateDistribution(message) => {
+                return self.template_distribution(message).await
+            }
+            _ => warn!("Unexpected message {}", message_type),
+        }
+        Ok(false)
+    }
+
+    async fn template_distribution(
+        &mut self,
+        message: TemplateDistribution<'_>,
+    ) -> Result<bool, ()> {
+        match message {
+            TemplateDistribution::CoinbaseOutputDataSize(m) => {
+                self.count("upstream", "CoinbaseOutputDataSize");
+                // The templates leave the whole block weight to the coinbase outputs
+                log::debug!(
+                    "Coinbase outputs of up to {} bytes",
+                    m.coinbase_output_max_additional_size
+                );
+                return Ok(true);
+            }
+            TemplateDistribution::RequestTransactionData(m) => {
+                self.count("upstream", "RequestTransactionData");
+                let reply = match self.chain.template(m.template_id) {
+                    // The templates have no transaction but the coinbase
+                    Some(_) => TemplateDistribution::RequestTransactionDataSuccess(
+                        RequestTransactionDataSuccess {
+                            template_id: m.template_id,
+                            excess_data: Vec::new().try_into().unwrap(),
+                            transaction_list: Vec::new().into(),
+                        },
+                    ),
+                    None => TemplateDistribution::RequestTransactionDataError(
+                        RequestTransactionDataError {
+                            template_id: m.template_id,
+                            error_code: "template-id-not-found".to_string().try_into().unwrap(),
+                        },
+                    ),
+                };
+                self.send(PoolMessages::TemplateDistribution(reply)).await?;
+            }
+            TemplateDistribution::SubmitSolution(m) => {
+                self.count("upstream", "SubmitSolution");
+                self.chain.submit_solution(
+                    m.template_id,
+                    m.version,
+                    m.header_timestamp,
+                    m.header_nonce,
+                    &m.coinbase_tx.to_vec(),
+                );
+            }
+            _ => warn!("Unexpected template distribution message"),
+        }
+        Ok(false)
+    }
+
+    // The last template and its tip, for a new or lagging downstream
+    async fn snapshot(&mut self) -> Result<(), ()> {
+        let (mut template, prev_hash) = self.chain.current();
+        // The tip only activates future templates
+        template.future = true;
+        let prev_hash = PrevHash {
+            template_id: template.id,
+            ..prev_hash
+        };
+        self.new_template(&template).await?;
+        self.set_new_prev_hash(&prev_hash).await
+    }
+
+    async fn new_template(&mut self, template: &Template) -> Result<(), ()> {
+        self.count("downstream", "NewTemplate");
+        let message = NewTemplate {
+            template_id: template.id,
+            future_template: template.future,
+            version: BLOCK_VERSION,
+            coinbase_tx_version: COINBASE_TX_VERSION,
+            coinbase_prefix: height_push(template.height).try_into().unwrap(),
+            coinbase_tx_input_sequence: COINBASE_TX_INPUT_SEQUENCE,
+            coinbase_tx_value_remaining: template.value,
+            coinbase_tx_outputs_count: 1,
+            coinbase_tx_outputs: witness_commitment().try_into().unwrap(),
+            coinbase_tx_locktime: 0,
+            merkle_path: Vec::new().into(),
+        };
+        self.send(PoolMessages::TemplateDistribution(
+            TemplateDistribution::NewTemplate(message),
+        ))
+        .await
+    }
+
+    async fn set_new_prev_hash(&mut self, prev_hash: &PrevHash) -> Result<(), ()> {
+        self.count("downstream", "SetNewPrevHash");
+        let message = SetNewPrevHash {
+            template_id: prev_hash.template_id,
+            prev_hash: prev_hash.hash.into(),
+            header_timestamp: prev_hash.time,
+            n_bits: prev_hash.nbits,
+            target: hash::target(prev_hash.nbits).into(),
+        };
+        self.send(PoolMessages::TemplateDistribution(
+            TemplateDistribution::SetNewPrevHash(message),
+        ))
+        .await
+    }
+
+    async fn send(&self, message: PoolMessages<'static>) -> Result<(), ()> {
+        let frame: StdFrame = message
+            .try_into()
+            .expect("A message can always be converted in a frame");
+        self.sender.send(frame.into()).await.map_err(|_| ())
+    }
+
+    fn count(&self, direction: &str, message_type: &str) {
+        self.metrics
+            .messages
+            .with_label_values(&[direction, message_type])
+            .inc();
+    }
+}
+
+// Waits for an update, forever while not subscribed
+async fn recv(
+    updates: &mut Option<tokio::sync::broadcast::Receiver<Update>>,
+) -> Result<Update, RecvError> {
+    match updates {
+        Some(updates) => updates.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+// BIP 34 push of the height, the start of the script of the coinbase input
+fn height_push(height: u32) -> Vec<u8> {
+    let mut bytes = height.to_le_bytes().to_vec();
+    while bytes.len() > 1 && bytes[bytes.len() - 1] == 0 {
+        bytes.pop();
+    }
+    // The height is a signed number
+    if bytes[bytes.len() - 1] & 0x80 != 0 {
+        bytes.push(0);
+    }
+    let mut push = vec![bytes.len() as u8];
+    push.extend(bytes);
+    push
+}
+
+// The output committing to the witnesses of a block without any other transaction
+fn witness_commitment() -> Vec<u8> {
+    // Witness root of the coinbase alone and the zero reserved value
+    let commitment = sha256d(&[0u8; 64]);
+    let mut output = 0u64.to_le_bytes().to_vec();
+    output.push((WITNESS_COMMITMENT_HEADER.len() + commitment.len()) as u8);
+    output.extend_from_slice(&WITNESS_COMMITMENT_HEADER);
+    output.extend_from_slice(&commitment);
+    output
+}
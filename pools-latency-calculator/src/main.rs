@@ -1,6 +1,7 @@
 use prometheus::{register_gauge, Encoder, Gauge, TextEncoder};
 use serde_json::json;
 use std::collections::HashMap;
+use std::env;
 use std::net::SocketAddr;
 use std::time::{Duration, Instant};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
@@ -71,7 +72,7 @@ async fn get_subscription_latency(address: &str) -> Result<Duration, std::io::Er
     }
 }
 
-async fn average_latency(pool_map: HashMap<&str, Vec<&str>>, repetitions: usize, gauge: Gauge) {
+async fn average_latency(pool_map: HashMap<String, Vec<String>>, repetitions: usize, gauge: Gauge) {
     let mut total_duration = Duration::new(0, 0);
     let mut total_pools = 0;
 
@@ -159,6 +160,39 @@ async fn main() {
         ("Viabtc", vec!["stratum+tcp://btc.viabtc.io:3333"]),
     ]);
 
+    // POOLS replaces the public pools, e.g. with a local mock pool:
+    // `Mock=stratum+tcp://10.5.0.43:3333;Other=stratum+tcp://host:port,stratum+tcp://host:port`
+    let pool_map: HashMap<String, Vec<String>> = match env::var("POOLS")
+        .ok()
+        .filter(|pools| !pools.trim().is_empty())
+    {
+        Some(pools) => pools
+            .split(';')
+            .filter(|pool| !pool.trim().is_empty())
+            .map(|pool| {
+                let (name, addresses) = pool
+                    .split_once('=')
+                    .expect("Invalid POOLS, expected <name>=<address>[,<address>];...");
+                (
+                    name.trim().to_string(),
+                    addresses
+                        .split(',')
+                        .map(|address| address.trim().to_string())
+                        .collect(),
+                )
+            })
+            .collect(),
+        None => pool_map
+            .into_iter()
+            .map(|(name, addresses)| {
+                (
+                    name.to_string(),
+                    addresses.into_iter().map(str::to_string).collect(),
+                )
+            })
+            .collect(),
+    };
+
     let repetitions = 10;
 
     let gauge = register_gauge!(
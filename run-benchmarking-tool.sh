@@ -55,14 +55,15 @@ if [[ "$CONFIG" != "A" && "$CONFIG" != "C" ]]; then
     exit 1
 fi
 
-# Prompt user to select network (mainnet, testnet3, or testnet4) with default value
+# Prompt user to select network (mainnet, testnet3, testnet4, or regtest) with default value
 echo ""
-read -p "Do you want to use mainnet, testnet3, or testnet4? (Enter 'mainnet', 'testnet3', or 'testnet4', default is 'testnet4'): " NETWORK
+echo -e "${bold}regtest${reset} mines a local chain at minimal difficulty, every share of the miners finds a block to measure the block propagation"
+read -p "Do you want to use mainnet, testnet3, testnet4, or regtest? (Enter 'mainnet', 'testnet3', 'testnet4', or 'regtest', default is 'testnet4'): " NETWORK
 NETWORK=${NETWORK:-$DEFAULT_NETWORK}
 
 # Validate the NETWORK input
-if [[ "$NETWORK" != "mainnet" && "$NETWORK" != "testnet3" && "$NETWORK" != "testnet4" ]]; then
-    echo "Invalid network choice. Please enter 'mainnet', 'testnet3', or 'testnet4'."
+if [[ "$NETWORK" != "mainnet" && "$NETWORK" != "testnet3" && "$NETWORK" != "testnet4" && "$NETWORK" != "regtest" ]]; then
+    echo "Invalid network choice. Please enter 'mainnet', 'testnet3', 'testnet4', or 'regtest'."
     exit 1
 fi
 
@@ -219,6 +220,8 @@ SV1_POOL_ENV="custom-configs/sv1-pool/.env"
 if [[ -f "$SV1_POOL_ENV" ]]; then
     if [[ "$NETWORK" == "mainnet" ]]; then
         NEW_NETWORK_VALUE="mainnet"
+    elif [[ "$NETWORK" == "regtest" ]]; then
+        NEW_NETWORK_VALUE="regtest"
     else
         NEW_NETWORK_VALUE="testnet"
     fi
@@ -0,0 +1,48 @@
+use log::info;
+use scenario_runner::render;
+use scenario_runner::report::ScenarioReport;
+use scenario_runner::results::Results;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Renders the SV1 vs SV2 comparison report of one or more results files of the scenario
+// runner, e.g. one of configuration A and one of configuration C.
+//
+// RESULTS_FILES  comma separated results files
+// REPORT_FILE    path of the report without extension, written as .html and .md
+//                (default: results/report)
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(
+        env_logger::Env::default()
+            .default_filter_or("info")
+            .default_write_style_or("always"),
+    )
+    .init();
+
+    let results_files =
+        env::var("RESULTS_FILES").expect("RESULTS_FILES environment variable not set");
+    let report_file = env::var("REPORT_FILE").unwrap_or_else(|_| "results/report".to_string());
+
+    let results = results_files
+        .split(',')
+        .map(str::trim)
+        .filter(|file| !file.is_empty())
+        .map(Results::load)
+        .collect::<Result<Vec<_>, _>>()?;
+    let reports: Vec<ScenarioReport> = results.iter().map(ScenarioReport::new).collect();
+    let blocks = render::document(&reports);
+
+    if let Some(dir) = Path::new(&report_file).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    for (extension, content) in [
+        ("html", render::html(&blocks)),
+        ("md", render::markdown(&blocks)),
+    ] {
+        let path = format!("{}.{}", report_file, extension);
+        fs::write(&path, content)?;
+        info!("Report written to {}", path);
+    }
+    Ok(())
+}
@@ -0,0 +1,9 @@
+pub mod prometheus;
+pub mod recordings;
+pub mod render;
+pub mod report;
+pub mod results;
+pub mod scenario;
+pub mod stack;
+pub mod stats;
+pub mod svg;
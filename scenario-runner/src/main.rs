@@ -0,0 +1,168 @@
+use chrono::Utc;
+use log::{error, info};
+use reqwest::Client;
+use scenario_runner::prometheus;
+use scenario_runner::recordings::{self, RECORDINGS_DIR};
+use scenario_runner::report;
+use scenario_runner::results::{QueryResult, Results, Run};
+use scenario_runner::scenario::Scenario;
+use scenario_runner::stack::Stack;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::time::sleep;
+
+// Runs a benchmark scenario end to end: brings the compose stack of the configuration up,
+// waits for it, applies the network profile, lets it warm up, measures, collects the series
+// from Prometheus and brings it down, once per repetition.
+//
+// SCENARIO_FILE   JSON scenario (see scenarios/)
+// RESULTS_FILE    path of the results (default: results/<name>-<timestamp>.json)
+// PROMETHEUS_URL  Prometheus of the stack (default: http://localhost:9090)
+//
+// Run it from the root of the repository, where the compose files are.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(
+        env_logger::Env::default()
+            .default_filter_or("info")
+            .default_write_style_or("always"),
+    )
+    .init();
+
+    let scenario_file =
+        env::var("SCENARIO_FILE").expect("SCENARIO_FILE environment variable not set");
+    let scenario = Scenario::load(&scenario_file)?;
+    let started_at = Utc::now();
+    let results_file = env::var("RESULTS_FILE").unwrap_or_else(|_| {
+        format!(
+            "results/{}-{}.json",
+            scenario.name,
+            started_at.format("%Y%m%dT%H%M%SZ")
+        )
+    });
+    let prometheus_url =
+        env::var("PROMETHEUS_URL").unwrap_or_else(|_| "http://localhost:9090".to_string());
+
+    let stack = Stack::new(scenario.configuration)?;
+    let client = Client::new();
+    let mut results = Results {
+        scenario: scenario.clone(),
+        started_at: started_at.to_rfc3339(),
+        runs: Vec::new(),
+    };
+
+    for repetition in 1..=scenario.repetitions {
+        info!(
+            "Scenario {}: run {}/{}",
+            scenario.name, repetition, scenario.repetitions
+        );
+        let outcome = tokio::select! {
+            outcome = run(&stack, &scenario, &client, &prometheus_url, repetition) => outcome,
+            _ = tokio::signal::ctrl_c() => Err("Interrupted".into()),
+        };
+        if let Err(e) = stack.down(&scenario).await {
+            error!("{}", e);
+        }
+        match outcome {
+            Ok(run) => {
+                results.runs.push(run);
+                results.write(&results_file)?;
+                info!("Results written to {}", results_file);
+            }
+            Err(e) => {
+                error!("Run {} failed: {}", repetition, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn run(
+    stack: &Stack,
+    scenario: &Scenario,
+    client: &Client,
+    prometheus_url: &str,
+    repetition: u32,
+) -> Result<Run, Box<dyn std::error::Error>> {
+    stack.up(scenario).await?;
+    stack
+        .wait_ready(Duration::from_secs(scenario.ready_timeout_s))
+        .await?;
+    while !prometheus::ready(client, prometheus_url).await {
+        info!("Waiting for Prometheus at {}", prometheus_url);
+        sleep(Duration::from_secs(5)).await;
+    }
+    stack
+        .apply_network_profile(&scenario.network_profile)
+        .await?;
+    let restarts_before = stack.restarts().await?;
+    let started_at = now();
+
+    info!("Warming up for {}s", scenario.warm_up_s);
+    sleep(Duration::from_secs(scenario.warm_up_s)).await;
+    let measured_from = now();
+    info!("Measuring for {}s", scenario.duration_s);
+    sleep(Duration::from_secs(scenario.duration_s)).await;
+    let measured_until = now();
+    // Let Prometheus scrape the end of the window
+    sleep(Duration::from_secs(scenario.step_s)).await;
+
+    let mut queries = Vec::with_capacity(scenario.queries.len());
+    for query in &scenario.queries {
+        let result = prometheus::query_range(
+            client,
+            prometheus_url,
+            query,
+            measured_from,
+            measured_until,
+            scenario.step_s,
+        )
+        .await;
+        queries.push(match result {
+            Ok(series) => QueryResult {
+                query: query.clone(),
+                series,
+                error: None,
+            },
+            Err(e) => {
+                error!("Query {} failed: {}", query, e);
+                QueryResult {
+                    query: query.clone(),
+                    series: Vec::new(),
+                    error: Some(e.to_string()),
+                }
+            }
+        });
+    }
+
+    let recorded_events = if scenario.record_measurements {
+        recordings::events(
+            Path::new(RECORDINGS_DIR),
+            &report::event_metrics(scenario.configuration),
+            measured_from,
+            measured_until,
+        )
+    } else {
+        BTreeMap::new()
+    };
+
+    Ok(Run {
+        repetition,
+        started_at,
+        measured_from,
+        measured_until,
+        restarts: stack.restarts().await? - restarts_before,
+        queries,
+        recorded_events,
+    })
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs_f64()
+}
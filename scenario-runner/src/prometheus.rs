@@ -0,0 +1,85 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Prometheus refuses ranges of more than 11000 points per series
+const MAX_POINTS: f64 = 11000.0;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Series {
+    pub labels: BTreeMap<String, String>,
+    // (seconds since the epoch, value)
+    pub values: Vec<(f64, f64)>,
+}
+
+#[derive(Deserialize)]
+struct QueryResponse {
+    status: String,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    data: Option<QueryData>,
+}
+
+#[derive(Deserialize)]
+struct QueryData {
+    result: Vec<RangeResult>,
+}
+
+#[derive(Deserialize)]
+struct RangeResult {
+    metric: BTreeMap<String, String>,
+    values: Vec<(f64, String)>,
+}
+
+pub async fn ready(client: &Client, url: &str) -> bool {
+    match client.get(format!("{}/-/ready", url)).send().await {
+        Ok(response) => response.status().is_success(),
+        Err(_) => false,
+    }
+}
+
+/// Runs `query` over [`start`, `end`] (seconds since the epoch).
+pub async fn query_range(
+    client: &Client,
+    url: &str,
+    query: &str,
+    start: f64,
+    end: f64,
+    step_s: u64,
+) -> Result<Vec<Series>, Box<dyn std::error::Error>> {
+    let step = (step_s as f64).max(((end - start) / MAX_POINTS).ceil());
+    let response: QueryResponse = client
+        .get(format!("{}/api/v1/query_range", url))
+        .query(&[
+            ("query", query.to_string()),
+            ("start", start.to_string()),
+            ("end", end.to_string()),
+            ("step", step.to_string()),
+        ])
+        .send()
+        .await?
+        .json()
+        .await?;
+    if response.status != "success" {
+        return Err(response
+            .error
+            .unwrap_or_else(|| "Prometheus query failed".to_string())
+            .into());
+    }
+    Ok(response
+        .data
+        .map(|data| data.result)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|result| Series {
+            labels: result.metric,
+            values: result
+                .values
+                .into_iter()
+                .filter_map(|(timestamp, value)| Some((timestamp, value.parse().ok()?)))
+                .filter(|(_, value): &(f64, f64)| value.is_finite())
+                .collect(),
+        })
+        .collect())
+}
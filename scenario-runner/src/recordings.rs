@@ -0,0 +1,118 @@
+use log::{error, warn};
+use measurements::schema;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Where the compose files mount the measurements files of the proxies.
+pub const RECORDINGS_DIR: &str = "recorded-measurements";
+
+/// Values taken by the `metrics` between `from` and `until` (seconds since the epoch) in the
+/// measurements files of `dir`, by metric. Zero is the value of a gauge before its first event,
+/// as for the Prometheus series.
+pub fn events(dir: &Path, metrics: &[&str], from: f64, until: f64) -> BTreeMap<String, Vec<f64>> {
+    let mut events: BTreeMap<String, Vec<(f64, f64)>> = BTreeMap::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("No recorded measurements in {}: {}", dir.display(), e);
+            return BTreeMap::new();
+        }
+    };
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        if path.extension().and_then(|extension| extension.to_str()) != Some("sqlite") {
+            continue;
+        }
+        let connection = match schema::open(&path.to_string_lossy()) {
+            Ok(connection) => connection,
+            Err(e) => {
+                error!("Can't open {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        for metric in metrics {
+            match schema::read(&connection, Some(metric)) {
+                Ok(measurements) => events.entry(metric.to_string()).or_default().extend(
+                    measurements
+                        .iter()
+                        .filter(|m| {
+                            m.timestamp_ms >= from * 1000.0 && m.timestamp_ms <= until * 1000.0
+                        })
+                        .filter(|m| m.value != 0.0)
+                        .map(|m| (m.timestamp_ms, m.value)),
+                ),
+                Err(e) => error!("Can't read {} from {}: {}", metric, path.display(), e),
+            }
+        }
+    }
+    events
+        .into_iter()
+        .filter(|(_, values)| !values.is_empty())
+        .map(|(metric, mut values)| {
+            // Several proxies report some of the metrics
+            values.sort_by(|a, b| a.0.total_cmp(&b.0));
+            (metric, values.into_iter().map(|(_, value)| value).collect())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use measurements::schema::Measurement;
+
+    fn measurement(proxy: &str, timestamp_ms: f64, metric: &str, value: f64) -> Measurement {
+        Measurement {
+            timestamp_ms,
+            proxy: proxy.to_string(),
+            metric: metric.to_string(),
+            labels: BTreeMap::new(),
+            value,
+        }
+    }
+
+    #[test]
+    fn keeps_the_events_of_the_window_in_time_order() {
+        let dir = std::env::temp_dir().join(format!("recordings-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, measurements) in [
+            (
+                "sv2-pool-translator-proxy.sqlite",
+                vec![
+                    measurement("pool-translator", 500.0, "new_job_pool_new_template", 7.0),
+                    measurement("pool-translator", 1_000.0, "new_job_pool_new_template", 0.0),
+                    measurement("pool-translator", 1_200.0, "new_job_pool_new_template", 4.0),
+                    measurement("pool-translator", 1_200.0, "new_job_pool_new_template", 4.0),
+                    measurement("pool-translator", 2_500.0, "new_job_pool_new_template", 9.0),
+                ],
+            ),
+            (
+                "sv2-jdc-translator-proxy.sqlite",
+                vec![
+                    measurement("jdc-translator", 1_100.0, "new_job_pool_new_template", 5.0),
+                    measurement("jdc-translator", 1_300.0, "sv2_valid_shares", 1.0),
+                ],
+            ),
+        ] {
+            let path = dir.join(file);
+            let mut connection = schema::open(path.to_str().unwrap()).unwrap();
+            schema::insert(&mut connection, &measurements).unwrap();
+        }
+
+        let events = events(
+            &dir,
+            &["new_job_pool_new_template", "sv1_new_job_latency"],
+            1.0,
+            2.0,
+        );
+        // Repeated values are kept, the zeros and the values out of the window are not
+        assert_eq!(
+            events,
+            BTreeMap::from([("new_job_pool_new_template".to_string(), vec![5.0, 4.0, 4.0])])
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,481 @@
+use crate::report::{
+    ComparisonReport, Measured, ScenarioReport, ALPHA, MIN_EXPECTED, MIN_RUNS, MIN_VALUES,
+};
+use crate::results::Run;
+use crate::scenario::{Configuration, NetworkProfile};
+use crate::stats;
+use crate::svg::{self, escape, number, Group};
+use chrono::{TimeZone, Utc};
+
+/// A part of the report, rendered in HTML or in Markdown.
+pub enum Block {
+    Heading(usize, String),
+    Paragraph(String),
+    Table {
+        header: Vec<String>,
+        rows: Vec<Vec<String>>,
+    },
+    Svg(String),
+}
+
+/// The report comparing SV1 and SV2 in every scenario. It only depends on the results, so the
+/// same results always give the same report.
+pub fn document(reports: &[ScenarioReport]) -> Vec<Block> {
+    let mut blocks = vec![
+        Block::Heading(1, "SV1 vs SV2 benchmark report".to_string()),
+        Block::Paragraph(format!(
+            "Scenarios: {}. Values are measured over the measurement window of every run, after the warm-up. Latencies and template values are the values the proxies reported during the window; rates and totals are computed from the increase of the counters.",
+            reports
+                .iter()
+                .map(|report| report.results.scenario.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+        Block::Heading(2, "Runs".to_string()),
+        Block::Table {
+            header: strings(&[
+                "Scenario",
+                "Configuration",
+                "Network",
+                "Measured",
+                "Warm-up",
+                "Repetitions",
+                "Network profile",
+                "Fault schedule",
+                "Started at",
+            ]),
+            rows: reports
+                .iter()
+                .map(|report| {
+                    let scenario = &report.results.scenario;
+                    vec![
+                        scenario.name.clone(),
+                        configuration(scenario.configuration).to_string(),
+                        scenario.network.clone(),
+                        format!("{} s", scenario.duration_s),
+                        format!("{} s", scenario.warm_up_s),
+                        format!("{} ({} completed)", scenario.repetitions, report.results.runs.len()),
+                        network_profile(&scenario.network_profile),
+                        scenario.fault_schedule.clone().unwrap_or_else(|| "none".to_string()),
+                        report.results.started_at.clone(),
+                    ]
+                })
+                .collect(),
+        },
+        Block::Table {
+            header: strings(&[
+                "Scenario",
+                "Run",
+                "Measured from",
+                "Measured until",
+                "Container restarts",
+                "Failed queries",
+            ]),
+            rows: reports
+                .iter()
+                .flat_map(|report| {
+                    report.results.runs.iter().map(|run| {
+                        vec![
+                            report.results.scenario.name.clone(),
+                            run.repetition.to_string(),
+                            time(run.measured_from),
+                            time(run.measured_until),
+                            run.restarts.to_string(),
+                            failed_queries(run),
+                        ]
+                    })
+                })
+                .collect(),
+        },
+    ];
+
+    for report in reports {
+        blocks.extend(scenario(report));
+    }
+
+    blocks.push(Block::Heading(2, "Method".to_string()));
+    blocks.push(Block::Paragraph(format!(
+        "Latencies and template values are compared with a Mann-Whitney U test on the values reported during all the runs, with a bootstrap interval ({} resamples) of the difference of their means. Share rates are compared with a two proportions z test on the shares of all the runs. Totals and bandwidth are compared over the values of the runs. A difference is significant when its p-value is below {}. It is flagged as insufficient data with fewer than {} runs with data on a side, fewer than {} reported values on a side, fewer than {} expected shares in a category, or when the runs disagree on its direction.",
+        stats::RESAMPLES, ALPHA, MIN_RUNS, MIN_VALUES, MIN_EXPECTED
+    )));
+
+    blocks.push(Block::Heading(2, "Charts".to_string()));
+    blocks.push(Block::Paragraph(
+        "Bars are the values over all the runs. Box plots span the quartiles of the values reported during the runs, the whiskers their minimum and maximum, the black line their median.".to_string(),
+    ));
+    let Some(first) = reports.first() else {
+        return blocks;
+    };
+    // Every configuration makes the same comparisons
+    for (index, comparison) in first.comparisons.iter().enumerate() {
+        blocks.push(Block::Heading(
+            3,
+            format!("{} ({})", comparison.title, comparison.unit),
+        ));
+        let compared = || {
+            reports.iter().map(move |report| {
+                (
+                    report.results.scenario.name.as_str(),
+                    &report.comparisons[index],
+                )
+            })
+        };
+        let chart = if compared().any(|(_, comparison)| {
+            comparison.sv1.events.is_some()
+                || comparison
+                    .sv2
+                    .as_ref()
+                    .is_some_and(|sv2| sv2.events.is_some())
+        }) {
+            let groups: Vec<Group<_>> = compared()
+                .map(|(label, comparison)| Group {
+                    label,
+                    sv1: comparison.sv1.events.as_ref(),
+                    sv2: comparison.sv2.as_ref().and_then(|sv2| sv2.events.as_ref()),
+                })
+                .collect();
+            svg::boxes(&groups, comparison.unit)
+        } else {
+            let groups: Vec<Group<_>> = compared()
+                .map(|(label, comparison)| Group {
+                    label,
+                    sv1: comparison.sv1.value,
+                    sv2: comparison.sv2.as_ref().and_then(|sv2| sv2.value),
+                })
+                .collect();
+            svg::bars(&groups, comparison.unit)
+        };
+        blocks.push(Block::Svg(chart));
+    }
+    blocks
+}
+
+fn scenario(report: &ScenarioReport) -> Vec<Block> {
+    let scenario = &report.results.scenario;
+    let runs = report.results.runs.len();
+    vec![
+        Block::Heading(
+            2,
+            format!(
+                "{} (configuration {})",
+                scenario.name,
+                configuration(scenario.configuration)
+            ),
+        ),
+        Block::Table {
+            header: strings(&["Metric", "SV1", "SV2", "SV2 delta"]),
+            rows: report
+                .comparisons
+                .iter()
+                .map(|comparison| {
+                    vec![
+                        comparison.title.to_string(),
+                        value(Some(&comparison.sv1), comparison.unit),
+                        value(comparison.sv2.as_ref(), comparison.unit),
+                        match comparison.delta() {
+                            Some((delta, Some(relative))) => format!(
+                                "{} {} ({}%)",
+                                signed(delta),
+                                comparison.unit,
+                                signed(relative)
+                            ),
+                            Some((delta, None)) => format!("{} {}", signed(delta), comparison.unit),
+                            None => "n/a".to_string(),
+                        },
+                    ]
+                })
+                .collect(),
+        },
+        Block::Heading(3, "Statistical significance".to_string()),
+        Block::Table {
+            header: strings(&[
+                "Metric",
+                "SV2 - SV1",
+                "95% interval",
+                "Test",
+                "p-value",
+                "Verdict",
+                "Data too thin because",
+            ]),
+            rows: report
+                .comparisons
+                .iter()
+                .filter_map(|comparison| {
+                    let significance = comparison.significance.as_ref()?;
+                    Some(vec![
+                        format!("{} ({})", comparison.title, comparison.unit),
+                        signed(significance.difference),
+                        significance
+                            .interval
+                            .map(|(low, high)| format!("[{}, {}]", signed(low), signed(high)))
+                            .unwrap_or_else(|| "n/a".to_string()),
+                        significance.test.to_string(),
+                        significance
+                            .p_value
+                            .map(p_value)
+                            .unwrap_or_else(|| "n/a".to_string()),
+                        significance.verdict().to_string(),
+                        significance.thin.join("; "),
+                    ])
+                })
+                .collect(),
+        },
+        Block::Heading(3, "Reported values".to_string()),
+        Block::Paragraph(event_source(report)),
+        Block::Table {
+            header: strings(&[
+                "Metric", "Side", "Values", "Mean", "Std dev", "Min", "Median", "p95", "Max",
+            ]),
+            rows: report
+                .comparisons
+                .iter()
+                .flat_map(|comparison| {
+                    sides(comparison)
+                        .into_iter()
+                        .filter_map(move |(side, measured)| {
+                            let summary = measured?.events.as_ref()?;
+                            Some(vec![
+                                format!("{} ({})", comparison.title, comparison.unit),
+                                side.to_string(),
+                                summary.count.to_string(),
+                                number(summary.mean),
+                                number(summary.std_dev),
+                                number(summary.min),
+                                number(summary.median),
+                                number(summary.p95),
+                                number(summary.max),
+                            ])
+                        })
+                })
+                .collect(),
+        },
+        Block::Heading(3, "Per run".to_string()),
+        Block::Table {
+            header: ["Metric", "Side"]
+                .iter()
+                .map(|header| header.to_string())
+                .chain((1..=runs).map(|run| format!("Run {}", run)))
+                .collect(),
+            rows: report
+                .comparisons
+                .iter()
+                .flat_map(|comparison| {
+                    sides(comparison)
+                        .into_iter()
+                        .filter_map(move |(side, measured)| {
+                            let measured = measured?;
+                            Some(
+                                [
+                                    format!("{} ({})", comparison.title, comparison.unit),
+                                    side.to_string(),
+                                ]
+                                .into_iter()
+                                .chain(measured.runs.iter().map(|run| optional(*run)))
+                                .collect(),
+                            )
+                        })
+                })
+                .collect(),
+        },
+        Block::Heading(3, "Bandwidth through the proxies".to_string()),
+        Block::Table {
+            header: strings(&["Proxy", "Upstream (B/s)", "Downstream (B/s)"]),
+            rows: report
+                .bandwidth
+                .iter()
+                .map(|proxy| {
+                    vec![
+                        proxy.job.clone(),
+                        optional(proxy.upstream),
+                        optional(proxy.downstream),
+                    ]
+                })
+                .collect(),
+        },
+    ]
+}
+
+pub fn html(blocks: &[Block]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>SV1 vs SV2 benchmark report</title>\n<style>\nbody { font-family: sans-serif; margin: 2em; }\ntable { border-collapse: collapse; margin: 1em 0; }\nth, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }\nth { background: #f4f4f4; }\n</style>\n</head>\n<body>\n",
+    );
+    for block in blocks {
+        match block {
+            Block::Heading(level, text) => {
+                html.push_str(&format!("<h{}>{}</h{}>\n", level, escape(text), level))
+            }
+            Block::Paragraph(text) => html.push_str(&format!("<p>{}</p>\n", escape(text))),
+            Block::Table { header, rows } => {
+                html.push_str("<table>\n<tr>");
+                for cell in header {
+                    html.push_str(&format!("<th>{}</th>", escape(cell)));
+                }
+                html.push_str("</tr>\n");
+                for row in rows {
+                    html.push_str("<tr>");
+                    for cell in row {
+                        html.push_str(&format!("<td>{}</td>", escape(cell)));
+                    }
+                    html.push_str("</tr>\n");
+                }
+                html.push_str("</table>\n");
+            }
+            Block::Svg(svg) => html.push_str(&format!("<div>{}</div>\n", svg)),
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+pub fn markdown(blocks: &[Block]) -> String {
+    let mut markdown = String::new();
+    for block in blocks {
+        match block {
+            Block::Heading(level, text) => {
+                markdown.push_str(&format!("{} {}\n\n", "#".repeat(*level), text))
+            }
+            Block::Paragraph(text) => markdown.push_str(&format!("{}\n\n", text)),
+            Block::Table { header, rows } => {
+                markdown.push_str(&row(header));
+                markdown.push_str(&row(&vec!["---".to_string(); header.len()]));
+                for cells in rows {
+                    markdown.push_str(&row(cells));
+                }
+                markdown.push('\n');
+            }
+            // Markdown renderers take inline HTML
+            Block::Svg(svg) => markdown.push_str(&format!("{}\n\n", svg)),
+        }
+    }
+    markdown
+}
+
+fn row(cells: &[String]) -> String {
+    format!(
+        "| {} |\n",
+        cells
+            .iter()
+            .map(|cell| cell.replace('|', "\\|"))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    )
+}
+
+// Where the values of the events come from: the measurements recorded by the proxies, or the
+// changes between the Prometheus samples, which miss some events
+fn event_source(report: &ScenarioReport) -> String {
+    let runs = &report.results.runs;
+    let recorded = runs
+        .iter()
+        .filter(|run| !run.recorded_events.is_empty())
+        .count();
+    let sampled = format!(
+        "the changes of the gauges between the Prometheus samples, every {} s: an event setting the value the gauge already had, or followed by another one before the next sample, is missed, and the counts understate the events",
+        report.results.scenario.step_s
+    );
+    if recorded == runs.len() {
+        "Values are every event recorded by the proxies during the window.".to_string()
+    } else if recorded == 0 {
+        format!(
+            "Values are {}. Set `record_measurements` in the scenario to get every event.",
+            sampled
+        )
+    } else {
+        format!(
+            "Values are every event recorded by the proxies in {} of the {} runs, and {} in the others.",
+            recorded,
+            runs.len(),
+            sampled
+        )
+    }
+}
+
+fn sides(comparison: &ComparisonReport) -> [(&'static str, Option<&Measured>); 2] {
+    [
+        ("SV1", Some(&comparison.sv1)),
+        ("SV2", comparison.sv2.as_ref()),
+    ]
+}
+
+fn strings(texts: &[&str]) -> Vec<String> {
+    texts.iter().map(|text| text.to_string()).collect()
+}
+
+fn configuration(configuration: Configuration) -> &'static str {
+    match configuration {
+        Configuration::A => "A",
+        Configuration::C => "C",
+    }
+}
+
+fn network_profile(profile: &NetworkProfile) -> String {
+    match profile {
+        NetworkProfile::Pools => "latency with the major pools".to_string(),
+        NetworkProfile::Fixed {
+            latency_ms,
+            jitter_ms,
+            loss_percent,
+            containers,
+        } => {
+            let mut profile = format!(
+                "{} ms ± {} ms, {}% loss",
+                latency_ms, jitter_ms, loss_percent
+            );
+            if let Some(containers) = containers {
+                profile.push_str(&format!(" on {}", containers.join(", ")));
+            }
+            profile
+        }
+    }
+}
+
+fn time(timestamp: f64) -> String {
+    Utc.timestamp_opt(timestamp as i64, 0)
+        .single()
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+fn failed_queries(run: &Run) -> String {
+    let failed: Vec<&str> = run
+        .queries
+        .iter()
+        .filter(|query| query.error.is_some())
+        .map(|query| query.query.as_str())
+        .collect();
+    if failed.is_empty() {
+        "none".to_string()
+    } else {
+        failed.join(", ")
+    }
+}
+
+fn value(measured: Option<&Measured>, unit: &str) -> String {
+    match measured {
+        None => "not measured".to_string(),
+        Some(Measured { value: None, .. }) => "no data".to_string(),
+        Some(Measured {
+            value: Some(value), ..
+        }) => format!("{} {}", number(*value), unit),
+    }
+}
+
+fn optional(value: Option<f64>) -> String {
+    value.map(number).unwrap_or_else(|| "no data".to_string())
+}
+
+fn p_value(p_value: f64) -> String {
+    if p_value < 0.0001 {
+        "< 0.0001".to_string()
+    } else {
+        format!("{:.4}", p_value)
+    }
+}
+
+fn signed(value: f64) -> String {
+    if value > 0.0 {
+        format!("+{}", number(value))
+    } else {
+        number(value)
+    }
+}
@@ -0,0 +1,511 @@
+use crate::prometheus::Series;
+use crate::results::{Results, Run};
+use crate::scenario::Configuration;
+use crate::stats::{self, Summary};
+use std::collections::BTreeMap;
+
+// Significance level of the tests, and confidence of the intervals
+pub const ALPHA: f64 = 0.05;
+// Below these, the comparison is flagged as too thin to support a claim
+pub const MIN_RUNS: usize = 3;
+pub const MIN_VALUES: usize = 20;
+// Expected successes and failures on each side for the normal approximation of the proportions
+pub const MIN_EXPECTED: f64 = 5.0;
+
+// Metrics counting the bytes the proxies forward
+const TRAFFIC_METRICS: [&str; 2] = ["sv1_traffic_bytes", "sv2_traffic_bytes"];
+
+/// A metric exported by a proxy, optionally restricted to the Prometheus job of one proxy.
+#[derive(Clone, Copy, Debug)]
+pub struct Selector {
+    pub name: &'static str,
+    pub job: Option<&'static str>,
+}
+
+const fn metric(name: &'static str) -> Selector {
+    Selector { name, job: None }
+}
+
+const fn metric_of(name: &'static str, job: &'static str) -> Selector {
+    Selector {
+        name,
+        job: Some(job),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Measure {
+    // Values taken by a gauge set on every event (a job latency, a template value)
+    Events(Selector),
+    // Increase of the counters over the measurement window
+    Increase(Selector),
+    // Increase of the first counters relative to the second ones, in %
+    Ratio(Selector, Selector),
+    // Increase of the counters per second of the measurement window
+    PerSecond(Selector),
+}
+
+/// A metric compared between the SV1 and the SV2 side of a configuration.
+pub struct Comparison {
+    pub title: &'static str,
+    pub unit: &'static str,
+    pub sv1: Measure,
+    // None when the configuration has no proxy measuring it
+    pub sv2: Option<Measure>,
+}
+
+/// The comparisons made for a configuration, following its Grafana dashboard.
+pub fn comparisons(configuration: Configuration) -> Vec<Comparison> {
+    let (new_job, new_job_prev_hash, block_propagation, bandwidth) = match configuration {
+        Configuration::A => (
+            "new_job_jdc_new_template",
+            "new_job_prev_hash_throught_sv2_jdc",
+            "block_propagation_time_through_sv2_jdc",
+            // The JDC connection to the pool isn't proxied
+            None,
+        ),
+        Configuration::C => (
+            "new_job_pool_new_template",
+            "new_job_prev_hash_through_sv2_pool",
+            "block_propagation_time_through_sv2_pool",
+            Some(Measure::PerSecond(metric_of(
+                "sv2_traffic_bytes",
+                "sv2-pool-translator-proxy",
+            ))),
+        ),
+    };
+    vec![
+        Comparison {
+            title: "Share acceptance rate",
+            unit: "%",
+            sv1: Measure::Ratio(metric("sv1_valid_shares"), metric("sv1_submitted_shares")),
+            sv2: Some(Measure::Ratio(
+                metric("sv2_valid_shares"),
+                metric("sv2_submitted_shares"),
+            )),
+        },
+        Comparison {
+            title: "Stale share rate",
+            unit: "%",
+            sv1: Measure::Ratio(metric("sv1_stale_shares"), metric("sv1_submitted_shares")),
+            sv2: Some(Measure::Ratio(
+                metric("sv2_stale_shares"),
+                metric("sv2_submitted_shares"),
+            )),
+        },
+        Comparison {
+            title: "Submitted shares",
+            unit: "shares",
+            sv1: Measure::Increase(metric("sv1_submitted_shares")),
+            sv2: Some(Measure::Increase(metric("sv2_submitted_shares"))),
+        },
+        Comparison {
+            title: "Mined blocks",
+            unit: "blocks",
+            sv1: Measure::Increase(metric("sv1_mined_blocks")),
+            sv2: Some(Measure::Increase(metric("sv2_mined_blocks"))),
+        },
+        Comparison {
+            title: "Time to get a new job",
+            unit: "ms",
+            sv1: Measure::Events(metric("sv1_new_job_latency")),
+            sv2: Some(Measure::Events(metric(new_job))),
+        },
+        Comparison {
+            title: "Time to get a new job after a new block",
+            unit: "ms",
+            sv1: Measure::Events(metric("sv1_new_job_prev_hash_latency")),
+            sv2: Some(Measure::Events(metric(new_job_prev_hash))),
+        },
+        Comparison {
+            title: "Block propagation time",
+            unit: "ms",
+            sv1: Measure::Events(metric("block_propagation_time_through_sv1_pool")),
+            sv2: Some(Measure::Events(metric(block_propagation))),
+        },
+        Comparison {
+            title: "Block template value",
+            unit: "sats",
+            sv1: Measure::Events(metric("sv1_block_template_value")),
+            sv2: Some(Measure::Events(metric("sv2_block_template_value"))),
+        },
+        Comparison {
+            title: "Bandwidth between the farm and the pool",
+            unit: "B/s",
+            sv1: Measure::PerSecond(metric_of("sv1_traffic_bytes", "sv1-pool-miner-proxy")),
+            sv2: bandwidth,
+        },
+    ]
+}
+
+/// The metrics of the events compared for a configuration.
+pub fn event_metrics(configuration: Configuration) -> Vec<&'static str> {
+    comparisons(configuration)
+        .iter()
+        .flat_map(|comparison| [Some(comparison.sv1), comparison.sv2])
+        .filter_map(|measure| match measure {
+            Some(Measure::Events(selector)) => Some(selector.name),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A measure over the runs of a scenario.
+#[derive(Clone, Debug, Default)]
+pub struct Measured {
+    // Value of every run, None when the run has no data
+    pub runs: Vec<Option<f64>>,
+    // Value over all the runs: mean of the events, or ratio, sum or rate of the increases
+    pub value: Option<f64>,
+    // The events of all the runs, for the measures of events
+    pub events: Option<Summary>,
+    pub values: Vec<f64>,
+    // Increases of the numerator and of the denominator over all the runs, for the ratios
+    pub counts: Option<(f64, f64)>,
+}
+
+pub struct ComparisonReport {
+    pub title: &'static str,
+    pub unit: &'static str,
+    pub sv1: Measured,
+    pub sv2: Option<Measured>,
+    pub significance: Option<Significance>,
+}
+
+/// Whether the difference between SV2 and SV1 is supported by the data.
+pub struct Significance {
+    pub test: &'static str,
+    // SV2 minus SV1 and its confidence interval, in the unit of the comparison
+    pub difference: f64,
+    pub interval: Option<(f64, f64)>,
+    pub p_value: Option<f64>,
+    // Why the data is too thin to support a claim, empty when it isn't
+    pub thin: Vec<String>,
+}
+
+impl Significance {
+    pub fn verdict(&self) -> &'static str {
+        if !self.thin.is_empty() {
+            "insufficient data"
+        } else if self.p_value.is_some_and(|p_value| p_value < ALPHA) {
+            "significant"
+        } else {
+            "not significant"
+        }
+    }
+}
+
+impl ComparisonReport {
+    /// SV2 minus SV1, absolute and relative to SV1 in %.
+    pub fn delta(&self) -> Option<(f64, Option<f64>)> {
+        let sv1 = self.sv1.value?;
+        let sv2 = self.sv2.as_ref()?.value?;
+        let relative = (sv1 != 0.0).then(|| (sv2 - sv1) / sv1 * 100.0);
+        Some((sv2 - sv1, relative))
+    }
+}
+
+/// Bytes per second forwarded by a proxy over the measurement windows.
+pub struct ProxyBandwidth {
+    pub job: String,
+    pub upstream: Option<f64>,
+    pub downstream: Option<f64>,
+}
+
+pub struct ScenarioReport<'a> {
+    pub results: &'a Results,
+    pub comparisons: Vec<ComparisonReport>,
+    pub bandwidth: Vec<ProxyBandwidth>,
+}
+
+impl<'a> ScenarioReport<'a> {
+    pub fn new(results: &'a Results) -> Self {
+        let comparisons = comparisons(results.scenario.configuration)
+            .into_iter()
+            .map(|comparison| {
+                let sv1 = measure(&results.runs, comparison.sv1);
+                let sv2 = comparison.sv2.map(|sv2| measure(&results.runs, sv2));
+                ComparisonReport {
+                    title: comparison.title,
+                    unit: comparison.unit,
+                    significance: sv2
+                        .as_ref()
+                        .and_then(|sv2| significance(comparison.sv1, &sv1, sv2)),
+                    sv1,
+                    sv2,
+                }
+            })
+            .collect();
+        Self {
+            results,
+            comparisons,
+            bandwidth: bandwidth(&results.runs),
+        }
+    }
+}
+
+pub fn measure(runs: &[Run], measure: Measure) -> Measured {
+    match measure {
+        Measure::Events(selector) => {
+            let events: Vec<Vec<f64>> = runs
+                .iter()
+                .map(|run| match run.recorded_events.get(selector.name) {
+                    Some(recorded) => recorded.clone(),
+                    None => matching(run, selector).flat_map(events).collect(),
+                })
+                .collect();
+            let all: Vec<f64> = events.iter().flatten().copied().collect();
+            Measured {
+                runs: events
+                    .iter()
+                    .map(|run| (!run.is_empty()).then(|| stats::mean(run)))
+                    .collect(),
+                value: (!all.is_empty()).then(|| stats::mean(&all)),
+                events: Summary::of(&all),
+                values: all,
+                counts: None,
+            }
+        }
+        Measure::Increase(selector) => {
+            let increases: Vec<Option<f64>> =
+                runs.iter().map(|run| run_increase(run, selector)).collect();
+            Measured {
+                value: sum(&increases),
+                runs: increases,
+                ..Default::default()
+            }
+        }
+        Measure::Ratio(numerator, denominator) => {
+            let pairs: Vec<(Option<f64>, Option<f64>)> = runs
+                .iter()
+                .map(|run| (run_increase(run, numerator), run_increase(run, denominator)))
+                .collect();
+            let numerators: Vec<Option<f64>> = pairs.iter().map(|pair| pair.0).collect();
+            let denominators: Vec<Option<f64>> = pairs.iter().map(|pair| pair.1).collect();
+            Measured {
+                runs: pairs
+                    .iter()
+                    .map(|&(numerator, denominator)| ratio(numerator, denominator))
+                    .collect(),
+                value: ratio(sum(&numerators), sum(&denominators)),
+                counts: sum(&denominators)
+                    .map(|denominator| (sum(&numerators).unwrap_or_default(), denominator)),
+                ..Default::default()
+            }
+        }
+        Measure::PerSecond(selector) => {
+            let increases: Vec<Option<f64>> =
+                runs.iter().map(|run| run_increase(run, selector)).collect();
+            let windows: f64 = runs
+                .iter()
+                .zip(&increases)
+                .filter(|(_, increase)| increase.is_some())
+                .map(|(run, _)| window(run))
+                .sum();
+            Measured {
+                runs: runs
+                    .iter()
+                    .zip(&increases)
+                    .map(|(run, increase)| Some(increase.as_ref()? / window(run)))
+                    .collect(),
+                value: sum(&increases).map(|total| total / windows),
+                ..Default::default()
+            }
+        }
+    }
+}
+
+/// Tests the difference between SV2 and SV1: Mann-Whitney U on the values reported during the
+/// runs with a bootstrap interval of the difference of their means, two proportions z test on
+/// the pooled counts of the ratios, Mann-Whitney U on the values of the runs otherwise.
+pub fn significance(kind: Measure, sv1: &Measured, sv2: &Measured) -> Option<Significance> {
+    let difference = sv2.value? - sv1.value?;
+    let runs = |measured: &Measured| measured.runs.iter().flatten().count();
+    let mut thin = Vec::new();
+    let runs_with_data = runs(sv1).min(runs(sv2));
+    if runs_with_data < MIN_RUNS {
+        thin.push(format!(
+            "{} runs with data, at least {} needed",
+            runs_with_data, MIN_RUNS
+        ));
+    }
+    let paired: Vec<f64> = sv1
+        .runs
+        .iter()
+        .zip(&sv2.runs)
+        .filter_map(|(sv1, sv2)| Some(sv2.as_ref()? - sv1.as_ref()?))
+        .collect();
+    if paired.iter().any(|delta| *delta > 0.0) && paired.iter().any(|delta| *delta < 0.0) {
+        thin.push("the runs disagree on the direction of the difference".to_string());
+    }
+
+    let confidence = 1.0 - ALPHA;
+    let significance = match kind {
+        Measure::Events(_) => {
+            let values = sv1.values.len().min(sv2.values.len());
+            if values < MIN_VALUES {
+                thin.push(format!(
+                    "{} values reported on a side, at least {} needed",
+                    values, MIN_VALUES
+                ));
+            }
+            Significance {
+                test: "Mann-Whitney U",
+                difference,
+                interval: stats::bootstrap_mean_difference(&sv1.values, &sv2.values, confidence),
+                p_value: stats::mann_whitney(&sv1.values, &sv2.values),
+                thin,
+            }
+        }
+        Measure::Ratio(..) => {
+            let (x1, n1) = sv1.counts?;
+            let (x2, n2) = sv2.counts?;
+            if x1 > n1 || x2 > n2 {
+                // The counters of the shares come from different proxies or scrapes
+                thin.push("more shares counted than submitted".to_string());
+            }
+            let pooled = (x1 + x2) / (n1 + n2);
+            let expected = [n1, n2]
+                .iter()
+                .flat_map(|n| [n * pooled, n * (1.0 - pooled)])
+                .fold(f64::INFINITY, f64::min);
+            if expected < MIN_EXPECTED {
+                thin.push(format!(
+                    "{:.1} expected shares in a category, at least {} needed",
+                    expected, MIN_EXPECTED
+                ));
+            }
+            Significance {
+                test: "two proportions z",
+                difference,
+                interval: stats::proportion_difference(x1, n1, x2, n2, confidence)
+                    .map(|(low, high)| (low * 100.0, high * 100.0)),
+                p_value: stats::two_proportions(x1, n1, x2, n2),
+                thin,
+            }
+        }
+        Measure::Increase(_) | Measure::PerSecond(_) => {
+            let sv1_runs: Vec<f64> = sv1.runs.iter().flatten().copied().collect();
+            let sv2_runs: Vec<f64> = sv2.runs.iter().flatten().copied().collect();
+            Significance {
+                test: "Mann-Whitney U over the runs",
+                // The totals depend on the number of runs, compare the runs
+                difference: stats::mean(&sv2_runs) - stats::mean(&sv1_runs),
+                interval: stats::bootstrap_mean_difference(&sv1_runs, &sv2_runs, confidence),
+                p_value: stats::mann_whitney(&sv1_runs, &sv2_runs),
+                thin,
+            }
+        }
+    };
+    Some(significance)
+}
+
+fn bandwidth(runs: &[Run]) -> Vec<ProxyBandwidth> {
+    // (job, direction) -> (bytes, seconds)
+    let mut totals: BTreeMap<(String, String), (f64, f64)> = BTreeMap::new();
+    for run in runs {
+        let mut bytes: BTreeMap<(String, String), f64> = BTreeMap::new();
+        for series in unique_series(run) {
+            let name = series.labels.get("__name__").map(String::as_str);
+            if !name.is_some_and(|name| TRAFFIC_METRICS.contains(&name)) {
+                continue;
+            }
+            let (Some(job), Some(direction)) =
+                (series.labels.get("job"), series.labels.get("direction"))
+            else {
+                continue;
+            };
+            *bytes.entry((job.clone(), direction.clone())).or_default() += increase(series);
+        }
+        for (key, bytes) in bytes {
+            let total = totals.entry(key).or_default();
+            total.0 += bytes;
+            total.1 += window(run);
+        }
+    }
+    let mut proxies: BTreeMap<String, ProxyBandwidth> = BTreeMap::new();
+    for ((job, direction), (bytes, seconds)) in totals {
+        let proxy = proxies.entry(job.clone()).or_insert(ProxyBandwidth {
+            job,
+            upstream: None,
+            downstream: None,
+        });
+        match direction.as_str() {
+            "upstream" => proxy.upstream = Some(bytes / seconds),
+            "downstream" => proxy.downstream = Some(bytes / seconds),
+            _ => {}
+        }
+    }
+    proxies.into_values().collect()
+}
+
+fn window(run: &Run) -> f64 {
+    run.measured_until - run.measured_from
+}
+
+// Every series of a run once, whatever the queries that returned it
+fn unique_series(run: &Run) -> impl Iterator<Item = &Series> {
+    let mut series = BTreeMap::new();
+    for query in &run.queries {
+        for result in &query.series {
+            series.entry(&result.labels).or_insert(result);
+        }
+    }
+    series.into_values()
+}
+
+fn matching(run: &Run, selector: Selector) -> impl Iterator<Item = &Series> {
+    unique_series(run).filter(move |series| {
+        series.labels.get("__name__").map(String::as_str) == Some(selector.name)
+            && selector
+                .job
+                .iter()
+                .all(|&job| series.labels.get("job").map(String::as_str) == Some(job))
+    })
+}
+
+// The values a gauge changed to during the window. The value at the start of the window was
+// set before it, and zero is the value of the gauge before its first event. Only the changes
+// between two Prometheus samples are seen, the runs recording the measurements have every one.
+fn events(series: &Series) -> impl Iterator<Item = f64> + '_ {
+    series
+        .values
+        .windows(2)
+        .filter(|pair| pair[1].1 != pair[0].1 && pair[1].1 != 0.0)
+        .map(|pair| pair[1].1)
+}
+
+// Increase of a counter, a decrease being a restart of the proxy
+fn increase(series: &Series) -> f64 {
+    series
+        .values
+        .windows(2)
+        .map(|pair| {
+            if pair[1].1 >= pair[0].1 {
+                pair[1].1 - pair[0].1
+            } else {
+                pair[1].1
+            }
+        })
+        .sum()
+}
+
+fn run_increase(run: &Run, selector: Selector) -> Option<f64> {
+    let mut found = false;
+    let mut total = 0.0;
+    for series in matching(run, selector) {
+        found = true;
+        total += increase(series);
+    }
+    found.then_some(total)
+}
+
+fn sum(values: &[Option<f64>]) -> Option<f64> {
+    let present: Vec<f64> = values.iter().flatten().copied().collect();
+    (!present.is_empty()).then(|| present.iter().sum())
+}
+
+fn ratio(numerator: Option<f64>, denominator: Option<f64>) -> Option<f64> {
+    let denominator = denominator?;
+    (denominator > 0.0).then(|| numerator.unwrap_or_default() / denominator * 100.0)
+}
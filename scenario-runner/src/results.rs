@@ -0,0 +1,55 @@
+use crate::prometheus::Series;
+use crate::scenario::Scenario;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QueryResult {
+    pub query: String,
+    pub series: Vec<Series>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// One repetition of the scenario. Times are seconds since the epoch.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Run {
+    pub repetition: u32,
+    // When the stack was ready
+    pub started_at: f64,
+    // The measurement window, after the warm-up
+    pub measured_from: f64,
+    pub measured_until: f64,
+    // Container restarts during the run
+    pub restarts: i64,
+    pub queries: Vec<QueryResult>,
+    // Values of the event gauges recorded by the proxies during the window, by metric, when
+    // the scenario records the measurements
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub recorded_events: BTreeMap<String, Vec<f64>>,
+}
+
+/// What the scenario runner writes, rewritten after every run so that an interrupted scenario
+/// keeps the completed runs.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Results {
+    pub scenario: Scenario,
+    // RFC 3339
+    pub started_at: String,
+    pub runs: Vec<Run>,
+}
+
+impl Results {
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn write(&self, path: &str) -> std::io::Result<()> {
+        if let Some(dir) = Path::new(path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+}
@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// Where the proxies find the fault schedules, mounted from custom-configs/fault-schedules
+pub const FAULT_SCHEDULES_DIR: &str = "custom-configs/fault-schedules";
+const FAULT_SCHEDULES_MOUNT: &str = "/fault-schedules";
+// The SV1 proxy injecting faults on the SV1 side, the other proxies are on the SV2 side (the
+// node-pool proxy forwards RPC calls and injects none)
+const SV1_FAULTS_PROXY: &str = "pool-miner";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Configuration {
+    A,
+    C,
+}
+
+impl Configuration {
+    pub fn compose_file(self) -> &'static str {
+        match self {
+            Configuration::A => "docker-compose-config-a.yaml",
+            Configuration::C => "docker-compose-config-c.yaml",
+        }
+    }
+
+    /// Label carried by every container of the configuration.
+    pub fn label(self) -> &'static str {
+        match self {
+            Configuration::A => "logging=config-a",
+            Configuration::C => "logging=config-c",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(tag = "profile", rename_all = "snake_case")]
+pub enum NetworkProfile {
+    // The latency derived from the RTT with the major pools, applied by the containers themselves
+    #[default]
+    Pools,
+    // A fixed netem profile replacing it, in `containers` or in every container applying the
+    // pools latency
+    Fixed {
+        latency_ms: u64,
+        #[serde(default)]
+        jitter_ms: u64,
+        #[serde(default)]
+        loss_percent: f64,
+        #[serde(default)]
+        containers: Option<Vec<String>>,
+    },
+}
+
+/// A benchmark: `repetitions` runs of `configuration`, each measured for `duration_s` seconds
+/// once the stack is ready and `warm_up_s` seconds passed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Scenario {
+    pub name: String,
+    pub configuration: Configuration,
+    #[serde(default = "default_network")]
+    pub network: String,
+    pub duration_s: u64,
+    #[serde(default)]
+    pub warm_up_s: u64,
+    #[serde(default = "default_repetitions")]
+    pub repetitions: u32,
+    #[serde(default)]
+    pub network_profile: NetworkProfile,
+    // File name in custom-configs/fault-schedules
+    #[serde(default)]
+    pub fault_schedule: Option<String>,
+    // Whether the proxies record their raw measurements in recorded-measurements/
+    #[serde(default)]
+    pub record_measurements: bool,
+    // Extra variables of the compose file, e.g. COMPOSE_PROFILES or SIMULATED_MINER_USER
+    #[serde(default)]
+    pub environment: BTreeMap<String, String>,
+    #[serde(default = "default_ready_timeout_s")]
+    pub ready_timeout_s: u64,
+    // Resolution of the collected series
+    #[serde(default = "default_step_s")]
+    pub step_s: u64,
+    // PromQL queries collected over every run
+    #[serde(default = "default_queries")]
+    pub queries: Vec<String>,
+}
+
+fn default_network() -> String {
+    "testnet4".to_string()
+}
+
+fn default_repetitions() -> u32 {
+    1
+}
+
+fn default_ready_timeout_s() -> u64 {
+    1800
+}
+
+fn default_step_s() -> u64 {
+    15
+}
+
+fn default_queries() -> Vec<String> {
+    [
+        r#"{__name__=~"sv1_.+|sv2_.+|new_job_.+|block_propagation_.+|average_pool_.+"}"#,
+        "container_network_transmit_bytes_total",
+        "container_network_receive_bytes_total",
+        "container_cpu_usage_seconds_total",
+        "container_memory_usage_bytes",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect()
+}
+
+impl Scenario {
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let scenario: Scenario = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if !matches!(
+            scenario.network.as_str(),
+            "mainnet" | "testnet3" | "testnet4" | "regtest"
+        ) {
+            return Err(format!("Unsupported network {}", scenario.network).into());
+        }
+        if scenario.duration_s == 0 || scenario.repetitions == 0 {
+            return Err("duration_s and repetitions must be positive".into());
+        }
+        if let Some(schedule) = &scenario.fault_schedule {
+            let path = Path::new(FAULT_SCHEDULES_DIR).join(schedule);
+            if !path.is_file() {
+                return Err(format!(
+                    "Fault schedule {} not found in {}",
+                    schedule, FAULT_SCHEDULES_DIR
+                )
+                .into());
+            }
+            check_fault_schedule(&fs::read_to_string(path)?)
+                .map_err(|e| format!("Fault schedule {}: {}", schedule, e))?;
+        }
+        Ok(scenario)
+    }
+
+    /// Value of `FAULT_SCHEDULE` for the proxies, empty without faults.
+    pub fn fault_schedule_env(&self) -> String {
+        match &self.fault_schedule {
+            Some(schedule) => format!("{}/{}", FAULT_SCHEDULES_MOUNT, schedule),
+            None => String::new(),
+        }
+    }
+}
+
+// Faults on a single side slow it down compared to the other one, which the comparison would
+// then attribute to the protocol
+fn check_fault_schedule(schedule: &str) -> Result<(), String> {
+    let entries: Vec<serde_json::Value> =
+        serde_json::from_str(schedule).map_err(|e| e.to_string())?;
+    let (mut sv1, mut sv2) = (false, false);
+    for entry in &entries {
+        let proxies = entry["proxies"]
+            .as_array()
+            .ok_or("every entry must list its proxies")?;
+        for proxy in proxies {
+            let proxy = proxy.as_str().ok_or("proxies must be strings")?;
+            if proxy == SV1_FAULTS_PROXY {
+                sv1 = true;
+            } else {
+                sv2 = true;
+            }
+        }
+    }
+    match (sv1, sv2) {
+        (true, false) => Err("faults are only injected on the SV1 side".to_string()),
+        (false, true) => Err(format!(
+            "faults are only injected on the SV2 side, add entries for the {} proxy",
+            SV1_FAULTS_PROXY
+        )),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,208 @@
+use crate::scenario::{Configuration, NetworkProfile, Scenario};
+use bollard::container::{InspectContainerOptions, ListContainersOptions};
+use bollard::exec::{CreateExecOptions, StartExecResults};
+use bollard::models::ContainerSummary;
+use bollard::Docker;
+use futures_util::StreamExt;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tokio::time::sleep;
+
+const READY_POLL: Duration = Duration::from_secs(10);
+// Script run next to the roles to apply the latency measured with the major pools
+const LATENCY_SCRIPT: &str = "monitor_and_apply_latency.sh";
+
+/// A docker compose stack of one configuration. Compose files are brought up and down with the
+/// `docker compose` CLI, the containers are then followed through the Docker API.
+pub struct Stack {
+    configuration: Configuration,
+    docker: Docker,
+}
+
+impl Stack {
+    pub fn new(configuration: Configuration) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            configuration,
+            docker: Docker::connect_with_local_defaults()?,
+        })
+    }
+
+    async fn compose(&self, args: &[&str], scenario: &Scenario) -> std::io::Result<()> {
+        let status = Command::new("docker")
+            .args(["compose", "-f", self.configuration.compose_file()])
+            .args(args)
+            .envs(&scenario.environment)
+            .env("NETWORK", &scenario.network)
+            .env("FAULT_SCHEDULE", scenario.fault_schedule_env())
+            .env(
+                "RECORD_MEASUREMENTS",
+                if scenario.record_measurements {
+                    "1"
+                } else {
+                    ""
+                },
+            )
+            .status()
+            .await?;
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "docker compose {} failed: {}",
+                args.join(" "),
+                status
+            )));
+        }
+        Ok(())
+    }
+
+    pub async fn up(&self, scenario: &Scenario) -> std::io::Result<()> {
+        info!("Starting {}", self.configuration.compose_file());
+        self.compose(&["up", "-d"], scenario).await
+    }
+
+    pub async fn down(&self, scenario: &Scenario) -> std::io::Result<()> {
+        info!("Stopping {}", self.configuration.compose_file());
+        self.compose(&["down"], scenario).await
+    }
+
+    async fn containers(&self) -> Result<Vec<ContainerSummary>, bollard::errors::Error> {
+        let mut filters = HashMap::new();
+        filters.insert(
+            "label".to_string(),
+            vec![self.configuration.label().to_string()],
+        );
+        self.docker
+            .list_containers(Some(ListContainersOptions::<String> {
+                all: true,
+                filters,
+                ..Default::default()
+            }))
+            .await
+    }
+
+    /// Waits until every container of the stack is running (and healthy when it has a
+    /// healthcheck), the builders having exited successfully.
+    pub async fn wait_ready(&self, timeout: Duration) -> Result<(), Box<dyn std::error::Error>> {
+        let started = Instant::now();
+        loop {
+            let containers = self.containers().await?;
+            let waiting: Vec<String> = containers
+                .iter()
+                .filter(|container| !ready(container))
+                .map(name)
+                .collect();
+            if !containers.is_empty() && waiting.is_empty() {
+                info!("{} containers ready", containers.len());
+                return Ok(());
+            }
+            if started.elapsed() > timeout {
+                return Err(format!("Containers not ready in time: {}", waiting.join(", ")).into());
+            }
+            info!("Waiting for {}", waiting.join(", "));
+            sleep(READY_POLL).await;
+        }
+    }
+
+    /// Sum of the restarts of the containers of the stack.
+    pub async fn restarts(&self) -> Result<i64, bollard::errors::Error> {
+        let mut restarts = 0;
+        for container in self.containers().await? {
+            let Some(id) = container.id else {
+                continue;
+            };
+            let details = self
+                .docker
+                .inspect_container(&id, None::<InspectContainerOptions>)
+                .await?;
+            restarts += details.restart_count.unwrap_or_default();
+        }
+        Ok(restarts)
+    }
+
+    pub async fn apply_network_profile(
+        &self,
+        profile: &NetworkProfile,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let NetworkProfile::Fixed {
+            latency_ms,
+            jitter_ms,
+            loss_percent,
+            containers,
+        } = profile
+        else {
+            return Ok(());
+        };
+        let targets = match containers {
+            Some(containers) => containers.clone(),
+            None => self.latency_containers().await?,
+        };
+        // The bracket keeps pkill from matching the shell running it
+        let command = format!(
+            "pkill -f '[m]onitor_and_apply_latency'; tc qdisc del dev eth0 root 2>/dev/null; \
+             tc qdisc add dev eth0 root netem delay {}ms {}ms loss {}%",
+            latency_ms, jitter_ms, loss_percent
+        );
+        for container in targets {
+            info!("Applying the network profile to {}", container);
+            let exec = self
+                .docker
+                .create_exec(
+                    &container,
+                    CreateExecOptions {
+                        cmd: Some(vec!["sh", "-c", command.as_str()]),
+                        attach_stdout: Some(true),
+                        attach_stderr: Some(true),
+                        ..Default::default()
+                    },
+                )
+                .await?;
+            if let StartExecResults::Attached { mut output, .. } =
+                self.docker.start_exec(&exec.id, None).await?
+            {
+                while let Some(Ok(line)) = output.next().await {
+                    warn!("{}: {}", container, line.to_string().trim_end());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The running containers applying the pools latency
+    async fn latency_containers(&self) -> Result<Vec<String>, bollard::errors::Error> {
+        Ok(self
+            .containers()
+            .await?
+            .iter()
+            .filter(|container| container.state.as_deref() == Some("running"))
+            .filter(|container| {
+                container
+                    .command
+                    .as_deref()
+                    .is_some_and(|command| command.contains(LATENCY_SCRIPT))
+            })
+            .map(name)
+            .collect())
+    }
+}
+
+fn name(container: &ContainerSummary) -> String {
+    container
+        .names
+        .as_ref()
+        .and_then(|names| names.first())
+        .map(|name| name.trim_start_matches('/').to_string())
+        .unwrap_or_default()
+}
+
+fn ready(container: &ContainerSummary) -> bool {
+    let status = container.status.as_deref().unwrap_or_default();
+    match container.state.as_deref() {
+        Some("running") => {
+            !status.contains("(health: starting)") && !status.contains("(unhealthy)")
+        }
+        // The builders only build the images and exit
+        Some("exited") => status.starts_with("Exited (0)"),
+        _ => false,
+    }
+}
@@ -0,0 +1,334 @@
+use serde::Serialize;
+
+/// Summary statistics of a set of samples.
+#[derive(Serialize, Clone, Debug)]
+pub struct Summary {
+    pub count: usize,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub p95: f64,
+    pub max: f64,
+}
+
+impl Summary {
+    pub fn of(samples: &[f64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let sorted = sorted(samples);
+        let mean = mean(&sorted);
+        Some(Self {
+            count: sorted.len(),
+            mean,
+            std_dev: std_dev(&sorted, mean),
+            min: sorted[0],
+            p25: quantile(&sorted, 0.25),
+            median: quantile(&sorted, 0.5),
+            p75: quantile(&sorted, 0.75),
+            p95: quantile(&sorted, 0.95),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+pub fn sorted(samples: &[f64]) -> Vec<f64> {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    sorted
+}
+
+pub fn mean(samples: &[f64]) -> f64 {
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+// Sample standard deviation, 0 for a single sample
+fn std_dev(samples: &[f64], mean: f64) -> f64 {
+    if samples.len() < 2 {
+        return 0.0;
+    }
+    let squares: f64 = samples.iter().map(|sample| (sample - mean).powi(2)).sum();
+    (squares / (samples.len() - 1) as f64).sqrt()
+}
+
+/// Quantile of sorted samples, interpolated between the closest ranks.
+pub fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let rank = q * (sorted.len() - 1) as f64;
+    let below = rank.floor() as usize;
+    let above = rank.ceil() as usize;
+    sorted[below] + (sorted[above] - sorted[below]) * (rank - below as f64)
+}
+
+// Resamples of the bootstrap confidence intervals
+pub const RESAMPLES: usize = 2000;
+// Seed of the resampling, fixed so the same results always give the same intervals
+const SEED: u64 = 0x5632_5631;
+// Largest samples for which the Mann-Whitney p-value is computed exactly
+const EXACT_MAX_SIZE: usize = 20;
+
+/// SplitMix64, enough to draw the bootstrap resamples and keeping them reproducible.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn index(&mut self, len: usize) -> usize {
+        (self.next() % len as u64) as usize
+    }
+}
+
+/// Percentile bootstrap interval, at the given confidence, of the difference of the means of
+/// `b` and `a` (b - a).
+pub fn bootstrap_mean_difference(a: &[f64], b: &[f64], confidence: f64) -> Option<(f64, f64)> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    let mut rng = SplitMix64(SEED);
+    let mut resample = |samples: &[f64]| {
+        (0..samples.len())
+            .map(|_| samples[rng.index(samples.len())])
+            .sum::<f64>()
+            / samples.len() as f64
+    };
+    let mut differences: Vec<f64> = (0..RESAMPLES)
+        .map(|_| {
+            let a = resample(a);
+            resample(b) - a
+        })
+        .collect();
+    differences.sort_by(f64::total_cmp);
+    let tail = (1.0 - confidence) / 2.0;
+    Some((
+        quantile(&differences, tail),
+        quantile(&differences, 1.0 - tail),
+    ))
+}
+
+/// Two-sided p-value of the Mann-Whitney U test that the values of `a` and `b` come from the
+/// same distribution. Exact for small samples without ties, normal approximation otherwise.
+pub fn mann_whitney(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    let (n1, n2) = (a.len(), b.len());
+    let mut values: Vec<(f64, bool)> = a
+        .iter()
+        .map(|&value| (value, true))
+        .chain(b.iter().map(|&value| (value, false)))
+        .collect();
+    values.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    // Average ranks of the ties, and the tie correction of the variance
+    let mut rank_sum_a = 0.0;
+    let mut ties = 0.0;
+    let mut start = 0;
+    while start < values.len() {
+        let mut end = start;
+        while end + 1 < values.len() && values[end + 1].0 == values[start].0 {
+            end += 1;
+        }
+        let rank = (start + end) as f64 / 2.0 + 1.0;
+        let tied = (end - start + 1) as f64;
+        ties += tied.powi(3) - tied;
+        rank_sum_a += rank * values[start..=end].iter().filter(|value| value.1).count() as f64;
+        start = end + 1;
+    }
+    let u = rank_sum_a - (n1 * (n1 + 1)) as f64 / 2.0;
+    let (n1, n2) = (n1 as f64, n2 as f64);
+    let mean = n1 * n2 / 2.0;
+
+    if ties == 0.0 && a.len() <= EXACT_MAX_SIZE && b.len() <= EXACT_MAX_SIZE {
+        return Some(exact_mann_whitney(a.len(), b.len(), u.min(n1 * n2 - u)));
+    }
+    let n = n1 + n2;
+    let variance = n1 * n2 / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        return Some(1.0);
+    }
+    let z = ((u - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+    Some(erfc(z / std::f64::consts::SQRT_2).min(1.0))
+}
+
+// P(U <= u) * 2 under the null hypothesis, counting the orderings of the two samples
+fn exact_mann_whitney(n1: usize, n2: usize, u: f64) -> f64 {
+    let max_u = n1 * n2;
+    // counts[j][k]: orderings of i values of a and j values of b with U = k
+    let mut counts = vec![vec![0.0_f64; max_u + 1]; n2 + 1];
+    for row in counts.iter_mut() {
+        row[0] = 1.0;
+    }
+    for _ in 1..=n1 {
+        let mut next = vec![vec![0.0_f64; max_u + 1]; n2 + 1];
+        next[0][0] = 1.0;
+        for j in 1..=n2 {
+            for k in 0..=max_u {
+                // The largest value is from b (U unchanged) or from a (ranked above j values of b)
+                next[j][k] = next[j - 1][k] + if k >= j { counts[j][k - j] } else { 0.0 };
+            }
+        }
+        counts = next;
+    }
+    let total: f64 = counts[n2].iter().sum();
+    let below: f64 = counts[n2][..=(u.floor() as usize).min(max_u)].iter().sum();
+    (2.0 * below / total).min(1.0)
+}
+
+/// Two-sided p-value of the z test that `x1` successes out of `n1` and `x2` out of `n2` come
+/// from the same proportion.
+pub fn two_proportions(x1: f64, n1: f64, x2: f64, n2: f64) -> Option<f64> {
+    if n1 <= 0.0 || n2 <= 0.0 {
+        return None;
+    }
+    let pooled = (x1 + x2) / (n1 + n2);
+    let se = (pooled * (1.0 - pooled) * (1.0 / n1 + 1.0 / n2)).sqrt();
+    if se <= 0.0 || se.is_nan() {
+        return Some(1.0);
+    }
+    let z = (x2 / n2 - x1 / n1).abs() / se;
+    Some(erfc(z / std::f64::consts::SQRT_2).min(1.0))
+}
+
+/// Normal approximation interval, at the given confidence, of the difference of the proportions
+/// `x2 / n2 - x1 / n1`.
+pub fn proportion_difference(
+    x1: f64,
+    n1: f64,
+    x2: f64,
+    n2: f64,
+    confidence: f64,
+) -> Option<(f64, f64)> {
+    if n1 <= 0.0 || n2 <= 0.0 {
+        return None;
+    }
+    let (p1, p2) = (x1 / n1, x2 / n2);
+    let se = (p1 * (1.0 - p1) / n1 + p2 * (1.0 - p2) / n2)
+        .max(0.0)
+        .sqrt();
+    let z = normal_quantile(1.0 - (1.0 - confidence) / 2.0);
+    Some((p2 - p1 - z * se, p2 - p1 + z * se))
+}
+
+// Complementary error function, fractional error below 1.2e-7 (Numerical Recipes erfcc)
+fn erfc(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.5 * x.abs());
+    let y = t
+        * (-x * x - 1.265_512_23
+            + t * (1.000_023_68
+                + t * (0.374_091_96
+                    + t * (0.096_784_18
+                        + t * (-0.186_288_06
+                            + t * (0.278_868_07
+                                + t * (-1.135_203_98
+                                    + t * (1.488_515_87
+                                        + t * (-0.822_152_23 + t * 0.170_872_77)))))))))
+            .exp();
+    if x >= 0.0 {
+        y
+    } else {
+        2.0 - y
+    }
+}
+
+// Quantile of the standard normal distribution, by bisection of its distribution function
+fn normal_quantile(p: f64) -> f64 {
+    let (mut low, mut high) = (-10.0, 10.0);
+    for _ in 0..100 {
+        let middle = (low + high) / 2.0;
+        if erfc(-middle / std::f64::consts::SQRT_2) / 2.0 < p {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    (low + high) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: f64, expected: f64, tolerance: f64) -> bool {
+        (actual - expected).abs() < tolerance
+    }
+
+    #[test]
+    fn exact_mann_whitney_counts_the_orderings() {
+        // 2 of the 20 orderings of 3 + 3 values have U <= 0 on either side
+        assert!(close(exact_mann_whitney(3, 3, 0.0), 0.1, 1e-12));
+        // U <= 1 for 2 orderings out of 20
+        assert!(close(exact_mann_whitney(3, 3, 1.0), 0.2, 1e-12));
+        // 1 ordering out of 70 on either side
+        assert!(close(exact_mann_whitney(4, 4, 0.0), 2.0 / 70.0, 1e-12));
+        assert_eq!(exact_mann_whitney(3, 3, 4.5), 1.0);
+    }
+
+    #[test]
+    fn mann_whitney_separated_samples() {
+        let p = mann_whitney(&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]).unwrap();
+        assert!(close(p, 0.1, 1e-12));
+        assert_eq!(p, mann_whitney(&[4.0, 5.0, 6.0], &[1.0, 2.0, 3.0]).unwrap());
+        assert_eq!(mann_whitney(&[], &[1.0]), None);
+    }
+
+    #[test]
+    fn mann_whitney_ties_use_average_ranks() {
+        // U = 2.5, with the variance corrected for a group of 3 and a group of 2 ties
+        let p = mann_whitney(&[1.0, 2.0, 2.0, 3.0], &[2.0, 3.0, 4.0, 5.0]).unwrap();
+        assert!(close(p, 0.136_658, 1e-5));
+        assert_eq!(mann_whitney(&[1.0, 1.0], &[1.0, 1.0]), Some(1.0));
+    }
+
+    #[test]
+    fn bootstrap_mean_difference_is_reproducible() {
+        let a: Vec<f64> = (0..50).map(|i| (i % 7) as f64).collect();
+        let b: Vec<f64> = a.iter().map(|value| value + 10.0).collect();
+        let (low, high) = bootstrap_mean_difference(&a, &b, 0.95).unwrap();
+        assert!(low < 10.0 && 10.0 < high);
+        assert_eq!(bootstrap_mean_difference(&a, &b, 0.95), Some((low, high)));
+        assert_eq!(
+            bootstrap_mean_difference(&[2.0; 10], &[5.0; 10], 0.95),
+            Some((3.0, 3.0))
+        );
+        assert_eq!(bootstrap_mean_difference(&[], &b, 0.95), None);
+    }
+
+    #[test]
+    fn two_proportions_z_test() {
+        // 30/100 against 45/100: z = 2.191
+        let p = two_proportions(30.0, 100.0, 45.0, 100.0).unwrap();
+        assert!(close(p, 0.028_460, 1e-5));
+        assert_eq!(two_proportions(0.0, 10.0, 0.0, 10.0), Some(1.0));
+        assert_eq!(two_proportions(1.0, 0.0, 1.0, 10.0), None);
+    }
+
+    #[test]
+    fn proportion_difference_interval() {
+        let (low, high) = proportion_difference(30.0, 100.0, 45.0, 100.0, 0.95).unwrap();
+        assert!(close(low, 0.017_430, 1e-5));
+        assert!(close(high, 0.282_570, 1e-5));
+    }
+
+    #[test]
+    fn erfc_known_values() {
+        assert!(close(erfc(0.0), 1.0, 1e-7));
+        assert!(close(erfc(1.0), 0.157_299_207, 1e-7));
+        assert!(close(erfc(-1.0), 1.842_700_793, 1e-7));
+        assert!(erfc(10.0) < 1e-20);
+    }
+
+    #[test]
+    fn normal_quantile_known_values() {
+        assert!(close(normal_quantile(0.975), 1.959_964, 1e-5));
+        assert!(close(normal_quantile(0.5), 0.0, 1e-7));
+        assert!(close(normal_quantile(0.025), -1.959_964, 1e-5));
+    }
+}
@@ -0,0 +1,206 @@
+use crate::stats::Summary;
+use std::fmt::Write;
+
+const WIDTH: f64 = 640.0;
+const HEIGHT: f64 = 260.0;
+const LEFT: f64 = 70.0;
+const RIGHT: f64 = 20.0;
+const TOP: f64 = 20.0;
+const BOTTOM: f64 = 50.0;
+const TICKS: f64 = 5.0;
+pub const SV1_COLOR: &str = "#f2a900";
+pub const SV2_COLOR: &str = "#3b7dd8";
+
+/// A group of the chart (a scenario), with its SV1 and SV2 values.
+pub struct Group<'a, T> {
+    pub label: &'a str,
+    pub sv1: Option<T>,
+    pub sv2: Option<T>,
+}
+
+/// Grouped bar chart of the SV1 and SV2 values of every group.
+pub fn bars(groups: &[Group<f64>], unit: &str) -> String {
+    let values = groups.iter().flat_map(|group| [group.sv1, group.sv2]);
+    let (low, high) = axis(values.flatten());
+    let mut svg = chart(groups, unit, low, high);
+    let slot = slot(groups.len());
+    for (index, group) in groups.iter().enumerate() {
+        for (side, value, color) in [(0.0, group.sv1, SV1_COLOR), (1.0, group.sv2, SV2_COLOR)] {
+            let Some(value) = value else {
+                continue;
+            };
+            let x = LEFT + slot * index as f64 + slot * (0.2 + 0.3 * side);
+            let top = y(value.max(low), low, high);
+            let bottom = y(0.0_f64.max(low), low, high);
+            let _ = write!(
+                svg,
+                r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}"><title>{}</title></rect>"#,
+                x,
+                top.min(bottom),
+                slot * 0.3,
+                (bottom - top).abs(),
+                color,
+                number(value)
+            );
+        }
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Box plots of the SV1 and SV2 events of every group: the box spans the quartiles, the
+/// whiskers the minimum and the maximum.
+pub fn boxes(groups: &[Group<&Summary>], unit: &str) -> String {
+    let values = groups
+        .iter()
+        .flat_map(|group| [group.sv1, group.sv2])
+        .flatten()
+        .flat_map(|summary| [summary.min, summary.max]);
+    let (low, high) = axis(values);
+    let mut svg = chart(groups, unit, low, high);
+    let slot = slot(groups.len());
+    for (index, group) in groups.iter().enumerate() {
+        for (side, summary, color) in [(0.0, group.sv1, SV1_COLOR), (1.0, group.sv2, SV2_COLOR)] {
+            let Some(summary) = summary else {
+                continue;
+            };
+            let x = LEFT + slot * index as f64 + slot * (0.2 + 0.3 * side);
+            let width = slot * 0.3;
+            let center = x + width / 2.0;
+            let _ = write!(
+                svg,
+                r#"<g><title>median {}, n={}</title><line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke="{}"/><rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}" fill-opacity="0.6" stroke="{}"/><line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke="black" stroke-width="2"/></g>"#,
+                number(summary.median),
+                summary.count,
+                center,
+                y(summary.max, low, high),
+                center,
+                y(summary.min, low, high),
+                color,
+                x + width * 0.1,
+                y(summary.p75, low, high),
+                width * 0.8,
+                y(summary.p25, low, high) - y(summary.p75, low, high),
+                color,
+                color,
+                x + width * 0.1,
+                y(summary.median, low, high),
+                x + width * 0.9,
+                y(summary.median, low, high),
+            );
+        }
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+// The frame, the axis and the legend of a chart
+fn chart<T>(groups: &[Group<T>], unit: &str, low: f64, high: f64) -> String {
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}" font-family="sans-serif" font-size="11">"#,
+        WIDTH, HEIGHT, WIDTH, HEIGHT
+    );
+    let step = (high - low) / TICKS;
+    for tick in 0..=TICKS as usize {
+        let value = low + step * tick as f64;
+        let tick_y = y(value, low, high);
+        let _ = write!(
+            svg,
+            r##"<line x1="{}" y1="{:.1}" x2="{}" y2="{:.1}" stroke="#ddd"/><text x="{}" y="{:.1}" text-anchor="end">{}</text>"##,
+            LEFT,
+            tick_y,
+            WIDTH - RIGHT,
+            tick_y,
+            LEFT - 5.0,
+            tick_y + 4.0,
+            number(value)
+        );
+    }
+    let _ = write!(
+        svg,
+        r#"<text x="12" y="{:.1}" transform="rotate(-90 12 {:.1})" text-anchor="middle">{}</text>"#,
+        TOP + (HEIGHT - TOP - BOTTOM) / 2.0,
+        TOP + (HEIGHT - TOP - BOTTOM) / 2.0,
+        escape(unit)
+    );
+    let slot = slot(groups.len());
+    for (index, group) in groups.iter().enumerate() {
+        let _ = write!(
+            svg,
+            r#"<text x="{:.1}" y="{:.1}" text-anchor="middle">{}</text>"#,
+            LEFT + slot * (index as f64 + 0.5),
+            HEIGHT - BOTTOM + 16.0,
+            escape(group.label)
+        );
+    }
+    for (index, (label, color)) in [("SV1", SV1_COLOR), ("SV2", SV2_COLOR)].iter().enumerate() {
+        let x = LEFT + 60.0 * index as f64;
+        let _ = write!(
+            svg,
+            r#"<rect x="{:.1}" y="{:.1}" width="10" height="10" fill="{}"/><text x="{:.1}" y="{:.1}">{}</text>"#,
+            x,
+            HEIGHT - 18.0,
+            color,
+            x + 14.0,
+            HEIGHT - 9.0,
+            label
+        );
+    }
+    svg
+}
+
+fn slot(groups: usize) -> f64 {
+    (WIDTH - LEFT - RIGHT) / groups.max(1) as f64
+}
+
+fn y(value: f64, low: f64, high: f64) -> f64 {
+    TOP + (HEIGHT - TOP - BOTTOM) * (1.0 - (value - low) / (high - low))
+}
+
+// Bounds of the axis: from zero (or below it) to a round value above the maximum
+fn axis(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (min, max) = values.fold((0.0_f64, 0.0_f64), |(min, max), value| {
+        (min.min(value), max.max(value))
+    });
+    let step = nice((max - min) / TICKS);
+    let low = (min / step).floor() * step;
+    (low, low + nice((max - low) / TICKS) * TICKS)
+}
+
+// Rounds up to 1, 2 or 5 times a power of ten
+fn nice(value: f64) -> f64 {
+    if value <= 0.0 {
+        return 1.0;
+    }
+    let magnitude = 10_f64.powf(value.log10().floor());
+    let fraction = value / magnitude;
+    let nice = if fraction <= 1.0 {
+        1.0
+    } else if fraction <= 2.0 {
+        2.0
+    } else if fraction <= 5.0 {
+        5.0
+    } else {
+        10.0
+    };
+    nice * magnitude
+}
+
+/// Formats a value with a precision depending on its magnitude.
+pub fn number(value: f64) -> String {
+    let magnitude = value.abs();
+    if magnitude >= 1000.0 || value.fract() == 0.0 {
+        format!("{:.0}", value)
+    } else if magnitude >= 10.0 {
+        format!("{:.1}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,239 @@
+use report::{Observed, Report};
+use session::{Direction, Message, Session};
+use std::env;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::sleep_until;
+
+mod report;
+mod session;
+mod sv1;
+mod sv2;
+
+// Replays one side of a connection recorded by the custom proxies (see `CAPTURE_DIR`) against a
+// live pool or miner, and reports where the live peer diverged from the capture.
+//
+// REPLAY_FILE        comma-separated capture files, in order
+// REPLAY_ROLE        `miner` to connect to REPLAY_ADDRESS and replay the miner side, `pool` to
+//                    listen on REPLAY_ADDRESS and replay the pool side
+// REPLAY_SPEED       multiplier of the recorded pace, 0 sends without waiting (default 1)
+// REPLAY_CONNECTION  connection id to replay (default: the first one in the capture)
+// REPLAY_TAIL_MS     how long to keep waiting for the peer's messages (default 5000)
+// REPLAY_REPORT      path of the JSON report (default: only logged)
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(
+        env_logger::Env::default()
+            .default_filter_or("info")
+            .default_write_style_or("always"),
+    )
+    .init();
+
+    let files: Vec<String> = env::var("REPLAY_FILE")
+        .expect("REPLAY_FILE environment variable not set")
+        .split(',')
+        .map(|file| file.trim().to_string())
+        .collect();
+    let role = env::var("REPLAY_ROLE").expect("REPLAY_ROLE environment variable not set");
+    let address = env::var("REPLAY_ADDRESS").expect("REPLAY_ADDRESS environment variable not set");
+    let speed: f64 = env::var("REPLAY_SPEED")
+        .map(|v| v.parse().expect("REPLAY_SPEED is not a number"))
+        .unwrap_or(1.0);
+    let connection = env::var("REPLAY_CONNECTION")
+        .ok()
+        .map(|v| v.parse().expect("REPLAY_CONNECTION is not a number"));
+    let tail = Duration::from_millis(
+        env::var("REPLAY_TAIL_MS")
+            .map(|v| v.parse().expect("REPLAY_TAIL_MS is not a number"))
+            .unwrap_or(5000),
+    );
+    let report_path = env::var("REPLAY_REPORT").ok();
+
+    // The replayed side sends what it sent in the capture and expects what it received
+    let sent_direction = match role.as_str() {
+        "miner" => Direction::Upstream,
+        "pool" => Direction::Downstream,
+        _ => panic!("REPLAY_ROLE must be `miner` or `pool`"),
+    };
+
+    let session = session::load(&files, connection)?;
+    log::info!(
+        "Loaded {} messages of connection {}",
+        session.entries.len(),
+        session.connection
+    );
+
+    let stream = if sent_direction == Direction::Upstream {
+        log::info!("Connecting to {}", address);
+        TcpStream::connect(&address).await?
+    } else {
+        let listener = TcpListener::bind(&address).await?;
+        log::info!("Waiting for a miner on {}", address);
+        listener.accept().await?.0
+    };
+
+    let protocol = if session.is_sv2() { "sv2" } else { "sv1" };
+    let (outgoing, incoming, start) = if session.is_sv2() {
+        let connection = if sent_direction == Direction::Upstream {
+            sv2::connect(stream).await
+        } else {
+            sv2::accept(stream).await
+        };
+        let start = Instant::now();
+        let (outgoing, incoming) = sv2::spawn(connection, session.sv2_names.clone(), start);
+        (outgoing, incoming, start)
+    } else {
+        let start = Instant::now();
+        let (outgoing, incoming) = sv1::spawn(stream, start);
+        (outgoing, incoming, start)
+    };
+
+    let (sent, expected, received) = replay(
+        &session,
+        sent_direction,
+        speed,
+        tail,
+        start,
+        outgoing,
+        incoming,
+    )
+    .await;
+
+    let report = Report::compare(
+        protocol,
+        &role,
+        session.connection,
+        speed,
+        sent,
+        expected,
+        received,
+    );
+    report.log_summary();
+    if let Some(path) = report_path {
+        std::fs::write(&path, serde_json::to_string_pretty(&report)?)?;
+        log::info!("Report written to {}", path);
+    }
+    if !report.divergences.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn observe(session: &Session, message: &Message) -> (String, Option<String>) {
+    match message {
+        Message::Sv1(value) => sv1::observe(value),
+        Message::Sv1Raw(_) => ("raw".to_string(), None),
+        Message::Sv2 { message_type, .. } => (sv2::name(&session.sv2_names, *message_type), None),
+    }
+}
+
+// Sends every message of `sent_direction` at its recorded offset, scaled by `speed`. A message
+// is held back until the peer has sent as many messages as it had at that point of the capture,
+// so a slower peer is given the chance to catch up before being answered.
+async fn replay(
+    session: &Session,
+    sent_direction: Direction,
+    speed: f64,
+    tail: Duration,
+    start: Instant,
+    outgoing: Sender<Message>,
+    mut incoming: Receiver<Observed>,
+) -> (usize, Vec<Observed>, Vec<Observed>) {
+    let first_us = session
+        .entries
+        .iter()
+        .map(|entry| entry.monotonic_us)
+        .min()
+        .unwrap_or_default();
+    let offset = |monotonic_us: u64| {
+        if speed > 0.0 {
+            let elapsed_us = monotonic_us.saturating_sub(first_us);
+            Duration::from_secs_f64(elapsed_us as f64 / 1_000_000.0 / speed)
+        } else {
+            Duration::ZERO
+        }
+    };
+
+    let mut sent = 0;
+    let mut expected = Vec::new();
+    let mut received = Vec::new();
+    let mut peer_closed = false;
+
+    for entry in &session.entries {
+        let due = offset(entry.monotonic_us);
+        if entry.direction != sent_direction {
+            let (key, outcome) = observe(session, &entry.message);
+            expected.push(Observed {
+                offset_ms: due.as_secs_f64() * 1000.0,
+                key,
+                outcome,
+            });
+            continue;
+        }
+        if peer_closed {
+            continue;
+        }
+
+        let due = start + due;
+        let deadline = due + tail;
+        loop {
+            let caught_up = received.len() >= expected.len();
+            let now = Instant::now();
+            if caught_up && now >= due {
+                break;
+            }
+            if now >= deadline {
+                log::warn!(
+                    "Peer sent {} of the {} messages expected so far, replaying anyway",
+                    received.len(),
+                    expected.len()
+                );
+                break;
+            }
+            let wake = if caught_up { due } else { deadline };
+            tokio::select! {
+                observed = incoming.recv() => match observed {
+                    Some(observed) => received.push(observed),
+                    None => {
+                        log::warn!("Peer closed the connection");
+                        peer_closed = true;
+                        break;
+                    }
+                },
+                _ = sleep_until(wake.into()) => {}
+            }
+        }
+        if peer_closed {
+            continue;
+        }
+
+        if outgoing.send(entry.message.clone()).await.is_err() {
+            log::warn!("Connection closed while replaying");
+            peer_closed = true;
+            continue;
+        }
+        sent += 1;
+    }
+
+    // Wait for the messages the peer still has to send
+    if !peer_closed {
+        let last_expected = expected
+            .last()
+            .map(|observed| Duration::from_secs_f64(observed.offset_ms / 1000.0))
+            .unwrap_or_default();
+        let end = Instant::now().max(start + last_expected) + tail;
+        loop {
+            tokio::select! {
+                observed = incoming.recv() => match observed {
+                    Some(observed) => received.push(observed),
+                    None => break,
+                },
+                _ = sleep_until(end.into()) => break,
+            }
+        }
+    }
+    drop(outgoing);
+
+    (sent, expected, received)
+}
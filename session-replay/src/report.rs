@@ -0,0 +1,157 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+
+/// A message received from the live peer, or recorded in the capture and expected from it.
+pub struct Observed {
+    // Milliseconds since the first message of the replay
+    pub offset_ms: f64,
+    // What expected and received messages are matched on: the SV1 method or response id, the
+    // SV2 message name
+    pub key: String,
+    // Whether an SV1 response is a success or an error
+    pub outcome: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Divergence {
+    pub kind: &'static str,
+    pub message: String,
+    pub expected_offset_ms: Option<f64>,
+    pub actual_offset_ms: Option<f64>,
+    pub detail: String,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub protocol: &'static str,
+    pub role: String,
+    pub connection: u64,
+    pub speed: f64,
+    pub sent: usize,
+    pub expected: usize,
+    pub received: usize,
+    pub matched: usize,
+    // How much later (or earlier, when negative) than in the capture the matched messages
+    // arrived, in milliseconds
+    pub average_lag_ms: f64,
+    pub max_lag_ms: f64,
+    pub divergences: Vec<Divergence>,
+}
+
+impl Report {
+    /// Matches every expected message with the first received message with the same key,
+    /// regardless of the order they arrived in.
+    pub fn compare(
+        protocol: &'static str,
+        role: &str,
+        connection: u64,
+        speed: f64,
+        sent: usize,
+        expected: Vec<Observed>,
+        received: Vec<Observed>,
+    ) -> Self {
+        let mut by_key: HashMap<&str, VecDeque<usize>> = HashMap::new();
+        for (index, observed) in received.iter().enumerate() {
+            by_key.entry(&observed.key).or_default().push_back(index);
+        }
+
+        let mut matched_received = vec![false; received.len()];
+        let mut divergences = Vec::new();
+        let mut lags = Vec::new();
+        for expected in &expected {
+            let Some(index) = by_key
+                .get_mut(expected.key.as_str())
+                .and_then(|indexes| indexes.pop_front())
+            else {
+                divergences.push(Divergence {
+                    kind: "missing",
+                    message: expected.key.clone(),
+                    expected_offset_ms: Some(expected.offset_ms),
+                    actual_offset_ms: None,
+                    detail: "expected message never received".to_string(),
+                });
+                continue;
+            };
+            matched_received[index] = true;
+            let actual = &received[index];
+            lags.push(actual.offset_ms - expected.offset_ms);
+            if expected.outcome != actual.outcome {
+                divergences.push(Divergence {
+                    kind: "outcome",
+                    message: expected.key.clone(),
+                    expected_offset_ms: Some(expected.offset_ms),
+                    actual_offset_ms: Some(actual.offset_ms),
+                    detail: format!(
+                        "expected {}, got {}",
+                        expected.outcome.as_deref().unwrap_or("-"),
+                        actual.outcome.as_deref().unwrap_or("-")
+                    ),
+                });
+            }
+        }
+        for (actual, _) in received
+            .iter()
+            .zip(matched_received)
+            .filter(|(_, matched)| !matched)
+        {
+            divergences.push(Divergence {
+                kind: "unexpected",
+                message: actual.key.clone(),
+                expected_offset_ms: None,
+                actual_offset_ms: Some(actual.offset_ms),
+                detail: "received message not in the capture".to_string(),
+            });
+        }
+
+        Self {
+            protocol,
+            role: role.to_string(),
+            connection,
+            speed,
+            sent,
+            expected: expected.len(),
+            received: received.len(),
+            matched: lags.len(),
+            average_lag_ms: if lags.is_empty() {
+                0.0
+            } else {
+                lags.iter().sum::<f64>() / lags.len() as f64
+            },
+            max_lag_ms: lags.iter().cloned().fold(0.0, f64::max),
+            divergences,
+        }
+    }
+
+    pub fn log_summary(&self) {
+        log::info!(
+            "Replayed connection {} of a {} capture as {}: sent {}, expected {}, received {}, matched {}",
+            self.connection,
+            self.protocol,
+            self.role,
+            self.sent,
+            self.expected,
+            self.received,
+            self.matched
+        );
+        log::info!(
+            "Lag compared to the capture: average {:.1} ms, max {:.1} ms",
+            self.average_lag_ms,
+            self.max_lag_ms
+        );
+        for divergence in &self.divergences {
+            log::warn!(
+                "{} {} (expected at {:?} ms, received at {:?} ms): {}",
+                divergence.kind,
+                divergence.message,
+                divergence.expected_offset_ms,
+                divergence.actual_offset_ms,
+                divergence.detail
+            );
+        }
+        if self.divergences.is_empty() {
+            log::info!("No divergences found");
+        } else {
+            log::warn!("{} divergences found", self.divergences.len());
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Direction {
+    // Sent by the miner (downstream) to the pool (upstream)
+    Upstream,
+    // Sent by the pool (upstream) to the miner (downstream)
+    Downstream,
+}
+
+#[derive(Clone)]
+pub enum Message {
+    Sv1(Value),
+    // SV1 lines that weren't valid JSON when recorded
+    Sv1Raw(String),
+    Sv2 {
+        extension_type: u16,
+        message_type: u8,
+        payload: Vec<u8>,
+    },
+}
+
+pub struct Entry {
+    pub direction: Direction,
+    pub monotonic_us: u64,
+    pub message: Message,
+}
+
+pub struct Session {
+    pub connection: u64,
+    pub entries: Vec<Entry>,
+    // Message names found in the SV2 capture, to name the frames received during the replay
+    pub sv2_names: HashMap<u8, String>,
+}
+
+impl Session {
+    pub fn is_sv2(&self) -> bool {
+        matches!(
+            self.entries.first().map(|entry| &entry.message),
+            Some(Message::Sv2 { .. })
+        )
+    }
+}
+
+/// Loads the entries of a single connection from capture files written by the custom proxies.
+/// Rotated files have to be given in order. When `connection` is `None`, the connection of the
+/// first entry is replayed.
+pub fn load(paths: &[String], connection: Option<u64>) -> Result<Session, Box<dyn Error>> {
+    let mut connection = connection;
+    let mut entries = Vec::new();
+    let mut sv2_names = HashMap::new();
+
+    for path in paths {
+        let reader = BufReader::new(File::open(path)?);
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let json: Value = serde_json::from_str(&line)
+                .map_err(|e| format!("{}:{}: {}", path, number + 1, e))?;
+            let entry_connection = json["connection"].as_u64().unwrap_or_default();
+            if *connection.get_or_insert(entry_connection) != entry_connection {
+                continue;
+            }
+            let direction = match json["direction"].as_str() {
+                Some("upstream") => Direction::Upstream,
+                Some("downstream") => Direction::Downstream,
+                _ => return Err(format!("{}:{}: invalid direction", path, number + 1).into()),
+            };
+            let message = if let Some(message_type) = json["message_type"].as_u64() {
+                let message_type = message_type as u8;
+                if let Some(name) = json["message_name"].as_str() {
+                    sv2_names.insert(message_type, name.to_string());
+                }
+                Message::Sv2 {
+                    extension_type: json["extension_type"].as_u64().unwrap_or_default() as u16,
+                    message_type,
+                    payload: hex::decode(json["payload"].as_str().unwrap_or_default())?,
+                }
+            } else if let Some(raw) = json["raw"].as_str() {
+                Message::Sv1Raw(raw.to_string())
+            } else {
+                Message::Sv1(json["message"].clone())
+            };
+            entries.push(Entry {
+                direction,
+                monotonic_us: json["monotonic_us"].as_u64().unwrap_or_default(),
+                message,
+            });
+        }
+    }
+
+    // The files may be given in any order, entries of the same instant keep theirs
+    entries.sort_by_key(|entry| entry.monotonic_us);
+
+    Ok(Session {
+        connection: connection.ok_or("No entries found in the capture")?,
+        entries,
+        sv2_names,
+    })
+}
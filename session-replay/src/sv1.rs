@@ -0,0 +1,75 @@
+use crate::report::Observed;
+use crate::session::Message;
+use serde_json::Value;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+/// Key an SV1 message is matched on: the method of requests and notifications, the id of
+/// responses. Responses also carry whether they succeeded.
+pub fn observe(message: &Value) -> (String, Option<String>) {
+    if let Some(method) = message["method"].as_str() {
+        return (method.to_string(), None);
+    }
+    let outcome = if !message["error"].is_null() || message["result"] == Value::Bool(false) {
+        "error"
+    } else {
+        "ok"
+    };
+    (
+        format!("response:{}", message["id"]),
+        Some(outcome.to_string()),
+    )
+}
+
+/// Forwards the messages to replay as JSON lines and parses every line received from the peer.
+pub fn spawn(stream: TcpStream, start: Instant) -> (Sender<Message>, Receiver<Observed>) {
+    let (reader, mut writer) = stream.into_split();
+    let (outgoing, mut to_send) = channel::<Message>(10);
+    let (received, incoming) = channel(10);
+
+    tokio::spawn(async move {
+        while let Some(message) = to_send.recv().await {
+            let line = match message {
+                Message::Sv1(value) => format!("{}\n", value),
+                // Recorded together with their line terminator
+                Message::Sv1Raw(raw) => raw,
+                Message::Sv2 { .. } => continue,
+            };
+            if let Err(e) = writer.write_all(line.as_bytes()).await {
+                log::error!("Failed to send to peer: {}", e);
+                break;
+            }
+        }
+        let _ = writer.shutdown().await;
+    });
+
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(reader).lines();
+        loop {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    log::error!("Failed to read from peer: {}", e);
+                    break;
+                }
+            };
+            let (key, outcome) = match serde_json::from_str::<Value>(&line) {
+                Ok(message) => observe(&message),
+                Err(_) => ("raw".to_string(), None),
+            };
+            let observed = Observed {
+                offset_ms: start.elapsed().as_secs_f64() * 1000.0,
+                key,
+                outcome,
+            };
+            if received.send(observed).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    (outgoing, incoming)
+}
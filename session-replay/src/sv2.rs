@@ -0,0 +1,116 @@
+use crate::report::Observed;
+use crate::session::Message;
+use bench_common::keys;
+use codec_sv2::buffer_sv2::Slice;
+use codec_sv2::{HandshakeRole, Initiator, Responder};
+use demand_easy_sv2::{Frame_, PoolMessages, StdFrame};
+use demand_sv2_connection::noise_connection_tokio::Connection;
+use key_utils::{Secp256k1PublicKey, Secp256k1SecretKey};
+use std::collections::HashMap;
+use std::time::Instant;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+/// Name of an SV2 message type, as recorded in the capture.
+pub fn name(names: &HashMap<u8, String>, message_type: u8) -> String {
+    names
+        .get(&message_type)
+        .cloned()
+        .unwrap_or_else(|| format!("0x{:02x}", message_type))
+}
+
+/// Completes the noise handshake with the pool, as a miner.
+pub async fn connect(stream: TcpStream) -> (Receiver<Frame_>, Sender<Frame_>) {
+    let initiator = Initiator::without_pk().expect("This fn call can not fail");
+    let (receiver, sender, _, _) = Connection::new::<'static, PoolMessages<'static>>(
+        stream,
+        HandshakeRole::Initiator(initiator),
+    )
+    .await
+    .expect("Impossible to complete handshake with upstream");
+    (receiver, sender)
+}
+
+/// Completes the noise handshake with the miner, as a pool.
+pub async fn accept(stream: TcpStream) -> (Receiver<Frame_>, Sender<Frame_>) {
+    let public: Secp256k1PublicKey = keys::PUB_KEY.parse().expect("Invalid proxy pub key");
+    let secret: Secp256k1SecretKey = keys::SEC_KEY.parse().expect("Invalid proxy sec key");
+    let responder = Responder::from_authority_kp(
+        &public.into_bytes(),
+        &secret.into_bytes(),
+        keys::CERT_VALIDITY,
+    )
+    .expect("invalid key pair");
+    let (receiver, sender, _, _) = Connection::new::<'static, PoolMessages<'static>>(
+        stream,
+        HandshakeRole::Responder(responder),
+    )
+    .await
+    .expect("Impossible to complete handshake with downstream");
+    (receiver, sender)
+}
+
+// Rebuilds the frame exactly as it was recorded, without decoding the payload
+fn frame(extension_type: u16, message_type: u8, payload: Vec<u8>) -> Option<Frame_> {
+    let length = payload.len() as u32;
+    let mut bytes = Vec::with_capacity(6 + payload.len());
+    bytes.extend_from_slice(&extension_type.to_le_bytes());
+    bytes.push(message_type);
+    bytes.extend_from_slice(&length.to_le_bytes()[..3]);
+    bytes.extend_from_slice(&payload);
+    let frame = StdFrame::from_bytes(Slice::from(bytes)).ok()?;
+    Some(frame.into())
+}
+
+/// Forwards the recorded frames to replay and names every frame received from the peer.
+pub fn spawn(
+    connection: (Receiver<Frame_>, Sender<Frame_>),
+    names: HashMap<u8, String>,
+    start: Instant,
+) -> (Sender<Message>, Receiver<Observed>) {
+    let (mut receiver, sender) = connection;
+    let (outgoing, mut to_send) = channel::<Message>(10);
+    let (received, incoming) = channel(10);
+
+    tokio::spawn(async move {
+        while let Some(message) = to_send.recv().await {
+            let Message::Sv2 {
+                extension_type,
+                message_type,
+                payload,
+            } = message
+            else {
+                continue;
+            };
+            let Some(frame) = frame(extension_type, message_type, payload) else {
+                log::error!("Invalid recorded frame of type 0x{:02x}", message_type);
+                continue;
+            };
+            if sender.send(frame).await.is_err() {
+                log::error!("Failed to send to peer: connection closed");
+                break;
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        while let Some(frame) = receiver.recv().await {
+            let Frame_::Sv2(frame) = frame else {
+                continue;
+            };
+            let Some(header) = frame.get_header() else {
+                continue;
+            };
+            let observed = Observed {
+                offset_ms: start.elapsed().as_secs_f64() * 1000.0,
+                key: name(&names, header.msg_type()),
+                outcome: None,
+            };
+            if received.send(observed).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    (outgoing, incoming)
+}
@@ -0,0 +1,169 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::time::{sleep, Duration};
+use warp::Filter;
+
+const DEFAULT_MAX_SHARES: usize = 100_000;
+const DEFAULT_MAX_AGE_S: u64 = 3600;
+
+// The block can reach the node (or the Template Provider) before the share is stored by the
+// other proxy
+const LOOKUP_RETRIES: usize = 3;
+const LOOKUP_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// The fields of a block header that identify the share it was found with.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct HeaderFields {
+    // Previous block hash, in the usual reversed hex
+    pub prev_hash: String,
+    pub nonce: u32,
+    pub ntime: u32,
+    pub version: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Share {
+    pub job_id: String,
+    #[serde(flatten)]
+    pub header: HeaderFields,
+    // Milliseconds since the epoch, when the share went through the proxy
+    pub timestamp: f64,
+}
+
+/// Shares submitted in the last `SHARE_STORE_MAX_AGE_S` seconds (at most `SHARE_STORE_MAX`), so
+/// that the proxy in front of the node or of the Template Provider can find the share a block
+/// was found with.
+#[derive(Clone)]
+pub struct ShareStore {
+    shares: Arc<Mutex<VecDeque<Share>>>,
+    max_shares: usize,
+    max_age_ms: f64,
+}
+
+impl ShareStore {
+    pub fn from_env() -> Self {
+        let max_shares = env::var("SHARE_STORE_MAX")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_SHARES)
+            .max(1);
+        let max_age_s = env::var("SHARE_STORE_MAX_AGE_S")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_AGE_S);
+        Self {
+            shares: Arc::new(Mutex::new(VecDeque::new())),
+            max_shares,
+            max_age_ms: (max_age_s * 1000) as f64,
+        }
+    }
+
+    pub fn insert(&self, job_id: String, header: HeaderFields) {
+        let timestamp = now();
+        let mut shares = self.shares.lock().unwrap();
+        while shares.len() >= self.max_shares
+            || shares
+                .front()
+                .is_some_and(|share| timestamp - share.timestamp > self.max_age_ms)
+        {
+            shares.pop_front();
+        }
+        shares.push_back(Share {
+            job_id,
+            header,
+            timestamp,
+        });
+    }
+
+    /// Returns the shares matching the header, the most recent first.
+    pub fn find(&self, header: &HeaderFields) -> Vec<Share> {
+        self.shares
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|share| share.header == *header)
+            .cloned()
+            .collect()
+    }
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as f64
+}
+
+/// Decodes the header fields of a serialized block, as hex sent to `submitblock`.
+pub fn decode_header(block: &str) -> Option<HeaderFields> {
+    let header = hex::decode(block.get(..160)?).ok()?;
+    let field = |offset: usize| {
+        u32::from_le_bytes(
+            header[offset..offset + 4]
+                .try_into()
+                .expect("Header field is 4 bytes"),
+        )
+    };
+    Some(HeaderFields {
+        prev_hash: display_hash(&header[4..36]),
+        version: field(0),
+        ntime: field(68),
+        nonce: field(76),
+    })
+}
+
+/// A hash in internal byte order (as in a header or an SV2 message), in the usual reversed hex.
+pub fn display_hash(hash: &[u8]) -> String {
+    let mut hash = hash.to_vec();
+    hash.reverse();
+    hex::encode(hash)
+}
+
+/// `GET /shares?prev_hash=<hex>&nonce=<n>&ntime=<n>&version=<n>` returns the stored shares
+/// matching the header.
+pub fn routes(
+    store: ShareStore,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("shares")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HeaderFields>())
+        .map(move |header: HeaderFields| warp::reply::json(&store.find(&header)))
+}
+
+/// Looks the share a block was found with up in the store of the proxy at `url`. A header
+/// matching several shares can't tell which one found the block, so it matches none.
+pub async fn lookup(client: &Client, url: &str, header: &HeaderFields) -> Option<Share> {
+    for attempt in 0..LOOKUP_RETRIES {
+        if attempt > 0 {
+            sleep(LOOKUP_RETRY_DELAY).await;
+        }
+        let mut shares: Vec<Share> = match client.get(url).query(header).send().await {
+            Ok(response) => match response.json().await {
+                Ok(shares) => shares,
+                Err(e) => {
+                    log::error!("Invalid share store response: {}", e);
+                    return None;
+                }
+            },
+            Err(e) => {
+                log::error!("Failed to query the share store: {}", e);
+                return None;
+            }
+        };
+        match shares.len() {
+            0 => continue,
+            1 => return shares.pop(),
+            matches => {
+                log::warn!("{} shares match {:?}, none is used", matches, header);
+                return None;
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,37 @@
+use bench_common::rpc_auth::RpcCredentials;
+use prometheus::{register_counter_vec, CounterVec};
+
+/// Credentials the node-pool proxy adds to the calls forwarded to the node at `SERVER`, when the
+/// SV1 pool doesn't authenticate itself, see [`RpcCredentials`].
+pub struct RpcAuth {
+    credentials: RpcCredentials,
+    failures: CounterVec,
+}
+
+impl RpcAuth {
+    pub fn from_env(server: &str) -> Self {
+        Self {
+            credentials: RpcCredentials::from_env(server),
+            failures: register_counter_vec!(
+                "sv1_rpc_auth_failures",
+                "Total number of calls of the SV1 pool refused by the node for bad credentials (HTTP 401)",
+                &["credentials"]
+            )
+            .unwrap(),
+        }
+    }
+
+    /// Returns the `Authorization` header value to add, with where it comes from.
+    pub fn authorization(&self) -> Option<(String, &'static str)> {
+        self.credentials.authorization()
+    }
+
+    /// Accounts a call refused with HTTP 401, `credentials` tells where they came from.
+    pub fn unauthorized(&self, credentials: &str) {
+        log::error!(
+            "The node refused the {} credentials (HTTP 401), check the RPC authentication of the proxy",
+            credentials
+        );
+        self.failures.with_label_values(&[credentials]).inc();
+    }
+}
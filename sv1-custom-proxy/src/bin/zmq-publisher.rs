@@ -0,0 +1,104 @@
+//! Stands in for the ZMQ notifications of bitcoind: publishes a made up block on `hashblock` and
+//! `rawblock` every `ZMQ_INTERVAL_S` seconds to the subscribers connected to `ZMQ_ADDRESS`.
+//! Subscriptions are not filtered, every subscriber gets both topics.
+
+// Shared with the proxy, which uses the subscriber side
+#[allow(dead_code)]
+#[path = "../zmq.rs"]
+mod zmq;
+
+use bench_common::hash::sha256d;
+use rand::Rng;
+use std::env;
+use std::time::SystemTime;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio::time::{interval, Duration};
+
+const DEFAULT_INTERVAL_S: u64 = 30;
+
+type Message = Vec<Vec<u8>>;
+
+// A header on top of `prev_hash` (internal byte order) and a block without transactions
+fn block(prev_hash: &[u8; 32]) -> ([u8; 32], Vec<u8>) {
+    let mut rng = rand::thread_rng();
+    let mut header = Vec::with_capacity(81);
+    header.extend_from_slice(&0x2000_0000u32.to_le_bytes());
+    header.extend_from_slice(prev_hash);
+    header.extend_from_slice(&rng.gen::<[u8; 32]>());
+    let time = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs() as u32;
+    header.extend_from_slice(&time.to_le_bytes());
+    header.extend_from_slice(&0x207f_ffffu32.to_le_bytes());
+    header.extend_from_slice(&rng.gen::<u32>().to_le_bytes());
+    let hash = sha256d(&header);
+    header.push(0);
+    (hash, header)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(
+        env_logger::Env::default()
+            .default_filter_or("info")
+            .default_write_style_or("always"),
+    )
+    .init();
+
+    let address = env::var("ZMQ_ADDRESS").expect("ZMQ_ADDRESS environment variable not set");
+    let interval_s = env::var("ZMQ_INTERVAL_S")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_INTERVAL_S)
+        .max(1);
+
+    let (sender, _) = broadcast::channel::<Message>(16);
+    let listener = TcpListener::bind(zmq::address(&address)).await?;
+    log::info!("Publishing a block every {}s on {}", interval_s, address);
+
+    let publisher = sender.clone();
+    tokio::spawn(async move {
+        let mut ticks = interval(Duration::from_secs(interval_s));
+        // The first tick completes immediately
+        ticks.tick().await;
+        let mut prev_hash = [0u8; 32];
+        let mut sequence = 0u32;
+        loop {
+            ticks.tick().await;
+            let (hash, raw_block) = block(&prev_hash);
+            prev_hash = hash;
+            let mut display_hash = hash;
+            display_hash.reverse();
+            log::info!("Publishing block {}", hex::encode(display_hash));
+            let sequence_bytes = sequence.to_le_bytes().to_vec();
+            let _ = publisher.send(vec![
+                b"hashblock".to_vec(),
+                display_hash.to_vec(),
+                sequence_bytes.clone(),
+            ]);
+            let _ = publisher.send(vec![b"rawblock".to_vec(), raw_block, sequence_bytes]);
+            sequence = sequence.wrapping_add(1);
+        }
+    });
+
+    loop {
+        let (mut stream, peer) = listener.accept().await?;
+        let mut messages = sender.subscribe();
+        tokio::spawn(async move {
+            if let Err(e) = zmq::handshake(&mut stream, "PUB").await {
+                log::error!("Handshake with {} failed: {}", peer, e);
+                return;
+            }
+            log::info!("Subscriber {} connected", peer);
+            while let Ok(message) = messages.recv().await {
+                let parts: Vec<&[u8]> = message.iter().map(Vec::as_slice).collect();
+                if let Err(e) = zmq::send(&mut stream, &parts).await {
+                    log::info!("Subscriber {} disconnected: {}", peer, e);
+                    return;
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,390 @@
+use prometheus::{
+    register_counter, register_counter_vec, register_gauge, Counter, CounterVec, Gauge,
+};
+use serde_json::Value;
+use share_store::HeaderFields;
+use std::env;
+use std::time::Duration;
+
+const INITIAL_SUBSIDY: u64 = 50 * 100_000_000;
+// The proxy between the miners and the SV1 pool stores every share
+const DEFAULT_SHARES_URL: &str = "http://10.5.0.19:2345/shares";
+const SHARES_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What the proxy learns from the block sent to `submitblock`.
+#[derive(Debug)]
+pub struct Block {
+    pub header: HeaderFields,
+    pub height: Option<u64>,
+    // Including the coinbase
+    pub transactions: u64,
+    // Sum of the coinbase outputs in sats, subsidy plus fees
+    pub value: u64,
+}
+
+impl Block {
+    pub fn fees(&self) -> Option<u64> {
+        self.height
+            .map(|height| self.value.saturating_sub(subsidy(height)))
+    }
+}
+
+fn subsidy(height: u64) -> u64 {
+    // Regtest halves every 150 blocks, every other network every 210000
+    let interval = match env::var("NETWORK").as_deref() {
+        Ok("regtest") => 150,
+        _ => 210_000,
+    };
+    let halvings = height / interval;
+    if halvings >= 64 {
+        0
+    } else {
+        INITIAL_SUBSIDY >> halvings
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn var_int(&mut self) -> Option<u64> {
+        match self.u8()? {
+            0xfd => Some(u16::from_le_bytes(self.take(2)?.try_into().ok()?) as u64),
+            0xfe => Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?) as u64),
+            0xff => self.u64(),
+            n => Some(n as u64),
+        }
+    }
+
+    fn var_bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.var_int()?;
+        self.take(usize::try_from(len).ok()?)
+    }
+}
+
+/// Decodes the header, the transaction count and the coinbase of a serialized block.
+pub fn decode(block: &str) -> Option<Block> {
+    let header = share_store::decode_header(block)?;
+    let bytes = hex::decode(block).ok()?;
+
+    let mut reader = Reader {
+        bytes: &bytes,
+        offset: 80,
+    };
+    let transactions = reader.var_int()?;
+
+    // Coinbase: version, optional segwit marker and flag, a single input, the outputs
+    reader.take(4)?;
+    if reader.bytes.get(reader.offset..reader.offset + 2) == Some(&[0x00, 0x01]) {
+        reader.take(2)?;
+    }
+    if reader.var_int()? != 1 {
+        return None;
+    }
+    reader.take(36)?;
+    let script_sig = reader.var_bytes()?;
+    reader.take(4)?;
+    let mut value = 0u64;
+    for _ in 0..reader.var_int()? {
+        value = value.checked_add(reader.u64()?)?;
+        reader.var_bytes()?;
+    }
+
+    Some(Block {
+        header,
+        height: height(script_sig),
+        transactions,
+        value,
+    })
+}
+
+// BIP 34 puts the height as first push of the coinbase script
+fn height(script_sig: &[u8]) -> Option<u64> {
+    match *script_sig.first()? {
+        0x00 => Some(0),
+        len @ 0x01..=0x08 => {
+            let bytes = script_sig.get(1..1 + len as usize)?;
+            Some(
+                bytes
+                    .iter()
+                    .rev()
+                    .fold(0u64, |height, byte| (height << 8) | *byte as u64),
+            )
+        }
+        op @ 0x51..=0x60 => Some((op - 0x50) as u64),
+        _ => None,
+    }
+}
+
+/// A block sent to `submitblock`, waiting for the node's answer.
+pub struct SubmittedBlock {
+    block: Option<Block>,
+    // Time since the matching share was submitted, in milliseconds
+    propagation_time: Option<f64>,
+}
+
+#[derive(Clone)]
+pub struct BlockMetrics {
+    client: reqwest::Client,
+    // Share store of the proxy between the miners and the SV1 pool, `SHARES_URL`
+    shares_url: String,
+    propagation_time: Gauge,
+    mined_blocks: Counter,
+    rejected_blocks: CounterVec,
+    unmatched_block_solutions: Counter,
+    last_block_value: Gauge,
+    last_block_fees: Gauge,
+    last_block_transactions: Gauge,
+}
+
+impl BlockMetrics {
+    pub fn register() -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(SHARES_TIMEOUT)
+                .build()
+                .expect("reqwest client"),
+            shares_url: env::var("SHARES_URL")
+                .unwrap_or_else(|_| DEFAULT_SHARES_URL.to_string()),
+            propagation_time: register_gauge!(
+                "block_propagation_time_through_sv1_pool",
+                "Time to submit a block through SV1 Pool in milliseconds"
+            )
+            .unwrap(),
+            mined_blocks: register_counter!(
+                "sv1_mined_blocks",
+                "Total number of SV1 blocks mined and accepted by the node"
+            )
+            .unwrap(),
+            rejected_blocks: register_counter_vec!(
+                "sv1_rejected_blocks",
+                "Total number of SV1 blocks rejected by the node, by reason",
+                &["reason"]
+            )
+            .unwrap(),
+            unmatched_block_solutions: register_counter!(
+                "sv1_unmatched_block_solutions",
+                "Total number of SV1 blocks submitted not matching exactly one submitted share"
+            )
+            .unwrap(),
+            last_block_value: register_gauge!(
+                "sv1_last_block_mined_value",
+                "Total reward of sats (subsidy and fees) of the last SV1 block accepted by the node"
+            )
+            .unwrap(),
+            last_block_fees: register_gauge!(
+                "sv1_last_block_mined_fees",
+                "Fees in sats of the last SV1 block accepted by the node"
+            )
+            .unwrap(),
+            last_block_transactions: register_gauge!(
+                "sv1_last_block_mined_transactions",
+                "Number of transactions (coinbase included) of the last SV1 block accepted by the node"
+            )
+            .unwrap(),
+        }
+    }
+
+    /// Decodes the block sent to `submitblock` at `timestamp` and finds the share it was found
+    /// with. Runs once the block is forwarded, the share store being a network call away.
+    pub async fn submitted(&self, request: &Value, timestamp: f64) -> SubmittedBlock {
+        let block = request["params"]
+            .get(0)
+            .and_then(|block| block.as_str())
+            .and_then(decode);
+        let Some(block) = block else {
+            log::warn!("Invalid block in submitblock");
+            return SubmittedBlock {
+                block: None,
+                propagation_time: None,
+            };
+        };
+        log::info!(
+            "Block submitted on top of {}: height {:?}, {} transactions, {} sats",
+            block.header.prev_hash,
+            block.height,
+            block.transactions,
+            block.value
+        );
+
+        let share = share_store::lookup(&self.client, &self.shares_url, &block.header).await;
+        let propagation_time = match share {
+            Some(share) => {
+                log::info!("Block found with a share of job {}", share.job_id);
+                Some(timestamp - share.timestamp)
+            }
+            None => {
+                log::warn!("No share matches the submitted block: {:?}", block.header);
+                self.unmatched_block_solutions.inc();
+                None
+            }
+        };
+        SubmittedBlock {
+            block: Some(block),
+            propagation_time,
+        }
+    }
+
+    /// Accounts the block according to the node's answer to `submitblock`: a null result
+    /// means accepted, anything else is the rejection reason.
+    pub fn answered(&self, submitted: SubmittedBlock, response: Option<&Value>) {
+        let reason = match response {
+            Some(response) if !response["error"].is_null() => {
+                format!("rpc-error-{}", response["error"]["code"])
+            }
+            Some(response) => match &response["result"] {
+                Value::Null => {
+                    self.accepted(submitted);
+                    return;
+                }
+                Value::String(reason) => reason.clone(),
+                result => result.to_string(),
+            },
+            None => "invalid-response".to_string(),
+        };
+        log::warn!("Block rejected by the node: {}", reason);
+        self.rejected_blocks.with_label_values(&[&reason]).inc();
+    }
+
+    fn accepted(&self, submitted: SubmittedBlock) {
+        log::info!("Block accepted by the node");
+        self.mined_blocks.inc();
+        if let Some(propagation_time) = submitted.propagation_time {
+            measurements::recorder::set(&self.propagation_time, propagation_time);
+        }
+        if let Some(block) = submitted.block {
+            measurements::recorder::set(&self.last_block_value, block.value as f64);
+            measurements::recorder::set(&self.last_block_transactions, block.transactions as f64);
+            if let Some(fees) = block.fees() {
+                measurements::recorder::set(&self.last_block_fees, fees as f64);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // The genesis block, its coinbase predates BIP 34 and segwit
+    const GENESIS_BLOCK: &str = concat!(
+        "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b2",
+        "7ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c01010000000100",
+        "00000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104",
+        "455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b20",
+        "6f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104",
+        "678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504",
+        "e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000",
+    );
+
+    // A block at height 840000 with a segwit coinbase paying 3.125 BTC plus 2345678 sats of
+    // fees, and the witness commitment
+    const SEGWIT_BLOCK: &str = concat!(
+        "00000020a583da1c3ff29b687248ff737822f8ce4827033a2820030000000000000000005d7c05ffa4dbff",
+        "a8595d88bfb5c526db8fc6cf7c0c6423f95dabef617846f37fb7072366ed4b03177d9863ea010200000000",
+        "01010000000000000000000000000000000000000000000000000000000000000000ffffffff090340d10c",
+        "04deadbeefffffffff02ee29c412000000001600140102030405060708090a0b0c0d0e0f10111213140000",
+        "000000000000266a24aa21a9ed6465666768696a6b6c6d6e6f707172737475767778797a7b7c7d7e7f8081",
+        "8283012000000000000000000000000000000000000000000000000000000000000000000000000000",
+    );
+
+    #[test]
+    fn decodes_a_segwit_block() {
+        let block = decode(SEGWIT_BLOCK).unwrap();
+        assert_eq!(
+            block.header,
+            HeaderFields {
+                prev_hash: "0000000000000000000320283a032748cef8227873ff4872689bf23f1cda83a5"
+                    .to_string(),
+                version: 0x20000000,
+                ntime: 1713571767,
+                nonce: 3932395645,
+            }
+        );
+        assert_eq!(block.height, Some(840_000));
+        assert_eq!(block.transactions, 1);
+        assert_eq!(block.value, 314_845_678);
+        assert_eq!(block.fees(), Some(2_345_678));
+    }
+
+    #[test]
+    fn decodes_a_non_segwit_block() {
+        let block = decode(GENESIS_BLOCK).unwrap();
+        assert_eq!(
+            block.header,
+            HeaderFields {
+                prev_hash: "00".repeat(32),
+                version: 1,
+                ntime: 1231006505,
+                nonce: 2083236893,
+            }
+        );
+        // The first push of the coinbase is the difficulty bits, not a height
+        assert_eq!(block.height, Some(0x1d00ffff));
+        assert_eq!(block.transactions, 1);
+        assert_eq!(block.value, 5_000_000_000);
+    }
+
+    #[test]
+    fn truncated_blocks_are_not_decoded() {
+        for len in [100, 160, 200, 300, GENESIS_BLOCK.len() - 20] {
+            assert!(decode(&GENESIS_BLOCK[..len]).is_none(), "{}", len);
+        }
+        assert!(decode(&SEGWIT_BLOCK[..SEGWIT_BLOCK.len() / 2]).is_none());
+    }
+
+    #[test]
+    fn heights() {
+        assert_eq!(height(&[0x00]), Some(0));
+        assert_eq!(height(&[0x51]), Some(1));
+        assert_eq!(height(&[0x60]), Some(16));
+        assert_eq!(height(&[0x01, 0x11]), Some(17));
+        assert_eq!(height(&[0x03, 0x40, 0xd1, 0x0c, 0xff]), Some(840_000));
+        assert_eq!(height(&[0x03, 0x40, 0xd1]), None);
+        assert_eq!(height(&[]), None);
+    }
+
+    #[test]
+    fn answered() {
+        let metrics = BlockMetrics::register();
+        let submitted = || SubmittedBlock {
+            block: decode(SEGWIT_BLOCK),
+            propagation_time: Some(120.0),
+        };
+
+        metrics.answered(submitted(), Some(&json!({"result": null, "error": null})));
+        assert_eq!(metrics.mined_blocks.get(), 1.0);
+        assert_eq!(metrics.last_block_value.get(), 314_845_678.0);
+        assert_eq!(metrics.propagation_time.get(), 120.0);
+
+        metrics.answered(
+            submitted(),
+            Some(&json!({"result": "bad-prevblk", "error": null})),
+        );
+        let error = json!({"result": null, "error": {"code": -25, "message": "bad block"}});
+        metrics.answered(submitted(), Some(&error));
+        metrics.answered(submitted(), None);
+        assert_eq!(metrics.mined_blocks.get(), 1.0);
+        for reason in ["bad-prevblk", "rpc-error--25", "invalid-response"] {
+            let rejected = metrics.rejected_blocks.with_label_values(&[reason]).get();
+            assert_eq!(rejected, 1.0, "{}", reason);
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use capture_files::CaptureFiles;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Records every line forwarded by the proxy to the capture files, see [`CaptureFiles`].
+#[derive(Clone)]
+pub struct Capture {
+    files: CaptureFiles,
+    connections: Arc<AtomicU64>,
+}
+
+impl Capture {
+    pub fn from_env(proxy_type: &str) -> Option<Self> {
+        let files = CaptureFiles::from_env(&format!("sv1-{}", proxy_type))?;
+        Some(Self {
+            files,
+            connections: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub fn files(&self) -> CaptureFiles {
+        self.files.clone()
+    }
+
+    /// Returns the capture of a newly accepted connection, with its own connection id.
+    pub fn connection(&self) -> ConnectionCapture {
+        ConnectionCapture {
+            capture: self.clone(),
+            id: self.connections.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    fn record(&self, connection: u64, direction: &str, line: &[u8]) {
+        // Lines that aren't valid JSON are kept as they are, to replay them faithfully
+        let fields = match serde_json::from_slice::<Value>(line) {
+            Ok(message) => json!({ "message": message }),
+            Err(_) => json!({ "raw": String::from_utf8_lossy(line) }),
+        };
+        self.files.record(connection, direction, fields);
+    }
+}
+
+pub struct ConnectionCapture {
+    capture: Capture,
+    id: u64,
+}
+
+impl ConnectionCapture {
+    /// Records a line sent by the miner (downstream) to the pool (upstream).
+    pub fn upstream(&self, line: &[u8]) {
+        self.capture.record(self.id, "upstream", line);
+    }
+
+    /// Records a line sent by the pool (upstream) to the miner (downstream).
+    pub fn downstream(&self, line: &[u8]) {
+        self.capture.record(self.id, "downstream", line);
+    }
+}
@@ -0,0 +1,317 @@
+use bench_common::fault_schedule::{self, Conditions};
+use prometheus::{register_counter_vec, CounterVec};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use warp::Filter;
+
+// Bytes written over a random byte of corrupted lines, each one breaks the JSON structure
+const CORRUPTION_BYTES: &[u8] = b"{}[],:\"";
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(tag = "fault", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Fault {
+    // Holds the line back for `ms`, plus a random delay up to `jitter_ms`
+    Delay {
+        ms: u64,
+        #[serde(default)]
+        jitter_ms: u64,
+    },
+    Drop {},
+    Duplicate {},
+    // Cuts the line at a random point, keeping the line terminator
+    Truncate {},
+    // Overwrites a random byte with a JSON delimiter
+    Corrupt {},
+    // Closes both sides of the connection after `after_messages` matching lines, or
+    // `after_seconds` after the miner connected
+    Disconnect {
+        after_messages: Option<u64>,
+        after_seconds: Option<u64>,
+    },
+}
+
+impl Fault {
+    fn name(&self) -> &'static str {
+        match self {
+            Fault::Delay { .. } => "delay",
+            Fault::Drop {} => "drop",
+            Fault::Duplicate {} => "duplicate",
+            Fault::Truncate {} => "truncate",
+            Fault::Corrupt {} => "corrupt",
+            Fault::Disconnect { .. } => "disconnect",
+        }
+    }
+}
+
+/// A fault applied to the lines of a given method under the [`Conditions`] of the rule.
+/// Responses are matched as `<method>.result`, like in the traffic metrics; a missing method
+/// matches all. Unknown fields are rejected by `Fault`, serde can't deny them on a struct with
+/// flattened fields.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Rule {
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(flatten)]
+    pub conditions: Conditions,
+    #[serde(flatten)]
+    pub fault: Fault,
+}
+
+impl Rule {
+    fn matches(&self, direction: &str, message_type: &str) -> bool {
+        self.conditions.matches(direction) && self.method.iter().all(|m| m == message_type)
+    }
+}
+
+struct Rules {
+    rules: Vec<Rule>,
+    // Bumped at every change, so connections reset their message counts
+    generation: u64,
+}
+
+/// Fault rules shared by every connection of the proxy. Initial rules are read as a JSON array
+/// from `FAULT_RULES` and from the entries of the `FAULT_SCHEDULE` file listing the proxy type
+/// in `proxies`, the schedule shared with the SV2 proxies. They can be changed at runtime
+/// through the admin API.
+#[derive(Clone)]
+pub struct Faults {
+    rules: Arc<Mutex<Rules>>,
+    started: Instant,
+    injected: CounterVec,
+}
+
+impl Faults {
+    pub fn register(proxy_type: &str) -> Self {
+        let mut rules: Vec<Rule> = match env::var("FAULT_RULES") {
+            Ok(rules) => serde_json::from_str(&rules).expect("FAULT_RULES is not valid"),
+            Err(_) => Vec::new(),
+        };
+        rules.extend(fault_schedule::from_env::<Rule>(proxy_type));
+        let faults = Self {
+            rules: Arc::new(Mutex::new(Rules {
+                rules: Vec::new(),
+                generation: 0,
+            })),
+            started: Instant::now(),
+            injected: register_counter_vec!(
+                "sv1_injected_faults",
+                "Total number of faults injected by the SV1 proxy",
+                &["fault", "direction", "message_type"]
+            )
+            .unwrap(),
+        };
+        faults.set(rules);
+        faults
+    }
+
+    fn get(&self) -> Vec<Rule> {
+        self.rules.lock().unwrap().rules.clone()
+    }
+
+    fn set(&self, rules: Vec<Rule>) {
+        if !rules.is_empty() {
+            log::warn!("Fault injection rules: {:?}", rules);
+        }
+        let mut current = self.rules.lock().unwrap();
+        current.rules = rules;
+        current.generation += 1;
+    }
+
+    pub fn connection(&self) -> ConnectionFaults {
+        ConnectionFaults {
+            faults: self.clone(),
+            connected: Instant::now(),
+            counts: Mutex::new((0, HashMap::new())),
+            closing: Notify::new(),
+        }
+    }
+}
+
+/// Applies the fault rules to the lines of a single connection.
+pub struct ConnectionFaults {
+    faults: Faults,
+    connected: Instant,
+    // Matching lines seen per disconnect rule, for the current rules generation
+    counts: Mutex<(u64, HashMap<usize, u64>)>,
+    closing: Notify,
+}
+
+impl ConnectionFaults {
+    /// Applies the rules to a line sent by the miner (downstream) to the pool (upstream) and
+    /// returns the lines to forward instead.
+    pub async fn upstream(&self, message_type: &str, line: Vec<u8>) -> Vec<Vec<u8>> {
+        self.apply("upstream", message_type, line).await
+    }
+
+    /// Applies the rules to a line sent by the pool (upstream) to the miner (downstream) and
+    /// returns the lines to forward instead.
+    pub async fn downstream(&self, message_type: &str, line: Vec<u8>) -> Vec<Vec<u8>> {
+        self.apply("downstream", message_type, line).await
+    }
+
+    async fn apply(&self, direction: &str, message_type: &str, line: Vec<u8>) -> Vec<Vec<u8>> {
+        let mut lines = vec![line];
+        let mut delay = Duration::ZERO;
+        {
+            let rules = self.faults.rules.lock().unwrap();
+            let mut counts = self.counts.lock().unwrap();
+            if counts.0 != rules.generation {
+                *counts = (rules.generation, HashMap::new());
+            }
+            let mut rng = rand::thread_rng();
+            let elapsed = self.faults.started.elapsed();
+            for (index, rule) in rules.rules.iter().enumerate() {
+                if !rule.conditions.active(elapsed) || !rule.matches(direction, message_type) {
+                    continue;
+                }
+                if let Fault::Disconnect { after_messages, .. } = &rule.fault {
+                    let count = counts.1.entry(index).or_default();
+                    *count += 1;
+                    // The last matching line is still forwarded
+                    if *after_messages != Some(*count) {
+                        continue;
+                    }
+                }
+                if !rng.gen_bool(rule.conditions.probability.clamp(0.0, 1.0)) {
+                    continue;
+                }
+                match &rule.fault {
+                    Fault::Delay { ms, jitter_ms } => {
+                        delay += Duration::from_millis(ms + rng.gen_range(0..=*jitter_ms));
+                    }
+                    Fault::Drop {} => lines.clear(),
+                    Fault::Duplicate {} => lines.extend(lines.clone()),
+                    Fault::Truncate {} => {
+                        for line in lines.iter_mut() {
+                            truncate(line, &mut rng);
+                        }
+                    }
+                    Fault::Corrupt {} => {
+                        for line in lines.iter_mut() {
+                            corrupt(line, &mut rng);
+                        }
+                    }
+                    Fault::Disconnect { .. } => self.closing.notify_one(),
+                }
+                self.faults
+                    .injected
+                    .with_label_values(&[rule.fault.name(), direction, message_type])
+                    .inc();
+                if lines.is_empty() {
+                    break;
+                }
+            }
+        }
+
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        lines
+    }
+
+    /// Resolves once a disconnect rule fires for this connection.
+    pub async fn disconnected(&self) {
+        tokio::select! {
+            _ = self.closing.notified() => {}
+            _ = self.expired() => {
+                self.faults
+                    .injected
+                    .with_label_values(&["disconnect", "both", "timer"])
+                    .inc();
+            }
+        }
+    }
+
+    // Resolves once a disconnect rule with `after_seconds` expires
+    async fn expired(&self) {
+        loop {
+            let deadline = self
+                .faults
+                .get()
+                .iter()
+                .filter_map(|rule| match rule.fault {
+                    Fault::Disconnect {
+                        after_seconds: Some(after),
+                        ..
+                    } => Some(self.connected + Duration::from_secs(after)),
+                    _ => None,
+                })
+                .min();
+            match deadline {
+                Some(deadline) if deadline <= Instant::now() => break,
+                // Rules may change in the meantime
+                Some(deadline) => {
+                    tokio::time::sleep(
+                        deadline
+                            .saturating_duration_since(Instant::now())
+                            .min(Duration::from_secs(1)),
+                    )
+                    .await
+                }
+                None => tokio::time::sleep(Duration::from_secs(1)).await,
+            }
+        }
+    }
+}
+
+fn truncate(line: &mut Vec<u8>, rng: &mut impl Rng) {
+    if line.len() > 2 {
+        line.truncate(rng.gen_range(1..line.len() - 1));
+        line.push(b'\n');
+    }
+}
+
+fn corrupt(line: &mut [u8], rng: &mut impl Rng) {
+    if line.len() > 1 {
+        let index = rng.gen_range(0..line.len() - 1);
+        let replacements: Vec<u8> = CORRUPTION_BYTES
+            .iter()
+            .copied()
+            .filter(|b| *b != line[index])
+            .collect();
+        line[index] = replacements[rng.gen_range(0..replacements.len())];
+    }
+}
+
+/// `GET /faults` returns the rules, `PUT /faults` replaces them, `POST /faults` appends a rule
+/// and `DELETE /faults` removes them all.
+pub fn routes(
+    faults: Faults,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let path = warp::path("faults").and(warp::path::end());
+    let get = {
+        let faults = faults.clone();
+        path.and(warp::get())
+            .map(move || warp::reply::json(&faults.get()))
+    };
+    let put = {
+        let faults = faults.clone();
+        path.and(warp::put())
+            .and(warp::body::json())
+            .map(move |rules: Vec<Rule>| {
+                faults.set(rules);
+                warp::reply::json(&faults.get())
+            })
+    };
+    let post = {
+        let faults = faults.clone();
+        path.and(warp::post())
+            .and(warp::body::json())
+            .map(move |rule: Rule| {
+                let mut rules = faults.get();
+                rules.push(rule);
+                faults.set(rules);
+                warp::reply::json(&faults.get())
+            })
+    };
+    let delete = path.and(warp::delete()).map(move || {
+        faults.set(Vec::new());
+        warp::reply::json(&faults.get())
+    });
+    get.or(put).or(post).or(delete)
+}
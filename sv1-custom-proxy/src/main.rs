@@ -1,18 +1,39 @@
+use auth::RpcAuth;
+use block::BlockMetrics;
+use capture::{Capture, ConnectionCapture};
+use faults::{ConnectionFaults, Faults};
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Client, Request, Response, Server, Uri};
+use hyper::{Body, Client, Request, Response, Server, StatusCode, Uri};
+mod auth;
+mod block;
+mod capture;
+mod faults;
+mod rpc;
+mod shares;
+mod tip;
+mod traffic;
+mod upstream;
+mod zmq;
+
 use prometheus::{
     register_counter, register_gauge, register_gauge_vec, Counter, Encoder, Gauge, GaugeVec,
     TextEncoder,
 };
+use rpc::RpcMetrics;
 use serde_json::Value;
+use share_store::ShareStore;
+use shares::ConnectionShares;
 use std::collections::VecDeque;
 use std::convert::Infallible;
 use std::env;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
+use tip::{NotifyDelay, TipTracker};
 use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::time::{sleep, Duration};
+use traffic::{ConnectionTraffic, TrafficMetrics};
+use upstream::{NewJobMetrics, NotifyLatency};
 use warp::Filter;
 
 #[allow(clippy::too_many_arguments)]
@@ -25,6 +46,11 @@ async fn transfer(
     share_submission_timestamp: GaugeVec,
     new_job_gauge: Gauge,
     new_job_prev_hash_gauge: Gauge,
+    traffic: ConnectionTraffic,
+    capture: Option<ConnectionCapture>,
+    faults: ConnectionFaults,
+    shares: ConnectionShares,
+    notify_delay: NotifyDelay,
 ) -> io::Result<()> {
     let (mut ri, mut wi) = inbound.split();
     let (mut ro, mut wo) = outbound.split();
@@ -41,6 +67,7 @@ async fn transfer(
             while let Some(pos) = client_buf.iter().position(|&b| b == b'\n') {
                 let line = client_buf.drain(..=pos).collect::<Vec<_>>();
                 if let Ok(json) = serde_json::from_slice::<Value>(&line) {
+                    shares.upstream(&json);
                     if json["method"] == "mining.submit" {
                         submitted_shares.inc();
                         if let Some(params) = json["params"].as_array() {
@@ -73,7 +100,14 @@ async fn transfer(
                         }
                     }
                 }
-                wo.write_all(&line).await?;
+                let message_type = traffic.message_type(&line);
+                for line in faults.upstream(&message_type, line).await {
+                    traffic.upstream(&message_type, &line);
+                    if let Some(capture) = &capture {
+                        capture.upstream(&line);
+                    }
+                    wo.write_all(&line).await?;
+                }
             }
         }
         wo.shutdown().await
@@ -92,6 +126,7 @@ async fn transfer(
             while let Some(pos) = server_buf.iter().position(|&b| b == b'\n') {
                 let line = server_buf.drain(..=pos).collect::<Vec<_>>();
                 if let Ok(json) = serde_json::from_slice::<Value>(&line) {
+                    shares.downstream(&json);
                     if json["method"] == "mining.notify" {
                         if let Some(params) = json["params"].as_array() {
                             if let Some(_prevhash) = params.get(1) {
@@ -100,57 +135,22 @@ async fn transfer(
                                     .expect("Time went backwards")
                                     .as_millis()
                                     as f64;
-                                let prometheus_url = "http://10.5.0.21:4567/metrics";
-                                let client = reqwest::Client::new();
-                                if let Ok(response) = client.get(prometheus_url).send().await {
-                                    if let Ok(body) = response.text().await {
-                                        for line in body.lines() {
-                                            if let Some(start_index) = line.find("flag=") {
-                                                let start = start_index + "flag=\"".len();
-                                                if let Some(value) = line.chars().nth(start) {
-                                                    if value != 's' {
-                                                        if let Some((_, timestamp)) =
-                                                            line.rsplit_once(' ')
-                                                        {
-                                                            log::info!(
-                                                                "The extracted timestamp is: {}",
-                                                                timestamp.trim()
-                                                            );
-                                                            let new_job_timestamp = timestamp
-                                                                .trim()
-                                                                .parse::<f64>()
-                                                                .unwrap();
-                                                            let delta = current_timestamp
-                                                                - new_job_timestamp;
-                                                            new_job_prev_hash_gauge.set(delta);
-                                                            new_job_gauge.set(delta);
-                                                        } else {
-                                                            log::info!("Line: {:?}", line);
-                                                            log::info!("No timestamp value found.");
-                                                        }
-                                                    } else if let Some((_, timestamp)) =
-                                                        line.rsplit_once(' ')
-                                                    {
-                                                        log::info!(
-                                                            "The extracted timestamp is: {}",
-                                                            timestamp.trim()
-                                                        );
-                                                        let new_job_timestamp = timestamp
-                                                            .trim()
-                                                            .parse::<f64>()
-                                                            .unwrap();
-                                                        let delta =
-                                                            current_timestamp - new_job_timestamp;
-                                                        new_job_gauge.set(delta);
-                                                    } else {
-                                                        log::info!("Line: {:?}", line);
-                                                        log::info!("No timestamp value found.");
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
+                                // Measured off the forwarding path, the lookups would
+                                // delay the notify they measure
+                                let notify_delay = notify_delay.clone();
+                                let new_job_gauge = new_job_gauge.clone();
+                                let new_job_prev_hash_gauge = new_job_prev_hash_gauge.clone();
+                                let json = json.clone();
+                                tokio::spawn(async move {
+                                    notify_delay.notify(&json, current_timestamp).await;
+                                    measure_new_job(
+                                        notify_delay.node_pool_proxy(),
+                                        current_timestamp,
+                                        &new_job_gauge,
+                                        &new_job_prev_hash_gauge,
+                                    )
+                                    .await;
+                                });
                             } else {
                                 log::info!("Server to Client: {}", json);
                                 log::info!("Prevhash not found in params");
@@ -171,21 +171,36 @@ async fn transfer(
                     log::info!("Server to Client: {:?}", line);
                     log::info!("Error in getting json from line")
                 }
-                wi.write_all(&line).await?;
+                let message_type = traffic.message_type(&line);
+                for line in faults.downstream(&message_type, line).await {
+                    traffic.downstream(&message_type, &line);
+                    if let Some(capture) = &capture {
+                        capture.downstream(&line);
+                    }
+                    wi.write_all(&line).await?;
+                }
             }
         }
         wi.shutdown().await
     };
 
-    tokio::try_join!(client_to_server, server_to_client)?;
+    tokio::select! {
+        result = async { tokio::try_join!(client_to_server, server_to_client) } => {
+            result?;
+        }
+        _ = faults.disconnected() => log::warn!("Injected disconnect, closing the connection"),
+    }
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_rpc_request(
     req: Request<Body>,
     forward_uri: Uri,
-    block_propagation_time: Gauge,
-    mined_blocks: Counter,
+    rpc_auth: Arc<RpcAuth>,
+    block_metrics: BlockMetrics,
+    rpc_metrics: RpcMetrics,
+    tip_tracker: TipTracker,
     sv1_block_template_value: Gauge,
     sv1_new_job_vec: GaugeVec,
     prev_hash_mutex: Arc<Mutex<VecDeque<String>>>,
@@ -193,72 +208,23 @@ async fn handle_rpc_request(
     let method = req.method().clone();
     let headers = req.headers().clone();
     let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
-    let body_str = String::from_utf8_lossy(&body_bytes);
     let mut is_get_block_template: bool = false;
+    // Time the block was submitted, matched with its share once forwarded to the node
+    let mut submitted_at: Option<f64> = None;
 
-    if let Ok(json) = serde_json::from_slice::<Value>(&body_bytes) {
+    let request = serde_json::from_slice::<Value>(&body_bytes).ok();
+    tip_tracker.request(request.as_ref());
+    let is_longpoll = request.as_ref().is_some_and(tip::is_longpoll);
+    if let Some(json) = &request {
         if let Some(method) = json.get("method") {
             if method == "submitblock" {
                 log::info!("Detected submitblock method.");
-                let current_timestamp = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .expect("Time went backwards")
-                    .as_millis() as f64;
-                let prometheus_url = "http://10.5.0.19:2345/metrics";
-                let client = reqwest::Client::new();
-                if let Ok(response) = client.get(prometheus_url).send().await {
-                    if let Ok(body) = response.text().await {
-                        let mut nonce_found = false;
-                        for line in body.lines() {
-                            if let Some(start_index) = line.find("nonce=\"\\\"") {
-                                let start = start_index + "nonce=\"\\\"".len();
-                                let end = match line[start..].find("\\\"") {
-                                    Some(index) => start + index,
-                                    None => {
-                                        log::info!(
-                                            "Failed to find end quote for nonce in line: {}",
-                                            line
-                                        );
-                                        continue; // Skip to the next line if end quote for nonce is not found
-                                    }
-                                };
-                                let nonce_value = &line[start..end];
-                                // Decode the nonce hex string into bytes
-                                let nonce_bytes_result = hex::decode(nonce_value);
-                                let nonce_bytes = match nonce_bytes_result {
-                                    Ok(bytes) => bytes,
-                                    Err(e) => {
-                                        log::info!("Failed to parse nonce hex: {}", e);
-                                        continue;
-                                    }
-                                };
-                                // Perform bytes swap with the nonce bytes
-                                let swapped_bytes: Vec<u8> =
-                                    nonce_bytes.iter().rev().cloned().collect();
-                                let swapped_nonce =
-                                    hex::encode_upper(&swapped_bytes).to_lowercase();
-
-                                // Check if the body contains the swapped nonce
-                                if body_str.contains(&swapped_nonce) {
-                                    let parts: Vec<&str> = line.split_whitespace().collect();
-                                    if let Some(timestamp_str) = parts.get(1) {
-                                        if let Ok(previous_timestamp) = timestamp_str.parse::<f64>()
-                                        {
-                                            let latency = current_timestamp - previous_timestamp;
-                                            block_propagation_time.set(latency);
-                                            mined_blocks.inc();
-                                        }
-                                    }
-                                    nonce_found = true;
-                                    break;
-                                }
-                            }
-                        }
-                        if !nonce_found {
-                            log::warn!("Nonce not found in Prometheus metrics");
-                        }
-                    }
-                }
+                submitted_at = Some(
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .expect("Time went backwards")
+                        .as_millis() as f64,
+                );
             } else if method == "getblocktemplate" {
                 is_get_block_template = true;
             }
@@ -275,29 +241,49 @@ async fn handle_rpc_request(
     *new_req.headers_mut() = headers.clone();
 
     // Add authentication header if not already present
+    let mut credentials = "client";
     if !new_req.headers().contains_key("authorization") {
-        let auth_value = "Basic dXNlcm5hbWU6cGFzc3dvcmQ=";
-        new_req
-            .headers_mut()
-            .insert("authorization", auth_value.parse().unwrap());
+        if let Some((auth_value, source)) = rpc_auth.authorization() {
+            credentials = source;
+            new_req
+                .headers_mut()
+                .insert("authorization", auth_value.parse().unwrap());
+        }
     }
 
+    let rpc_call = rpc_metrics.start(request.as_ref());
     let res = match client.request(new_req).await {
         Ok(res) => res,
         Err(err) => {
             log::error!("Error forwarding request: {}", err);
+            rpc_call.failed();
             return Err(err);
         }
     };
 
     let status = res.status();
+    if status == StatusCode::UNAUTHORIZED {
+        rpc_auth.unauthorized(credentials);
+    }
     let body_bytes = hyper::body::to_bytes(res.into_body()).await?;
+    let response = serde_json::from_slice::<Value>(&body_bytes).ok();
+    rpc_call.answered(status, &body_bytes, response.as_ref());
+
+    // The node answers null when it accepts the block, the rejection reason otherwise
+    if let (Some(timestamp), Some(request)) = (submitted_at, request.clone()) {
+        let response = response.clone();
+        tokio::spawn(async move {
+            let submitted_block = block_metrics.submitted(&request, timestamp).await;
+            block_metrics.answered(submitted_block, response.as_ref());
+        });
+    }
 
-    if let Ok(json) = serde_json::from_slice::<Value>(&body_bytes) {
+    if let Some(json) = response {
         if is_get_block_template {
             if let Some(result) = json.get("result") {
                 if let Some(previousblockhash) = result.get("previousblockhash") {
                     if let Some(prevhash) = previousblockhash.as_str() {
+                        tip_tracker.template(prevhash, is_longpoll);
                         let prev_hash = reverse_string(prevhash);
                         let mut flag = "same";
                         if let Some(prev_hash_value) = prev_hash_mutex.lock().unwrap().pop_front() {
@@ -323,7 +309,10 @@ async fn handle_rpc_request(
                         // Take the coinbase value and set the block template value metric
                         if let Some(coinbasevalue) = result.get("coinbasevalue") {
                             if let Some(block_value) = coinbasevalue.as_i64() {
-                                sv1_block_template_value.set(block_value as f64);
+                                measurements::recorder::set(
+                                    &sv1_block_template_value,
+                                    block_value as f64,
+                                );
                             }
                         }
                     }
@@ -340,6 +329,49 @@ async fn handle_rpc_request(
     Ok(new_res)
 }
 
+// Time between the template scraped from the node-pool proxy and the mining.notify sent to
+// the miner at `current_timestamp`
+async fn measure_new_job(
+    node_pool_proxy: &str,
+    current_timestamp: f64,
+    new_job_gauge: &Gauge,
+    new_job_prev_hash_gauge: &Gauge,
+) {
+    let prometheus_url = format!("{}/metrics", node_pool_proxy);
+    let client = reqwest::Client::new();
+    if let Ok(response) = client.get(prometheus_url).send().await {
+        if let Ok(body) = response.text().await {
+            for line in body.lines() {
+                if let Some(start_index) = line.find("flag=") {
+                    let start = start_index + "flag=\"".len();
+                    if let Some(value) = line.chars().nth(start) {
+                        if value != 's' {
+                            if let Some((_, timestamp)) = line.rsplit_once(' ') {
+                                log::info!("The extracted timestamp is: {}", timestamp.trim());
+                                let new_job_timestamp = timestamp.trim().parse::<f64>().unwrap();
+                                let delta = current_timestamp - new_job_timestamp;
+                                measurements::recorder::set(new_job_prev_hash_gauge, delta);
+                                measurements::recorder::set(new_job_gauge, delta);
+                            } else {
+                                log::info!("Line: {:?}", line);
+                                log::info!("No timestamp value found.");
+                            }
+                        } else if let Some((_, timestamp)) = line.rsplit_once(' ') {
+                            log::info!("The extracted timestamp is: {}", timestamp.trim());
+                            let new_job_timestamp = timestamp.trim().parse::<f64>().unwrap();
+                            let delta = current_timestamp - new_job_timestamp;
+                            measurements::recorder::set(new_job_gauge, delta);
+                        } else {
+                            log::info!("Line: {:?}", line);
+                            log::info!("No timestamp value found.");
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
 fn reverse_string(s: &str) -> String {
     let mut chars: Vec<char> = s.chars().collect();
     chars.reverse();
@@ -361,6 +393,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let prometheus_exporter_address =
         env::var("PROM_ADDRESS").expect("PROM_ADDRESS environment variable not set");
 
+    let capture = Capture::from_env(&proxy_type);
+    measurements::recorder::start(&proxy_type);
+
+    let faults = Faults::register(&proxy_type);
+    let share_store = ShareStore::from_env();
+    let tip_tracker = TipTracker::register();
+
+    let capture_routes = capture_files::routes(capture.as_ref().map(Capture::files));
+    let fault_routes = faults::routes(faults.clone());
+    let share_routes = share_store::routes(share_store.clone());
+    let tip_routes = tip::routes(tip_tracker.clone());
     tokio::spawn(async move {
         let metrics_route = warp::path("metrics").map(move || {
             let encoder = TextEncoder::new();
@@ -374,7 +417,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let addr: std::net::SocketAddr = prometheus_exporter_address
             .parse()
             .expect("Invalid address");
-        warp::serve(metrics_route).run(addr).await;
+        warp::serve(
+            metrics_route
+                .or(capture_routes)
+                .or(fault_routes)
+                .or(share_routes)
+                .or(tip_routes),
+        )
+        .run(addr)
+        .await;
     });
 
     if proxy_type == "pool-miner" {
@@ -405,6 +456,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         )
         .unwrap();
 
+        let traffic = TrafficMetrics::register();
+        let notify_delay = NotifyDelay::register();
+
         let client_address: SocketAddr = client.parse().expect("Invalid address");
         let server_address: SocketAddr = server.parse().expect("Invalid address");
         let listener = TcpListener::bind(client_address).await?;
@@ -420,6 +474,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             let share_submission_timestamp_clone = share_submission_timestamp.clone();
             let new_job_gauge = sv1_new_job_latency.clone();
             let new_job_prev_hash_gauge = sv1_new_job_prev_hash_latency.clone();
+            let connection_traffic = traffic.connection();
+            let connection_capture = capture.as_ref().map(Capture::connection);
+            let connection_faults = faults.connection();
+            let connection_shares = shares::connection(&share_store);
+            let notify_delay = notify_delay.clone();
 
             tokio::spawn(async move {
                 if let Err(e) = transfer(
@@ -431,6 +490,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     share_submission_timestamp_clone,
                     new_job_gauge,
                     new_job_prev_hash_gauge,
+                    connection_traffic,
+                    connection_capture,
+                    connection_faults,
+                    connection_shares,
+                    notify_delay,
                 )
                 .await
                 {
@@ -442,15 +506,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         let addr = env::var("CLIENT").expect("CLIENT environment variable not set");
         let forward_uri = env::var("SERVER").expect("SERVER_URI environment variable not set");
         let addr: SocketAddr = addr.parse().expect("Invalid address");
+        let rpc_auth = Arc::new(RpcAuth::from_env(&forward_uri));
         let forward_uri: Uri = forward_uri.parse().expect("Invalid URI");
         let prev_hash: Arc<Mutex<VecDeque<String>>> = Arc::new(Mutex::new(VecDeque::new()));
 
-        let block_propagation_time_through_sv1_pool = register_gauge!(
-            "block_propagation_time_through_sv1_pool",
-            "Time to submit a block through SV1 Pool in milliseconds"
-        )?;
-        let mined_blocks =
-            register_counter!("sv1_mined_blocks", "Total number of SV1 blocks mined")?;
+        let block_metrics = BlockMetrics::register();
+        let rpc_metrics = RpcMetrics::register();
+        tip_tracker.follow_zmq();
         let sv1_block_template_value = register_gauge!(
             "sv1_block_template_value",
             "Total reward of sats contained in the current SV1 block template"
@@ -464,9 +526,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         let make_svc = make_service_fn(move |_conn| {
             let forward_uri = forward_uri.clone();
-            let block_propagation_time_through_sv1_pool =
-                block_propagation_time_through_sv1_pool.clone();
-            let blocks_mined_metric = mined_blocks.clone();
+            let rpc_auth = rpc_auth.clone();
+            let block_metrics = block_metrics.clone();
+            let rpc_metrics = rpc_metrics.clone();
+            let tip_tracker = tip_tracker.clone();
             let sv1_block_template_value = sv1_block_template_value.clone();
             let sv1_new_job_vec = sv1_new_job_vec.clone();
             let prev_hash_clone = prev_hash.clone();
@@ -475,8 +538,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     handle_rpc_request(
                         req,
                         forward_uri.clone(),
-                        block_propagation_time_through_sv1_pool.clone(),
-                        blocks_mined_metric.clone(),
+                        rpc_auth.clone(),
+                        block_metrics.clone(),
+                        rpc_metrics.clone(),
+                        tip_tracker.clone(),
                         sv1_block_template_value.clone(),
                         sv1_new_job_vec.clone(),
                         prev_hash_clone.clone(),
@@ -493,44 +558,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             log::error!("server error: {}", e);
         }
     } else if proxy_type == "translator-miner" {
-        let new_job_prev_hash_throught_sv2_jdc = Arc::new(
-            register_gauge!(
-                "new_job_prev_hash_throught_sv2_jdc",
-                "Time required to complete one tp->jdc , translator->node round of new job prev hash"
-            ).unwrap()
-        );
-        let new_job_prev_hash_through_sv2_pool = Arc::new(
-            register_gauge!(
-                "new_job_prev_hash_through_sv2_pool",
-                "Time required to complete one tp->pool , translator->node round of new job prev hash"
-            ).unwrap()
-        );
-        let new_job_time_sv2_jdc = Arc::new(
-            register_gauge!("new_job_jdc_new_template", "new job jdc new template").unwrap(),
-        );
-        let new_job_time_sv2_pool = Arc::new(
-            register_gauge!("new_job_pool_new_template", "new job pool new template").unwrap(),
-        );
-
-        let listener = tokio::net::TcpListener::bind("0.0.0.0:34255")
-            .await
-            .unwrap();
-        log::info!("SV2 proxy translation proxy started at 34255");
+        let new_job = NewJobMetrics::register();
+        let notify_latency = NotifyLatency::register();
+
+        let traffic = TrafficMetrics::register();
+
+        let listener = tokio::net::TcpListener::bind(&client).await.unwrap();
+        log::info!("SV2 proxy translation proxy started at {}", client);
         loop {
             let (inbound, _) = listener.accept().await.unwrap();
-            let outbound = TcpStream::connect("10.5.0.7:34256").await.unwrap();
-            let new_job_jdc = new_job_prev_hash_throught_sv2_jdc.clone();
-            let new_job_pool = new_job_prev_hash_through_sv2_pool.clone();
-            let new_job_time_sv2_jdc = new_job_time_sv2_jdc.clone();
-            let new_job_time_sv2_pool = new_job_time_sv2_pool.clone();
+            let outbound = TcpStream::connect(&server).await.unwrap();
+            let new_job = new_job.clone();
+            let notify_latency = notify_latency.clone();
+            let connection_traffic = traffic.connection();
+            let connection_capture = capture.as_ref().map(Capture::connection);
+            let connection_faults = faults.connection();
             tokio::spawn(async move {
                 if let Err(e) = transfer_new_job(
                     inbound,
                     outbound,
-                    new_job_jdc,
-                    new_job_pool,
-                    new_job_time_sv2_jdc,
-                    new_job_time_sv2_pool,
+                    new_job,
+                    notify_latency,
+                    connection_traffic,
+                    connection_capture,
+                    connection_faults,
                 )
                 .await
                 {
@@ -543,13 +594,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn transfer_new_job(
     mut inbound: tokio::net::TcpStream,
     mut outbound: tokio::net::TcpStream,
-    new_job_prev_hash_throught_sv2_jdc: Arc<Gauge>,
-    new_job_prev_hash_through_sv2_pool: Arc<Gauge>,
-    new_job_time_sv2_jdc: Arc<Gauge>,
-    new_job_time_sv2_pool: Arc<Gauge>,
+    new_job: NewJobMetrics,
+    notify_latency: NotifyLatency,
+    traffic: ConnectionTraffic,
+    capture: Option<ConnectionCapture>,
+    faults: ConnectionFaults,
 ) -> std::io::Result<()> {
     let (mut ri, mut wi) = inbound.split();
 
@@ -572,7 +625,14 @@ async fn transfer_new_job(
             while let Some(pos) = client_buf.iter().position(|&b| b == b'\n') {
                 let line = client_buf.drain(..=pos).collect::<Vec<_>>();
 
-                wo.write_all(&line).await?;
+                let message_type = traffic.message_type(&line);
+                for line in faults.upstream(&message_type, line).await {
+                    traffic.upstream(&message_type, &line);
+                    if let Some(capture) = &capture {
+                        capture.upstream(&line);
+                    }
+                    wo.write_all(&line).await?;
+                }
             }
         }
 
@@ -602,52 +662,19 @@ async fn transfer_new_job(
                         .as_millis() as f64;
                     if json["method"] == "mining.notify" {
                         if let Some(params) = json["params"].as_array() {
-                            if let Some(_prevhash) = params.get(1) {
-                                let prometheus_url = "http://10.5.0.20:5678/metrics";
-                                let client = reqwest::Client::new();
-                                if let Ok(response) = client.get(prometheus_url).send().await {
-                                    if let Ok(body) = response.text().await {
-                                        for line in body.lines() {
-                                            if let Some(start_index) = line.find("prevhash=") {
-                                                let start = start_index + "prevhash=\"".len();
-                                                let _end = match line[start..].find('"') {
-                                                    Some(index) => start + index,
-                                                    None => {
-                                                        log::error!("Failed to find end quote for prevhash in line: {}", line);
-                                                        continue;
-                                                    }
-                                                };
-                                                if let Some((_, timestamp)) = line.rsplit_once(' ')
-                                                {
-                                                    let new_job_timestamp =
-                                                        timestamp.trim().parse::<f64>().unwrap();
-                                                    let delta =
-                                                        current_timestamp - new_job_timestamp;
-                                                    new_job_prev_hash_throught_sv2_jdc.set(delta);
-                                                    new_job_prev_hash_through_sv2_pool.set(delta);
-                                                } else {
-                                                    log::warn!("No timestamp value found.");
-                                                }
-                                            }
-                                            if line.contains("id=") {
-                                                if let Some((_, timestamp)) = line.rsplit_once(' ')
-                                                {
-                                                    let new_job_timestamp =
-                                                        timestamp.trim().parse::<f64>().unwrap();
-                                                    let delta =
-                                                        current_timestamp - new_job_timestamp;
-                                                    new_job_time_sv2_jdc.set(delta);
-                                                    new_job_time_sv2_pool.set(delta);
-                                                } else {
-                                                    log::warn!("No timestamp value found.");
-                                                }
-                                            }
-                                        }
-                                    }
+                            // Off the forwarding path, the lookups would delay the notify they
+                            // measure
+                            let new_job = new_job.clone();
+                            let notify_latency = notify_latency.clone();
+                            let params = params.clone();
+                            tokio::spawn(async move {
+                                if params.get(1).is_some() {
+                                    new_job.measure(current_timestamp).await;
+                                } else {
+                                    log::warn!("Prevhash not found in params");
                                 }
-                            } else {
-                                log::warn!("Prevhash not found in params");
-                            }
+                                notify_latency.notify(&params, current_timestamp).await;
+                            });
                         } else {
                             log::warn!("Params is not an array");
                         }
@@ -656,11 +683,23 @@ async fn transfer_new_job(
                 } else {
                     log::info!("Server to Client: {:?}", line);
                 }
-                wi.write_all(&line).await?;
+                let message_type = traffic.message_type(&line);
+                for line in faults.downstream(&message_type, line).await {
+                    traffic.downstream(&message_type, &line);
+                    if let Some(capture) = &capture {
+                        capture.downstream(&line);
+                    }
+                    wi.write_all(&line).await?;
+                }
             }
         }
         wi.shutdown().await
     };
-    tokio::try_join!(client_to_server, server_to_client)?;
+    tokio::select! {
+        result = async { tokio::try_join!(client_to_server, server_to_client) } => {
+            result?;
+        }
+        _ = faults.disconnected() => log::warn!("Injected disconnect, closing the connection"),
+    }
     Ok(())
 }
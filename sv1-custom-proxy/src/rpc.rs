@@ -0,0 +1,161 @@
+use crate::tip;
+use hyper::StatusCode;
+use prometheus::{
+    exponential_buckets, register_counter_vec, register_gauge, register_histogram_vec, CounterVec,
+    Gauge, HistogramVec,
+};
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::time::{interval, Duration};
+
+/// Metrics of the JSON-RPC calls forwarded by the node-pool proxy to the Bitcoin node.
+#[derive(Clone)]
+pub struct RpcMetrics {
+    latency: HistogramVec,
+    responses: CounterVec,
+    errors: CounterVec,
+    response_size: HistogramVec,
+    template_age: Gauge,
+    // Previous block hash of the last template and when it was first seen
+    last_template: Arc<Mutex<Option<(String, Instant)>>>,
+}
+
+impl RpcMetrics {
+    pub fn register() -> Self {
+        let metrics = Self {
+            latency: register_histogram_vec!(
+                "sv1_rpc_latency",
+                "Time for the Bitcoin node to answer a JSON-RPC call of the SV1 pool in milliseconds",
+                &["method"],
+                exponential_buckets(1.0, 2.0, 14).unwrap()
+            )
+            .unwrap(),
+            responses: register_counter_vec!(
+                "sv1_rpc_responses",
+                "Total number of JSON-RPC calls of the SV1 pool by method and HTTP status",
+                &["method", "status"]
+            )
+            .unwrap(),
+            errors: register_counter_vec!(
+                "sv1_rpc_errors",
+                "Total number of JSON-RPC errors returned by the Bitcoin node by method and code",
+                &["method", "code"]
+            )
+            .unwrap(),
+            response_size: register_histogram_vec!(
+                "sv1_rpc_response_size",
+                "Size of the Bitcoin node answers to the SV1 pool in bytes",
+                &["method"],
+                exponential_buckets(256.0, 4.0, 10).unwrap()
+            )
+            .unwrap(),
+            template_age: register_gauge!(
+                "sv1_template_age",
+                "Seconds since the last block template on top of a new previous block hash"
+            )
+            .unwrap(),
+            last_template: Arc::new(Mutex::new(None)),
+        };
+        let ticking = metrics.clone();
+        tokio::spawn(async move {
+            let mut ticks = interval(Duration::from_secs(1));
+            loop {
+                ticks.tick().await;
+                ticking.update_template_age();
+            }
+        });
+        metrics
+    }
+
+    /// Starts timing a call, `request` is the parsed request body.
+    pub fn start(&self, request: Option<&Value>) -> RpcCall {
+        let method = match request {
+            Some(Value::Array(_)) => "batch".to_string(),
+            // Long polling calls are held by the node until the template changes
+            Some(request) if tip::is_longpoll(request) => "getblocktemplate.longpoll".to_string(),
+            Some(request) => request["method"].as_str().unwrap_or("unknown").to_string(),
+            None => "invalid".to_string(),
+        };
+        RpcCall {
+            metrics: self.clone(),
+            method,
+            started: Instant::now(),
+        }
+    }
+
+    fn update_template_age(&self) {
+        if let Some((_, since)) = self.last_template.lock().unwrap().as_ref() {
+            self.template_age.set(since.elapsed().as_secs_f64());
+        }
+    }
+
+    fn template(&self, prev_hash: &str) {
+        let mut last_template = self.last_template.lock().unwrap();
+        if last_template
+            .as_ref()
+            .is_some_and(|(last_prev_hash, _)| last_prev_hash == prev_hash)
+        {
+            return;
+        }
+        *last_template = Some((prev_hash.to_string(), Instant::now()));
+        drop(last_template);
+        self.update_template_age();
+    }
+}
+
+/// A call forwarded to the Bitcoin node, waiting for its answer.
+pub struct RpcCall {
+    metrics: RpcMetrics,
+    method: String,
+    started: Instant,
+}
+
+impl RpcCall {
+    /// Accounts a call the node couldn't be reached for.
+    pub fn failed(self) {
+        self.metrics
+            .responses
+            .with_label_values(&[&self.method, "unreachable"])
+            .inc();
+    }
+
+    /// Accounts the answer of the node, `response` is the parsed response body.
+    pub fn answered(self, status: StatusCode, body: &[u8], response: Option<&Value>) {
+        let metrics = &self.metrics;
+        metrics
+            .latency
+            .with_label_values(&[&self.method])
+            .observe(self.started.elapsed().as_secs_f64() * 1000.0);
+        metrics
+            .responses
+            .with_label_values(&[&self.method, status.as_str()])
+            .inc();
+        metrics
+            .response_size
+            .with_label_values(&[&self.method])
+            .observe(body.len() as f64);
+
+        let responses = match response {
+            Some(Value::Array(responses)) => responses.iter().collect(),
+            Some(response) => vec![response],
+            None => Vec::new(),
+        };
+        for response in responses {
+            if !response["error"].is_null() {
+                metrics
+                    .errors
+                    .with_label_values(&[&self.method, &response["error"]["code"].to_string()])
+                    .inc();
+            }
+        }
+
+        if self.method.starts_with("getblocktemplate") {
+            if let Some(prev_hash) =
+                response.and_then(|response| response["result"]["previousblockhash"].as_str())
+            {
+                metrics.template(prev_hash);
+            }
+        }
+    }
+}
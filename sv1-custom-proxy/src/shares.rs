@@ -0,0 +1,124 @@
+use crate::tip::stratum_prev_hash;
+use serde_json::Value;
+use share_store::{HeaderFields, ShareStore};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+// Jobs remembered per connection to rebuild the header of the shares
+const MAX_JOBS: usize = 1000;
+// BIP 310 default version rolling mask, used until the pool answers mining.configure
+const DEFAULT_VERSION_ROLLING_MASK: u32 = 0x1fffe000;
+
+/// Stores the shares of a new connection in `store`.
+pub fn connection(store: &ShareStore) -> ConnectionShares {
+    ConnectionShares {
+        store: store.clone(),
+        jobs: Mutex::new(Jobs {
+            headers: HashMap::new(),
+            order: VecDeque::new(),
+            version_rolling_mask: DEFAULT_VERSION_ROLLING_MASK,
+        }),
+    }
+}
+
+// Version and previous block hash of a job
+struct Job {
+    version: u32,
+    prev_hash: String,
+}
+
+struct Jobs {
+    headers: HashMap<String, Job>,
+    order: VecDeque<String>,
+    version_rolling_mask: u32,
+}
+
+/// Stores the shares of a single connection. mining.submit only carries the rolled version
+/// bits, the full version and the previous block hash come from the mining.notify of the job.
+pub struct ConnectionShares {
+    store: ShareStore,
+    jobs: Mutex<Jobs>,
+}
+
+impl ConnectionShares {
+    /// Handles a message sent by the miner (downstream) to the pool (upstream).
+    pub fn upstream(&self, json: &Value) {
+        if json["method"] != "mining.submit" {
+            return;
+        }
+        let Some(params) = json["params"].as_array() else {
+            return;
+        };
+        let field = |index: usize| {
+            params
+                .get(index)
+                .and_then(|param| param.as_str())
+                .and_then(|param| u32::from_str_radix(param, 16).ok())
+        };
+        let (Some(job_id), Some(ntime), Some(nonce)) =
+            (params.get(1).and_then(|id| id.as_str()), field(3), field(4))
+        else {
+            log::warn!("Invalid mining.submit params: {:?}", params);
+            return;
+        };
+        let jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.headers.get(job_id) else {
+            log::warn!("mining.submit for unknown job {}", job_id);
+            return;
+        };
+        let mask = jobs.version_rolling_mask;
+        let version = match field(5) {
+            Some(version_bits) => (job.version & !mask) | (version_bits & mask),
+            None => job.version,
+        };
+        self.store.insert(
+            job_id.to_string(),
+            HeaderFields {
+                prev_hash: job.prev_hash.clone(),
+                nonce,
+                ntime,
+                version,
+            },
+        );
+    }
+
+    /// Handles a message sent by the pool (upstream) to the miner (downstream).
+    pub fn downstream(&self, json: &Value) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(mask) = json["result"]["version-rolling.mask"]
+            .as_str()
+            .and_then(|mask| u32::from_str_radix(mask, 16).ok())
+        {
+            jobs.version_rolling_mask = mask;
+            return;
+        }
+        if json["method"] != "mining.notify" {
+            return;
+        }
+        let Some(params) = json["params"].as_array() else {
+            return;
+        };
+        let (Some(job_id), Some(prev_hash), Some(version)) = (
+            params.first().and_then(|id| id.as_str()),
+            params
+                .get(1)
+                .and_then(|prev_hash| prev_hash.as_str())
+                .and_then(stratum_prev_hash),
+            params
+                .get(5)
+                .and_then(|version| version.as_str())
+                .and_then(|version| u32::from_str_radix(version, 16).ok()),
+        ) else {
+            return;
+        };
+        let job = Job { version, prev_hash };
+        if jobs.headers.insert(job_id.to_string(), job).is_none() {
+            jobs.order.push_back(job_id.to_string());
+        }
+        while jobs.order.len() > MAX_JOBS {
+            if let Some(job_id) = jobs.order.pop_front() {
+                jobs.headers.remove(&job_id);
+            }
+        }
+    }
+}
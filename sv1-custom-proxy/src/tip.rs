@@ -0,0 +1,302 @@
+use crate::zmq;
+use bench_common::hash::sha256d;
+use prometheus::{
+    register_counter, register_counter_vec, register_gauge, register_gauge_vec, Counter,
+    CounterVec, Gauge, GaugeVec,
+};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::net::TcpStream;
+use tokio::time::{sleep, Duration};
+use warp::Filter;
+
+const ZMQ_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const DEFAULT_NODE_POOL_PROXY_URL: &str = "http://10.5.0.21:4567";
+// Former tips remembered to recognize templates fetched before the tip changed
+const MAX_FORMER_TIPS: usize = 10;
+
+/// The chain tip as seen by the node-pool proxy.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Tip {
+    // Block hash, in the usual reversed hex
+    pub hash: String,
+    // Milliseconds since the epoch, when the proxy learnt about the block
+    pub changed_at: f64,
+    // `hashblock`, `rawblock` or `getblocktemplate` when ZMQ didn't notify it first
+    pub source: String,
+    // Milliseconds since the epoch, of the first template on top of the block
+    pub template_at: Option<f64>,
+}
+
+/// Follows the chain tip through the ZMQ notifications of the node at `ZMQ_ADDRESS` (when set)
+/// and the templates fetched by the SV1 pool, to measure how long the pool takes to react.
+#[derive(Clone)]
+pub struct TipTracker {
+    tip: Arc<Mutex<Option<Tip>>>,
+    former: Arc<Mutex<VecDeque<String>>>,
+    changes: CounterVec,
+    template_delay: GaugeVec,
+    longpolls: Counter,
+}
+
+impl TipTracker {
+    pub fn register() -> Self {
+        Self {
+            tip: Arc::new(Mutex::new(None)),
+            former: Arc::new(Mutex::new(VecDeque::new())),
+            changes: register_counter_vec!(
+                "sv1_tip_changes",
+                "Total number of chain tip changes by how the SV1 node-pool proxy learnt about them",
+                &["source"]
+            )
+            .unwrap(),
+            template_delay: register_gauge_vec!(
+                "sv1_tip_to_template_delay",
+                "Time between a chain tip change and the first template on top of it fetched by the SV1 pool in milliseconds",
+                &["trigger"]
+            )
+            .unwrap(),
+            longpolls: register_counter!(
+                "sv1_longpoll_requests",
+                "Total number of long polling getblocktemplate calls of the SV1 pool"
+            )
+            .unwrap(),
+        }
+    }
+
+    /// Subscribes to the ZMQ notifications of the node at `ZMQ_ADDRESS`, when set.
+    pub fn follow_zmq(&self) {
+        match env::var("ZMQ_ADDRESS") {
+            Ok(endpoint) => {
+                let subscriber = self.clone();
+                tokio::spawn(async move {
+                    loop {
+                        if let Err(e) = subscriber.subscribe(zmq::address(&endpoint)).await {
+                            log::error!("ZMQ subscription to {} failed: {}", endpoint, e);
+                        }
+                        sleep(ZMQ_RECONNECT_DELAY).await;
+                    }
+                });
+            }
+            Err(_) => log::warn!("ZMQ_ADDRESS not set, tip changes are detected from templates"),
+        }
+    }
+
+    async fn subscribe(&self, address: &str) -> tokio::io::Result<()> {
+        let mut stream = TcpStream::connect(address).await?;
+        zmq::handshake(&mut stream, "SUB").await?;
+        zmq::subscribe(&mut stream, "hashblock").await?;
+        zmq::subscribe(&mut stream, "rawblock").await?;
+        log::info!("Subscribed to the ZMQ notifications of {}", address);
+        loop {
+            let message = zmq::recv(&mut stream).await?;
+            let (Some(topic), Some(body)) = (message.first(), message.get(1)) else {
+                continue;
+            };
+            let hash = match topic.as_slice() {
+                b"hashblock" if body.len() == 32 => hex::encode(body),
+                b"rawblock" if body.len() >= 80 => block_hash(&body[..80]),
+                _ => continue,
+            };
+            let source = String::from_utf8_lossy(topic).to_string();
+            self.changed(&hash, &source);
+        }
+    }
+
+    fn changed(&self, hash: &str, source: &str) -> bool {
+        self.update(&mut self.tip.lock().unwrap(), hash, source)
+    }
+
+    fn update(&self, tip: &mut Option<Tip>, hash: &str, source: &str) -> bool {
+        if tip.as_ref().is_some_and(|tip| tip.hash == hash) {
+            return false;
+        }
+        log::info!("New chain tip {} from {}", hash, source);
+        self.changes.with_label_values(&[source]).inc();
+        if let Some(former_tip) = tip.take() {
+            let mut former = self.former.lock().unwrap();
+            former.push_back(former_tip.hash);
+            if former.len() > MAX_FORMER_TIPS {
+                former.pop_front();
+            }
+        }
+        *tip = Some(Tip {
+            hash: hash.to_string(),
+            changed_at: now(),
+            source: source.to_string(),
+            template_at: None,
+        });
+        true
+    }
+
+    /// Counts the long polling `getblocktemplate` calls, which the node only answers once the
+    /// template changed.
+    pub fn request(&self, request: Option<&Value>) {
+        if request.is_some_and(is_longpoll) {
+            self.longpolls.inc();
+        }
+    }
+
+    /// Handles a template fetched by the pool on top of `prev_hash`. Templates on top of a
+    /// former tip were requested before ZMQ notified the current one and are ignored.
+    pub fn template(&self, prev_hash: &str, longpoll: bool) {
+        let mut tip = self.tip.lock().unwrap();
+        if self
+            .former
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|hash| hash == prev_hash)
+        {
+            log::debug!("Template on top of the former tip {}", prev_hash);
+            return;
+        }
+        if self.update(&mut tip, prev_hash, "getblocktemplate") {
+            tip.as_mut().unwrap().template_at = Some(now());
+            return;
+        }
+        let Some(tip) = tip.as_mut() else {
+            return;
+        };
+        if tip.template_at.is_some() {
+            return;
+        }
+        let template_at = now();
+        tip.template_at = Some(template_at);
+        let trigger = if longpoll { "longpoll" } else { "poll" };
+        measurements::recorder::set(
+            &self.template_delay.with_label_values(&[trigger]),
+            template_at - tip.changed_at,
+        );
+    }
+
+    pub fn current(&self) -> Option<Tip> {
+        self.tip.lock().unwrap().clone()
+    }
+}
+
+/// Whether a `getblocktemplate` request is a long polling one (BIP 22).
+pub fn is_longpoll(request: &Value) -> bool {
+    request["method"] == "getblocktemplate" && request["params"][0].get("longpollid").is_some()
+}
+
+fn block_hash(header: &[u8]) -> String {
+    let mut hash = sha256d(header);
+    hash.reverse();
+    hex::encode(hash)
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as f64
+}
+
+/// `GET /tip` returns the current chain tip, `null` until the first one is known.
+pub fn routes(
+    tracker: TipTracker,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("tip")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || warp::reply::json(&tracker.current()))
+}
+
+/// Measures, from the proxy between the miners and the SV1 pool, the time between a chain tip
+/// change and the first `mining.notify` on top of it.
+#[derive(Clone)]
+pub struct NotifyDelay {
+    client: Client,
+    // The proxy between the SV1 pool and the node, `NODE_POOL_PROXY_URL`
+    node_pool_proxy: String,
+    // Hash of the last tip the delay was measured for
+    measured: Arc<Mutex<Option<String>>>,
+    delay: Gauge,
+}
+
+impl NotifyDelay {
+    pub fn register() -> Self {
+        Self {
+            client: Client::new(),
+            node_pool_proxy: env::var("NODE_POOL_PROXY_URL")
+                .unwrap_or_else(|_| DEFAULT_NODE_POOL_PROXY_URL.to_string()),
+            measured: Arc::new(Mutex::new(None)),
+            delay: register_gauge!(
+                "sv1_tip_to_notify_delay",
+                "Time between a chain tip change and the first mining.notify on top of it in milliseconds"
+            )
+            .unwrap(),
+        }
+    }
+
+    pub fn node_pool_proxy(&self) -> &str {
+        &self.node_pool_proxy
+    }
+
+    /// Handles a `mining.notify` sent to a miner at `timestamp`.
+    pub async fn notify(&self, json: &Value, timestamp: f64) {
+        let Some(prev_hash) = json["params"][1].as_str().and_then(stratum_prev_hash) else {
+            return;
+        };
+        if self.measured.lock().unwrap().as_deref() == Some(prev_hash.as_str()) {
+            return;
+        }
+        // The proxy between the SV1 pool and the node follows the tip
+        let url = format!("{}/tip", self.node_pool_proxy);
+        let tip: Option<Tip> = match self.client.get(url).send().await {
+            Ok(response) => response.json().await.unwrap_or_else(|e| {
+                log::error!("Invalid tip response: {}", e);
+                None
+            }),
+            Err(e) => {
+                log::error!("Failed to get the chain tip: {}", e);
+                None
+            }
+        };
+        let Some(tip) = tip.filter(|tip| tip.hash == prev_hash) else {
+            return;
+        };
+        let mut measured = self.measured.lock().unwrap();
+        if measured.as_deref() == Some(prev_hash.as_str()) {
+            return;
+        }
+        *measured = Some(prev_hash);
+        measurements::recorder::set(&self.delay, timestamp - tip.changed_at);
+    }
+}
+
+// Stratum sends the previous block hash in internal byte order with every 4 bytes swapped
+pub fn stratum_prev_hash(prev_hash: &str) -> Option<String> {
+    let mut bytes = hex::decode(prev_hash).ok()?;
+    if bytes.len() != 32 {
+        return None;
+    }
+    for word in bytes.chunks_mut(4) {
+        word.reverse();
+    }
+    bytes.reverse();
+    Some(hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stratum_prev_hashes() {
+        // The prevhash of the mining.notify example of the stratum documentation
+        assert_eq!(
+            stratum_prev_hash("00000000440b921e1b77c6c0487ae5616de67f788f44ae2a5af6e2194d16b6f8")
+                .as_deref(),
+            Some("4d16b6f85af6e2198f44ae2a6de67f78487ae5611b77c6c0440b921e00000000")
+        );
+        assert_eq!(stratum_prev_hash("00000000440b921e"), None);
+        assert_eq!(stratum_prev_hash(&"zz".repeat(32)), None);
+    }
+}
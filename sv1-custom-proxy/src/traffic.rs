@@ -0,0 +1,92 @@
+use prometheus::{register_counter_vec, CounterVec};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Clone)]
+pub struct TrafficMetrics {
+    messages: CounterVec,
+    bytes: CounterVec,
+}
+
+impl TrafficMetrics {
+    pub fn register() -> Self {
+        Self {
+            messages: register_counter_vec!(
+                "sv1_traffic_messages",
+                "Total number of SV1 messages by direction and message type",
+                &["direction", "message_type"]
+            )
+            .unwrap(),
+            bytes: register_counter_vec!(
+                "sv1_traffic_bytes",
+                "Total number of SV1 bytes on the wire by direction and message type",
+                &["direction", "message_type"]
+            )
+            .unwrap(),
+        }
+    }
+
+    pub fn connection(&self) -> ConnectionTraffic {
+        ConnectionTraffic {
+            metrics: self.clone(),
+            requests: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+/// Accounts the traffic of a single connection. Responses carry no method, so the method of
+/// the request they answer is looked up by id and reported as `<method>.result`.
+pub struct ConnectionTraffic {
+    metrics: TrafficMetrics,
+    requests: Mutex<HashMap<String, String>>,
+}
+
+impl ConnectionTraffic {
+    /// Returns the message type of a line received from either side, as used in the labels.
+    pub fn message_type(&self, line: &[u8]) -> String {
+        match serde_json::from_slice::<Value>(line) {
+            Ok(json) => self.json_message_type(&json),
+            Err(_) => "invalid".to_string(),
+        }
+    }
+
+    /// Records a line sent by the miner (downstream) to the pool (upstream).
+    pub fn upstream(&self, message_type: &str, line: &[u8]) {
+        self.record("upstream", message_type, line);
+    }
+
+    /// Records a line sent by the pool (upstream) to the miner (downstream).
+    pub fn downstream(&self, message_type: &str, line: &[u8]) {
+        self.record("downstream", message_type, line);
+    }
+
+    fn record(&self, direction: &str, message_type: &str, line: &[u8]) {
+        self.metrics
+            .messages
+            .with_label_values(&[direction, message_type])
+            .inc();
+        self.metrics
+            .bytes
+            .with_label_values(&[direction, message_type])
+            .inc_by(line.len() as f64);
+    }
+
+    fn json_message_type(&self, json: &Value) -> String {
+        let id = match &json["id"] {
+            Value::Null => None,
+            id => Some(id.to_string()),
+        };
+        if let Some(method) = json["method"].as_str() {
+            // Notifications have no id and get no response
+            if let Some(id) = id {
+                self.requests.lock().unwrap().insert(id, method.to_string());
+            }
+            method.to_string()
+        } else {
+            id.and_then(|id| self.requests.lock().unwrap().remove(&id))
+                .map(|method| format!("{}.result", method))
+                .unwrap_or_else(|| "result".to_string())
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use prometheus::{register_gauge, register_gauge_vec, Gauge, GaugeVec};
+use reqwest::Client;
+use serde_json::Value;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Metrics endpoints of the proxy between the Template Provider and the JDC or the pool, and of
+// the proxy between them and the translator
+const DEFAULT_TP_PROXY_URL: &str = "http://10.5.0.20:5678";
+const DEFAULT_TRANSLATOR_PROXY_URL: &str = "http://10.5.0.17:3456";
+// The lookups are made off the forwarding path, but a stalled one would still pile up
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The SV2 role feeding the translator: the JDC in config A, the pool in config C.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UpstreamPath {
+    Jdc,
+    Pool,
+}
+
+impl UpstreamPath {
+    const ALL: [UpstreamPath; 2] = [UpstreamPath::Jdc, UpstreamPath::Pool];
+
+    fn name(self) -> &'static str {
+        match self {
+            UpstreamPath::Jdc => "jdc",
+            UpstreamPath::Pool => "pool",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|path| path.name() == name)
+    }
+
+    // Metrics of the proxy between the Template Provider and the JDC or the pool, labelled by
+    // prevhash and by template id
+    fn prev_hash_metric(self) -> String {
+        format!("sv2_new_job_prev_hash_timestamp_{}{{", self.name())
+    }
+
+    fn template_metric(self) -> String {
+        format!("sv2_new_job_timestamp_{}{{", self.name())
+    }
+}
+
+struct PathGauges {
+    prev_hash: Gauge,
+    template: Gauge,
+}
+
+/// New job latencies of the translator-miner proxy, kept apart for each upstream path. The path
+/// is `UPSTREAM_PATH` (`jdc` or `pool`) or, when not set, the one the Template Provider proxy
+/// exports timestamps for.
+#[derive(Clone)]
+pub struct NewJobMetrics {
+    client: Client,
+    // `TP_PROXY_URL`
+    tp_proxy: String,
+    configured: Option<UpstreamPath>,
+    active: GaugeVec,
+    jdc: Arc<PathGauges>,
+    pool: Arc<PathGauges>,
+}
+
+impl NewJobMetrics {
+    pub fn register() -> Self {
+        let configured = env::var("UPSTREAM_PATH").ok().map(|path| {
+            UpstreamPath::from_name(&path)
+                .unwrap_or_else(|| panic!("Invalid UPSTREAM_PATH {}, expected jdc or pool", path))
+        });
+        let metrics = Self {
+            client: scrape_client(),
+            tp_proxy: env::var("TP_PROXY_URL").unwrap_or_else(|_| DEFAULT_TP_PROXY_URL.to_string()),
+            configured,
+            active: register_gauge_vec!(
+                "sv2_translator_upstream_path",
+                "1 for the SV2 path (jdc or pool) feeding the translator",
+                &["path"]
+            )
+            .unwrap(),
+            jdc: Arc::new(PathGauges {
+                prev_hash: register_gauge!(
+                    "new_job_prev_hash_throught_sv2_jdc",
+                    "Time required to complete one tp->jdc , translator->node round of new job prev hash"
+                )
+                .unwrap(),
+                template: register_gauge!("new_job_jdc_new_template", "new job jdc new template")
+                    .unwrap(),
+            }),
+            pool: Arc::new(PathGauges {
+                prev_hash: register_gauge!(
+                    "new_job_prev_hash_through_sv2_pool",
+                    "Time required to complete one tp->pool , translator->node round of new job prev hash"
+                )
+                .unwrap(),
+                template: register_gauge!(
+                    "new_job_pool_new_template",
+                    "new job pool new template"
+                )
+                .unwrap(),
+            }),
+        };
+        if let Some(path) = configured {
+            metrics.set_active(path);
+        }
+        metrics
+    }
+
+    fn gauges(&self, path: UpstreamPath) -> &PathGauges {
+        match path {
+            UpstreamPath::Jdc => &self.jdc,
+            UpstreamPath::Pool => &self.pool,
+        }
+    }
+
+    fn set_active(&self, active: UpstreamPath) {
+        for path in UpstreamPath::ALL {
+            let value = if path == active { 1.0 } else { 0.0 };
+            self.active.with_label_values(&[path.name()]).set(value);
+        }
+    }
+
+    /// Scrapes the Template Provider proxy for a `mining.notify` sent at `timestamp`.
+    pub async fn measure(&self, timestamp: f64) {
+        let prometheus_url = format!("{}/metrics", self.tp_proxy);
+        if let Some(body) = scrape(&self.client, &prometheus_url).await {
+            self.notify(&body, timestamp);
+        }
+    }
+
+    /// Updates the gauges of the active path from the metrics of the Template Provider proxy,
+    /// for a `mining.notify` sent at `timestamp`.
+    pub fn notify(&self, metrics: &str, timestamp: f64) {
+        let detected = UpstreamPath::ALL.into_iter().find(|path| {
+            let (prev_hash, template) = (path.prev_hash_metric(), path.template_metric());
+            metrics
+                .lines()
+                .any(|line| line.starts_with(&prev_hash) || line.starts_with(&template))
+        });
+        let path = match (self.configured, detected) {
+            (Some(configured), Some(detected)) if configured != detected => {
+                log::warn!(
+                    "UPSTREAM_PATH is {} but the Template Provider proxy feeds the {}",
+                    configured.name(),
+                    detected.name()
+                );
+                return;
+            }
+            (Some(path), _) | (None, Some(path)) => path,
+            (None, None) => return,
+        };
+        self.set_active(path);
+
+        let gauges = self.gauges(path);
+        let (prev_hash, template) = (path.prev_hash_metric(), path.template_metric());
+        for line in metrics.lines() {
+            let gauge = if line.starts_with(&prev_hash) {
+                &gauges.prev_hash
+            } else if line.starts_with(&template) {
+                &gauges.template
+            } else {
+                continue;
+            };
+            match line
+                .rsplit_once(' ')
+                .and_then(|(_, value)| value.trim().parse::<f64>().ok())
+            {
+                Some(new_job_timestamp) => {
+                    measurements::recorder::set(gauge, timestamp - new_job_timestamp)
+                }
+                None => log::warn!("No timestamp value found in {}", line),
+            }
+        }
+    }
+}
+
+/// Latency of the `mining.notify` sent by the translator, from the SV2 message it was built on.
+#[derive(Clone)]
+pub struct NotifyLatency {
+    client: Client,
+    // `TRANSLATOR_PROXY_URL`
+    translator_proxy: String,
+    job_to_notify: Gauge,
+    prev_hash_to_notify: Gauge,
+}
+
+impl NotifyLatency {
+    pub fn register() -> Self {
+        Self {
+            client: scrape_client(),
+            translator_proxy: env::var("TRANSLATOR_PROXY_URL")
+                .unwrap_or_else(|_| DEFAULT_TRANSLATOR_PROXY_URL.to_string()),
+            job_to_notify: register_gauge!(
+                "sv2_job_to_notify_latency",
+                "Time between the NewExtendedMiningJob reaching the translator and the corresponding mining.notify in milliseconds"
+            )
+            .unwrap(),
+            prev_hash_to_notify: register_gauge!(
+                "sv2_prev_hash_to_notify_latency",
+                "Time between the mining SetNewPrevHash reaching the translator and the corresponding mining.notify in milliseconds"
+            )
+            .unwrap(),
+        }
+    }
+
+    /// Measures the `mining.notify` with `params` sent at `timestamp`. Clean jobs are sent when
+    /// a SetNewPrevHash activates the job, the others as soon as the NewExtendedMiningJob is
+    /// received.
+    pub async fn notify(&self, params: &[Value], timestamp: f64) {
+        let Some(job_id) = params.first().and_then(|id| id.as_str()) else {
+            return;
+        };
+        let clean_jobs = params
+            .get(8)
+            .and_then(|clean| clean.as_bool())
+            .unwrap_or(false);
+        let (metric, gauge) = if clean_jobs {
+            ("sv2_mining_prev_hash_timestamp", &self.prev_hash_to_notify)
+        } else {
+            ("sv2_new_extended_mining_job_timestamp", &self.job_to_notify)
+        };
+        if let Some(job_timestamp) = self.fetch_job_timestamp(metric, job_id).await {
+            measurements::recorder::set(gauge, timestamp - job_timestamp);
+        }
+    }
+
+    // The proxy between the SV2 upstream and the translator keeps the job timestamps labelled
+    // by job id, which the translator reuses as mining.notify job id.
+    async fn fetch_job_timestamp(&self, metric: &str, job_id: &str) -> Option<f64> {
+        let prometheus_url = format!("{}/metrics", self.translator_proxy);
+        let body = scrape(&self.client, &prometheus_url).await?;
+        let series = format!("{}{{job_id=\"{}\"}}", metric, job_id);
+        body.lines()
+            .find(|line| line.starts_with(&series))
+            .and_then(|line| line.rsplit_once(' '))
+            .and_then(|(_, timestamp)| timestamp.trim().parse::<f64>().ok())
+    }
+}
+
+fn scrape_client() -> Client {
+    Client::builder()
+        .timeout(SCRAPE_TIMEOUT)
+        .build()
+        .expect("reqwest client")
+}
+
+async fn scrape(client: &Client, url: &str) -> Option<String> {
+    match client.get(url).send().await {
+        Ok(response) => response.text().await.ok(),
+        Err(e) => {
+            log::warn!("Failed to scrape {}: {}", url, e);
+            None
+        }
+    }
+}
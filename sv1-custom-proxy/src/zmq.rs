@@ -0,0 +1,165 @@
+//! Just enough of ZMTP 3.0 (NULL mechanism) to subscribe to the notifications of bitcoind and
+//! to stand in for it when testing.
+
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const MORE: u8 = 0x01;
+const LONG: u8 = 0x02;
+const COMMAND: u8 = 0x04;
+
+/// Strips the `tcp://` scheme of a ZMQ endpoint.
+pub fn address(endpoint: &str) -> &str {
+    endpoint.strip_prefix("tcp://").unwrap_or(endpoint)
+}
+
+fn greeting() -> [u8; 64] {
+    let mut greeting = [0u8; 64];
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+    // Version 3.0
+    greeting[10] = 3;
+    greeting[11] = 0;
+    greeting[12..16].copy_from_slice(b"NULL");
+    greeting
+}
+
+fn ready(socket_type: &str) -> Vec<u8> {
+    let mut body = vec![5];
+    body.extend_from_slice(b"READY");
+    body.push(11);
+    body.extend_from_slice(b"Socket-Type");
+    body.extend_from_slice(&(socket_type.len() as u32).to_be_bytes());
+    body.extend_from_slice(socket_type.as_bytes());
+    body
+}
+
+/// Exchanges the greetings and the READY commands, announcing `socket_type`.
+pub async fn handshake(stream: &mut TcpStream, socket_type: &str) -> io::Result<()> {
+    stream.write_all(&greeting()).await?;
+    let mut peer_greeting = [0u8; 64];
+    stream.read_exact(&mut peer_greeting).await?;
+    if peer_greeting[0] != 0xff || peer_greeting[9] != 0x7f || peer_greeting[10] < 3 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not a ZMTP 3 peer",
+        ));
+    }
+    write_frame(stream, COMMAND, &ready(socket_type)).await?;
+    let (flags, _) = read_frame(stream).await?;
+    if flags & COMMAND == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Expected the READY command",
+        ));
+    }
+    Ok(())
+}
+
+async fn write_frame(stream: &mut TcpStream, flags: u8, body: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(body.len() + 9);
+    if body.len() > u8::MAX as usize {
+        frame.push(flags | LONG);
+        frame.extend_from_slice(&(body.len() as u64).to_be_bytes());
+    } else {
+        frame.push(flags);
+        frame.push(body.len() as u8);
+    }
+    frame.extend_from_slice(body);
+    stream.write_all(&frame).await
+}
+
+async fn read_frame(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let flags = stream.read_u8().await?;
+    let len = if flags & LONG != 0 {
+        stream.read_u64().await? as usize
+    } else {
+        stream.read_u8().await? as usize
+    };
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).await?;
+    Ok((flags, body))
+}
+
+/// Sends a multipart message.
+pub async fn send(stream: &mut TcpStream, parts: &[&[u8]]) -> io::Result<()> {
+    for (index, part) in parts.iter().enumerate() {
+        let flags = if index + 1 < parts.len() { MORE } else { 0 };
+        write_frame(stream, flags, part).await?;
+    }
+    Ok(())
+}
+
+/// Receives the next multipart message, skipping commands.
+pub async fn recv(stream: &mut TcpStream) -> io::Result<Vec<Vec<u8>>> {
+    let mut parts = Vec::new();
+    loop {
+        let (flags, body) = read_frame(stream).await?;
+        if flags & COMMAND != 0 {
+            continue;
+        }
+        parts.push(body);
+        if flags & MORE == 0 {
+            return Ok(parts);
+        }
+    }
+}
+
+/// Subscribes to the messages whose topic starts with `topic`.
+pub async fn subscribe(stream: &mut TcpStream, topic: &str) -> io::Result<()> {
+    // ZMTP 3.0 subscriptions are messages starting with 1
+    let mut body = vec![1];
+    body.extend_from_slice(topic.as_bytes());
+    send(stream, &[&body]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn addresses() {
+        assert_eq!(address("tcp://10.5.0.2:28332"), "10.5.0.2:28332");
+        assert_eq!(address("10.5.0.2:28332"), "10.5.0.2:28332");
+    }
+
+    #[tokio::test]
+    async fn publish_and_subscribe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("tcp://{}", listener.local_addr().unwrap());
+        // A block is longer than a short frame
+        let raw_block = vec![0xab; 300];
+        let message = vec![b"rawblock".to_vec(), raw_block, 7u32.to_le_bytes().to_vec()];
+
+        let published = message.clone();
+        let publisher = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            handshake(&mut stream, "PUB").await.unwrap();
+            let subscription = recv(&mut stream).await.unwrap();
+            let parts: Vec<&[u8]> = published.iter().map(Vec::as_slice).collect();
+            send(&mut stream, &parts).await.unwrap();
+            subscription
+        });
+
+        let mut stream = TcpStream::connect(address(&endpoint)).await.unwrap();
+        handshake(&mut stream, "SUB").await.unwrap();
+        subscribe(&mut stream, "rawblock").await.unwrap();
+        assert_eq!(recv(&mut stream).await.unwrap(), message);
+        assert_eq!(publisher.await.unwrap(), vec![b"\x01rawblock".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn other_peers_are_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(&[b'{'; 64]).await.unwrap();
+        });
+
+        let mut stream = TcpStream::connect(peer).await.unwrap();
+        let error = handshake(&mut stream, "SUB").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
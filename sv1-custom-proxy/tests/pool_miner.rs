@@ -0,0 +1,325 @@
+//! Runs the `pool-miner` proxy between a scripted mock pool and a test miner.
+
+use mock_sv1_pool::{Event, MockPool, Reject, Script, Step};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+use tokio::time::{sleep, timeout, Duration};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const PREV_HASH_A: &str = "aaaaaaaa00000000000000000000000000000000000000000000000000000000";
+const PREV_HASH_B: &str = "bbbbbbbb00000000000000000000000000000000000000000000000000000000";
+
+struct Proxy {
+    _process: Child,
+    address: SocketAddr,
+    prometheus_address: SocketAddr,
+}
+
+impl Proxy {
+    fn start(pool: &MockPool) -> Self {
+        let address = free_address();
+        let prometheus_address = free_address();
+        let process = Command::new(env!("CARGO_BIN_EXE_sv1-custom-proxy"))
+            .env("PROXY_TYPE", "pool-miner")
+            .env("CLIENT", address.to_string())
+            .env("SERVER", pool.address().to_string())
+            .env("PROM_ADDRESS", prometheus_address.to_string())
+            // Nothing listens there, the notify latencies are just not measured
+            .env("NODE_POOL_PROXY_URL", format!("http://{}", free_address()))
+            .kill_on_drop(true)
+            .spawn()
+            .expect("Failed to start the proxy");
+        Self {
+            _process: process,
+            address,
+            prometheus_address,
+        }
+    }
+
+    async fn get(&self, path: &str) -> String {
+        reqwest::get(format!("http://{}{}", self.prometheus_address, path))
+            .await
+            .expect("Proxy HTTP server unreachable")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Value of a series of the proxy, `name{labels}` as exported.
+    async fn metric(&self, series: &str) -> f64 {
+        self.get("/metrics")
+            .await
+            .lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+            .map(|value| value.parse().unwrap())
+            .unwrap_or_else(|| panic!("{} not exported", series))
+    }
+}
+
+struct Miner {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+    next_id: u64,
+    // Notifications received while waiting for a response
+    notifications: VecDeque<Value>,
+}
+
+impl Miner {
+    /// Connects to the proxy, waiting for it to listen.
+    async fn connect(proxy: &Proxy) -> Self {
+        let stream = timeout(TIMEOUT, async {
+            loop {
+                match TcpStream::connect(proxy.address).await {
+                    Ok(stream) => return stream,
+                    Err(_) => sleep(Duration::from_millis(50)).await,
+                }
+            }
+        })
+        .await
+        .expect("Proxy not listening");
+        let (reader, writer) = stream.into_split();
+        Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            next_id: 1,
+            notifications: VecDeque::new(),
+        }
+    }
+
+    async fn receive(&mut self) -> Option<Value> {
+        let line = timeout(TIMEOUT, self.lines.next_line())
+            .await
+            .expect("Nothing received from the proxy")
+            .unwrap()?;
+        Some(serde_json::from_str(&line).unwrap())
+    }
+
+    /// Sends a request and returns its response.
+    async fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = json!({"id": id, "method": method, "params": params});
+        self.writer
+            .write_all(format!("{}\n", request).as_bytes())
+            .await
+            .unwrap();
+        loop {
+            let message = self.receive().await.expect("Connection closed");
+            if message["id"] == id {
+                return message;
+            }
+            self.notifications.push_back(message);
+        }
+    }
+
+    /// Returns the next notification with `method`, skipping the others.
+    async fn notification(&mut self, method: &str) -> Value {
+        loop {
+            let message = match self.notifications.pop_front() {
+                Some(message) => message,
+                None => self.receive().await.expect("Connection closed"),
+            };
+            if message["method"] == method {
+                return message;
+            }
+        }
+    }
+
+    async fn handshake(&mut self) {
+        self.request("mining.configure", json!([["version-rolling"], {}]))
+            .await;
+        self.request("mining.subscribe", json!(["test-miner"]))
+            .await;
+        let authorized = self
+            .request("mining.authorize", json!(["test-miner.0", "x"]))
+            .await;
+        assert_eq!(authorized["result"], true);
+    }
+
+    async fn submit(&mut self, job_id: &str, nonce: &str, version_bits: &str) -> Value {
+        self.request(
+            "mining.submit",
+            json!([
+                "test-miner.0",
+                job_id,
+                "00000000",
+                "66000000",
+                nonce,
+                version_bits
+            ]),
+        )
+        .await
+    }
+}
+
+fn free_address() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn notify(prev_hash: &str) -> Step {
+    Step::after(
+        Duration::ZERO,
+        Event::Notify {
+            prev_hash: prev_hash.to_string(),
+            clean_jobs: true,
+        },
+    )
+}
+
+#[tokio::test]
+async fn counts_submitted_valid_and_stale_shares() {
+    let pool = MockPool::start(
+        "127.0.0.1:0",
+        Script {
+            reject: vec![Reject::Every(2)],
+            steps: vec![notify(PREV_HASH_A)],
+            ..Script::default()
+        },
+    )
+    .await
+    .unwrap();
+    let proxy = Proxy::start(&pool);
+    let mut miner = Miner::connect(&proxy).await;
+    miner.handshake().await;
+    miner.notification("mining.notify").await;
+
+    for nonce in ["00000001", "00000002", "00000003", "00000004"] {
+        miner.submit("0", nonce, "00000000").await;
+    }
+
+    assert_eq!(pool.stats().accepted, 2);
+    assert_eq!(pool.stats().rejected, 2);
+    assert_eq!(proxy.metric("sv1_submitted_shares").await, 4.0);
+    // The response to mining.authorize is not a share
+    assert_eq!(proxy.metric("sv1_valid_shares").await, 2.0);
+    assert_eq!(proxy.metric("sv1_stale_shares").await, 2.0);
+}
+
+#[tokio::test]
+async fn forwards_notify_and_counts_shares_of_old_jobs_as_stale() {
+    let pool = MockPool::start(
+        "127.0.0.1:0",
+        Script {
+            reject: vec![Reject::Stale, Reject::UnknownJob],
+            steps: vec![notify(PREV_HASH_A), notify(PREV_HASH_B)],
+            ..Script::default()
+        },
+    )
+    .await
+    .unwrap();
+    let proxy = Proxy::start(&pool);
+    let mut miner = Miner::connect(&proxy).await;
+    miner.handshake().await;
+    let first = miner.notification("mining.notify").await;
+    let second = miner.notification("mining.notify").await;
+    assert_eq!(first["params"][1], PREV_HASH_A);
+    assert_eq!(second["params"][1], PREV_HASH_B);
+
+    let stale = miner.submit("0", "00000001", "00000000").await;
+    let accepted = miner.submit("1", "00000002", "00000000").await;
+    let unknown = miner.submit("ff", "00000003", "00000000").await;
+
+    assert_eq!(stale["error"][0], 21);
+    assert_eq!(accepted["result"], true);
+    assert_eq!(unknown["error"][0], 21);
+    assert_eq!(proxy.metric("sv1_valid_shares").await, 1.0);
+    assert_eq!(proxy.metric("sv1_stale_shares").await, 2.0);
+    assert_eq!(
+        proxy
+            .metric(r#"sv1_traffic_messages{direction="downstream",message_type="mining.notify"}"#)
+            .await,
+        2.0
+    );
+}
+
+#[tokio::test]
+async fn stores_shares_with_the_version_of_their_job() {
+    let pool = MockPool::start(
+        "127.0.0.1:0",
+        Script {
+            version: 0x2000_0000,
+            version_rolling_mask: 0x1fff_e000,
+            steps: vec![notify(PREV_HASH_A)],
+            ..Script::default()
+        },
+    )
+    .await
+    .unwrap();
+    let proxy = Proxy::start(&pool);
+    let mut miner = Miner::connect(&proxy).await;
+    miner.handshake().await;
+    miner.notification("mining.notify").await;
+
+    // Bits outside of the negotiated mask are not rolled
+    miner.submit("0", "0000abcd", "e0006000").await;
+
+    // The previous block hash of the job, in the usual byte order instead of the stratum one
+    let prev_hash = format!("{}aaaaaaaa", "0".repeat(56));
+    let shares: Value = serde_json::from_str(
+        &proxy
+            .get(&format!(
+                "/shares?prev_hash={}&nonce={}&ntime={}&version={}",
+                prev_hash, 0xabcd, 0x6600_0000u32, 0x2000_6000u32
+            ))
+            .await,
+    )
+    .unwrap();
+    assert_eq!(shares.as_array().map(Vec::len), Some(1));
+    assert_eq!(shares[0]["job_id"], "0");
+}
+
+#[tokio::test]
+async fn forwards_difficulty_changes_and_reconnects() {
+    let pool = MockPool::start(
+        "127.0.0.1:0",
+        Script {
+            difficulty: 2.0,
+            steps: vec![
+                notify(PREV_HASH_A),
+                Step::after(Duration::from_millis(50), Event::SetDifficulty(0.5)),
+                Step::after(
+                    Duration::from_millis(50),
+                    Event::Reconnect {
+                        host: "127.0.0.1".to_string(),
+                        port: 3333,
+                        wait_s: 0,
+                    },
+                ),
+                Step::after(Duration::from_millis(50), Event::Disconnect),
+            ],
+            ..Script::default()
+        },
+    )
+    .await
+    .unwrap();
+    let proxy = Proxy::start(&pool);
+    let mut miner = Miner::connect(&proxy).await;
+    miner.handshake().await;
+
+    let initial = miner.notification("mining.set_difficulty").await;
+    let changed = miner.notification("mining.set_difficulty").await;
+    let reconnect = miner.notification("client.reconnect").await;
+    assert_eq!(initial["params"], json!([2.0]));
+    assert_eq!(changed["params"], json!([0.5]));
+    assert_eq!(reconnect["params"], json!(["127.0.0.1", 3333, 0]));
+    // The proxy closes the connection of the miner with the one of the pool
+    while miner.receive().await.is_some() {}
+
+    assert_eq!(
+        proxy
+            .metric(
+                r#"sv1_traffic_messages{direction="downstream",message_type="client.reconnect"}"#
+            )
+            .await,
+        1.0
+    );
+    assert_eq!(pool.stats().connections, 1);
+}
@@ -0,0 +1,472 @@
+use crate::job::{Job, Submission};
+use bench_common::hash;
+use bench_common::hasher::{self, Merkle};
+use log::{error, info, warn};
+use prometheus::{register_counter_vec, register_gauge_vec, CounterVec, GaugeVec};
+use rand::Rng;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep, sleep_until, Duration, Instant};
+
+// Shares are submitted for the job and version mask they were found with
+type Work = hasher::Work<Submission>;
+type Share = hasher::Share<Submission>;
+
+// Wait before connecting again after the pool refused or closed the connection
+const RETRY: Duration = Duration::from_secs(5);
+// Difficulty until the pool sends mining.set_difficulty
+const DEFAULT_DIFFICULTY: f64 = 1.0;
+// Version bits a device asks to roll (BIP 320)
+const VERSION_ROLLING_MASK: u32 = 0x1fff_e000;
+
+/// What every simulated device is configured with.
+pub struct Settings {
+    pub pool_address: String,
+    pub user: String,
+    pub password: String,
+    // Hashes per second of each device
+    pub hashrate: f64,
+    // Difficulty suggested to the pool, when set
+    pub difficulty: Option<f64>,
+    pub version_rolling: bool,
+    // Delay added to every message, both ways, and the random jitter on top of it
+    pub latency: Duration,
+    pub jitter: Duration,
+}
+
+#[derive(Clone)]
+pub struct DeviceMetrics {
+    hashes: CounterVec,
+    shares: CounterVec,
+    jobs: CounterVec,
+    blocks: CounterVec,
+    difficulty: GaugeVec,
+}
+
+impl DeviceMetrics {
+    pub fn register() -> Self {
+        Self {
+            hashes: register_counter_vec!(
+                "sv1_simulated_hashes",
+                "Total number of headers hashed by the simulated devices",
+                &["device"]
+            )
+            .unwrap(),
+            shares: register_counter_vec!(
+                "sv1_simulated_shares",
+                "Total number of shares of the simulated devices by result (submitted, accepted, rejected)",
+                &["device", "result"]
+            )
+            .unwrap(),
+            jobs: register_counter_vec!(
+                "sv1_simulated_jobs",
+                "Total number of jobs received by the simulated devices",
+                &["device"]
+            )
+            .unwrap(),
+            blocks: register_counter_vec!(
+                "sv1_simulated_blocks",
+                "Total number of shares of the simulated devices meeting the network target",
+                &["device"]
+            )
+            .unwrap(),
+            difficulty: register_gauge_vec!(
+                "sv1_simulated_difficulty",
+                "Difficulty of the last job of the simulated devices",
+                &["device"]
+            )
+            .unwrap(),
+        }
+    }
+}
+
+// The requests waiting for their response, by id
+enum Request {
+    Configure,
+    Subscribe,
+    Authorize,
+    SuggestDifficulty,
+    Submit,
+}
+
+/// A simulated device: a hashing thread fed with the jobs of one SV1 connection, reconnecting
+/// whenever the connection ends.
+pub async fn run(index: usize, settings: Arc<Settings>, metrics: DeviceMetrics) {
+    let (work_sender, work) = watch::channel(None);
+    let (share_sender, mut shares) = mpsc::unbounded_channel();
+    let hashrate = settings.hashrate;
+    let hashes = metrics.hashes.with_label_values(&[&index.to_string()]);
+    std::thread::spawn(move || hasher::run(work, share_sender, hashrate, hashes));
+
+    let mut pool_address = settings.pool_address.clone();
+    loop {
+        let stream = match TcpStream::connect(&pool_address).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Device {} can't connect to {}: {}", index, pool_address, e);
+                sleep(RETRY).await;
+                continue;
+            }
+        };
+        info!("Device {} connected to {}", index, pool_address);
+        // Shares of the previous connection are not submitted
+        while shares.try_recv().is_ok() {}
+        let mut session = Session::new(index, &pool_address, &settings, &metrics, &work_sender);
+        match session.run(stream, &mut shares).await {
+            Some((address, wait)) => {
+                info!("Device {} asked to reconnect to {}", index, address);
+                pool_address = address;
+                sleep(wait).await;
+            }
+            None => {
+                warn!("Device {} disconnected from {}", index, pool_address);
+                sleep(RETRY).await;
+            }
+        }
+        work_sender.send_replace(None);
+    }
+}
+
+struct Session<'a> {
+    device: String,
+    pool_address: String,
+    settings: &'a Settings,
+    metrics: &'a DeviceMetrics,
+    work: &'a watch::Sender<Option<Work>>,
+    worker: String,
+    next_id: u64,
+    pending: HashMap<u64, Request>,
+    extranonce_1: Vec<u8>,
+    extranonce_2_size: usize,
+    difficulty: f64,
+    version_mask: u32,
+}
+
+impl<'a> Session<'a> {
+    fn new(
+        index: usize,
+        pool_address: &str,
+        settings: &'a Settings,
+        metrics: &'a DeviceMetrics,
+        work: &'a watch::Sender<Option<Work>>,
+    ) -> Self {
+        Self {
+            device: index.to_string(),
+            pool_address: pool_address.to_string(),
+            settings,
+            metrics,
+            work,
+            worker: format!("{}.{}", settings.user, index),
+            next_id: 1,
+            pending: HashMap::new(),
+            extranonce_1: Vec::new(),
+            extranonce_2_size: 0,
+            difficulty: DEFAULT_DIFFICULTY,
+            version_mask: 0,
+        }
+    }
+
+    /// Runs the connection until it ends, returning where to reconnect (and after how long)
+    /// when the pool sent `client.reconnect`.
+    async fn run(
+        &mut self,
+        stream: TcpStream,
+        shares: &mut mpsc::UnboundedReceiver<Share>,
+    ) -> Option<(String, Duration)> {
+        let (reader, mut writer) = stream.into_split();
+        let (outgoing, to_pool) = mpsc::unbounded_channel::<(Instant, String)>();
+        let (from_pool, incoming) = mpsc::unbounded_channel::<(Instant, String)>();
+        let mut to_pool = self.delayed(to_pool);
+        let incoming = self.delayed(incoming);
+        tokio::spawn(async move {
+            while let Some(line) = to_pool.recv().await {
+                if writer.write_all(line.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let reading = tokio::spawn(async move {
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if from_pool.send((Instant::now(), line)).is_err() {
+                    break;
+                }
+            }
+        });
+        let reconnect = self.exchange(outgoing, incoming, shares).await;
+        // Closes the connection, the writer stopping with the outgoing messages
+        reading.abort();
+        reconnect
+    }
+
+    async fn exchange(
+        &mut self,
+        outgoing: mpsc::UnboundedSender<(Instant, String)>,
+        mut incoming: mpsc::UnboundedReceiver<String>,
+        shares: &mut mpsc::UnboundedReceiver<Share>,
+    ) -> Option<(String, Duration)> {
+        if self.settings.version_rolling {
+            self.request(
+                &outgoing,
+                Request::Configure,
+                "mining.configure",
+                json!([
+                    ["version-rolling"],
+                    {
+                        "version-rolling.mask": format!("{:08x}", VERSION_ROLLING_MASK),
+                        "version-rolling.min-bit-count": 2
+                    }
+                ]),
+            );
+        }
+        self.request(
+            &outgoing,
+            Request::Subscribe,
+            "mining.subscribe",
+            json!([concat!("sv1-miner-simulator/", env!("CARGO_PKG_VERSION"))]),
+        );
+        let params = json!([self.worker, self.settings.password]);
+        self.request(&outgoing, Request::Authorize, "mining.authorize", params);
+        if let Some(difficulty) = self.settings.difficulty {
+            self.request(
+                &outgoing,
+                Request::SuggestDifficulty,
+                "mining.suggest_difficulty",
+                json!([difficulty]),
+            );
+        }
+
+        loop {
+            tokio::select! {
+                line = incoming.recv() => {
+                    let line = line?;
+                    match serde_json::from_str::<Value>(&line) {
+                        Ok(message) => {
+                            if let Some(reconnect) = self.handle(&message) {
+                                return Some(reconnect);
+                            }
+                        }
+                        Err(e) => warn!("Device {} received an invalid message: {}", self.device, e),
+                    }
+                }
+                Some(share) = shares.recv() => self.submit(&outgoing, share),
+            }
+        }
+    }
+
+    fn request(
+        &mut self,
+        outgoing: &mpsc::UnboundedSender<(Instant, String)>,
+        request: Request,
+        method: &str,
+        params: Value,
+    ) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.insert(id, request);
+        let message = json!({"id": id, "method": method, "params": params});
+        let _ = outgoing.send((Instant::now(), format!("{}\n", message)));
+    }
+
+    fn submit(&mut self, outgoing: &mpsc::UnboundedSender<(Instant, String)>, share: Share) {
+        let mut params = vec![
+            json!(self.worker),
+            json!(share.job.job_id),
+            json!(hex::encode(&share.extranonce)),
+            json!(format!("{:08x}", share.time)),
+            json!(format!("{:08x}", share.nonce)),
+        ];
+        if share.job.version_mask != 0 {
+            let version_bits = share.version & share.job.version_mask;
+            params.push(json!(format!("{:08x}", version_bits)));
+        }
+        if share.block {
+            info!(
+                "Device {} found a block with job {}",
+                self.device, share.job.job_id
+            );
+            self.metrics.blocks.with_label_values(&[&self.device]).inc();
+        }
+        self.metrics
+            .shares
+            .with_label_values(&[&self.device, "submitted"])
+            .inc();
+        self.request(
+            outgoing,
+            Request::Submit,
+            "mining.submit",
+            Value::from(params),
+        );
+    }
+
+    // Handles a message of the pool, returning where to reconnect on `client.reconnect`
+    fn handle(&mut self, message: &Value) -> Option<(String, Duration)> {
+        if let Some(method) = message["method"].as_str() {
+            let params = message["params"].as_array().cloned().unwrap_or_default();
+            match method {
+                "mining.notify" => match Job::from_notify(&params) {
+                    Some(job) => self.notify(job),
+                    None => warn!("Device {} received an invalid job", self.device),
+                },
+                "mining.set_difficulty" => {
+                    if let Some(difficulty) = params.first().and_then(Value::as_f64) {
+                        if difficulty > 0.0 {
+                            self.difficulty = difficulty;
+                        }
+                    }
+                }
+                "mining.set_extranonce" => {
+                    if let Some(extranonce_1) = params
+                        .first()
+                        .and_then(Value::as_str)
+                        .and_then(|extranonce_1| hex::decode(extranonce_1).ok())
+                    {
+                        self.extranonce_1 = extranonce_1;
+                    }
+                    if let Some(size) = params.get(1).and_then(Value::as_u64) {
+                        self.extranonce_2_size = size as usize;
+                    }
+                }
+                "mining.set_version_mask" => {
+                    if let Some(mask) = params.first().and_then(Value::as_str) {
+                        self.version_mask = mask_of(mask) & VERSION_ROLLING_MASK;
+                    }
+                }
+                "client.reconnect" => {
+                    let host = params.first().and_then(Value::as_str);
+                    let port = params.get(1).and_then(|port| match port {
+                        Value::String(port) => port.parse::<u16>().ok(),
+                        port => port.as_u64().map(|port| port as u16),
+                    });
+                    let wait = params.get(2).and_then(Value::as_u64).unwrap_or(0);
+                    let current = self.pool_address.rsplit_once(':');
+                    let host = host
+                        .filter(|host| !host.is_empty())
+                        .or(current.map(|(host, _)| host))?;
+                    let port = port.or(current.and_then(|(_, port)| port.parse().ok()))?;
+                    return Some((format!("{}:{}", host, port), Duration::from_secs(wait)));
+                }
+                _ => {}
+            }
+            return None;
+        }
+
+        let request = message["id"]
+            .as_u64()
+            .and_then(|id| self.pending.remove(&id))?;
+        let result = &message["result"];
+        let error = &message["error"];
+        match request {
+            Request::Configure => {
+                if result["version-rolling"].as_bool() == Some(true) {
+                    self.version_mask = result["version-rolling.mask"]
+                        .as_str()
+                        .map(mask_of)
+                        .unwrap_or(VERSION_ROLLING_MASK)
+                        & VERSION_ROLLING_MASK;
+                    info!(
+                        "Device {} rolls the version bits {:08x}",
+                        self.device, self.version_mask
+                    );
+                }
+            }
+            Request::Subscribe => {
+                let extranonce_1 = result[1].as_str().and_then(|hex| hex::decode(hex).ok());
+                let size = result[2].as_u64();
+                match (extranonce_1, size) {
+                    (Some(extranonce_1), Some(size)) => {
+                        self.extranonce_1 = extranonce_1;
+                        self.extranonce_2_size = size as usize;
+                    }
+                    _ => error!("Device {} failed to subscribe: {}", self.device, error),
+                }
+            }
+            Request::Authorize => {
+                if result.as_bool() == Some(true) {
+                    info!("Device {} authorized as {}", self.device, self.worker);
+                } else {
+                    error!("Device {} failed to authorize: {}", self.device, error);
+                }
+            }
+            Request::SuggestDifficulty => {}
+            Request::Submit => {
+                let accepted = result.as_bool() == Some(true);
+                if !accepted {
+                    warn!("Device {} share rejected: {}", self.device, error);
+                }
+                self.metrics
+                    .shares
+                    .with_label_values(&[
+                        &self.device,
+                        if accepted { "accepted" } else { "rejected" },
+                    ])
+                    .inc();
+            }
+        }
+        None
+    }
+
+    // The difficulty, extranonce and version mask last received apply from the next job on
+    fn notify(&mut self, job: Job) {
+        self.metrics.jobs.with_label_values(&[&self.device]).inc();
+        self.metrics
+            .difficulty
+            .with_label_values(&[&self.device])
+            .set(self.difficulty);
+        let mut prefix = job.coinbase_1;
+        prefix.extend_from_slice(&self.extranonce_1);
+        self.work.send_replace(Some(Work {
+            job: Submission {
+                job_id: job.id,
+                version_mask: self.version_mask,
+            },
+            version: job.version,
+            version_mask: self.version_mask,
+            prev_hash: job.prev_hash,
+            merkle: Merkle::Coinbase {
+                prefix,
+                suffix: job.coinbase_2,
+                path: job.merkle_branch,
+                extranonce_size: self.extranonce_2_size,
+            },
+            time: job.time,
+            bits: job.bits,
+            target: hash::difficulty_target(self.difficulty),
+        }));
+    }
+
+    // Forwards the messages of a channel once the latency and a random jitter elapsed after
+    // they were sent, keeping their order
+    fn delayed(
+        &self,
+        mut input: mpsc::UnboundedReceiver<(Instant, String)>,
+    ) -> mpsc::UnboundedReceiver<String> {
+        let (output, delayed) = mpsc::unbounded_channel();
+        let (latency, jitter) = (self.settings.latency, self.settings.jitter);
+        tokio::spawn(async move {
+            let mut last = Instant::now();
+            while let Some((sent, message)) = input.recv().await {
+                let jitter = if jitter.is_zero() {
+                    Duration::ZERO
+                } else {
+                    rand::thread_rng().gen_range(Duration::ZERO..jitter)
+                };
+                last = last.max(sent + latency + jitter);
+                sleep_until(last).await;
+                if output.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        delayed
+    }
+}
+
+// A version mask sent as hex
+fn mask_of(mask: &str) -> u32 {
+    u32::from_str_radix(mask, 16).unwrap_or(0)
+}
@@ -0,0 +1,54 @@
+/// A `mining.notify` job, the hashes in header byte order.
+pub struct Job {
+    pub id: String,
+    pub prev_hash: [u8; 32],
+    pub coinbase_1: Vec<u8>,
+    pub coinbase_2: Vec<u8>,
+    pub merkle_branch: Vec<[u8; 32]>,
+    pub version: u32,
+    pub bits: u32,
+    pub time: u32,
+}
+
+impl Job {
+    /// Parses the params of `mining.notify`.
+    pub fn from_notify(params: &[serde_json::Value]) -> Option<Self> {
+        let text = |index: usize| params.get(index)?.as_str();
+        let number = |index: usize| u32::from_str_radix(text(index)?, 16).ok();
+        let stratum_prev_hash = hex::decode(text(1)?).ok()?;
+        if stratum_prev_hash.len() != 32 {
+            return None;
+        }
+        // Stratum sends the previous hash as 8 words with their bytes swapped
+        let mut prev_hash = [0u8; 32];
+        for (word, chunk) in stratum_prev_hash.chunks(4).enumerate() {
+            for (byte, value) in chunk.iter().rev().enumerate() {
+                prev_hash[word * 4 + byte] = *value;
+            }
+        }
+        let merkle_branch = params
+            .get(4)?
+            .as_array()?
+            .iter()
+            .map(|hash| hex::decode(hash.as_str()?).ok()?.try_into().ok())
+            .collect::<Option<Vec<[u8; 32]>>>()?;
+        Some(Self {
+            id: text(0)?.to_string(),
+            prev_hash,
+            coinbase_1: hex::decode(text(2)?).ok()?,
+            coinbase_2: hex::decode(text(3)?).ok()?,
+            merkle_branch,
+            version: number(5)?,
+            bits: number(6)?,
+            time: number(7)?,
+        })
+    }
+}
+
+/// What the shares found on a job are submitted with.
+#[derive(Clone)]
+pub struct Submission {
+    pub job_id: String,
+    // Bits of the version the device rolled, 0 without version rolling
+    pub version_mask: u32,
+}
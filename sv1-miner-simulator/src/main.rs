@@ -0,0 +1,81 @@
+use device::{DeviceMetrics, Settings};
+use prometheus::{Encoder, TextEncoder};
+use std::env;
+use std::sync::Arc;
+use tokio::time::Duration;
+use warp::Filter;
+mod device;
+mod job;
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(
+        env_logger::Env::default()
+            .default_filter_or("info")
+            .default_write_style_or("always"),
+    )
+    .init();
+    let pool_address = env::var("POOL_ADDRESS").expect("POOL_ADDRESS environment variable not set");
+    let prometheus_exporter_address =
+        env::var("PROM_ADDRESS").unwrap_or_else(|_| "0.0.0.0:9100".to_string());
+    let devices: usize = env::var("DEVICES")
+        .ok()
+        .and_then(|devices| devices.parse().ok())
+        .unwrap_or(1);
+    let millis = |name: &str| {
+        Duration::from_millis(
+            env::var(name)
+                .ok()
+                .and_then(|millis| millis.parse().ok())
+                .unwrap_or(0),
+        )
+    };
+    let settings = Arc::new(Settings {
+        pool_address,
+        user: env::var("USER_NAME").unwrap_or_else(|_| "sv1-miner-simulator".to_string()),
+        password: env::var("PASSWORD").unwrap_or_else(|_| "x".to_string()),
+        hashrate: env::var("HASHRATE")
+            .ok()
+            .and_then(|hashrate| hashrate.parse().ok())
+            .filter(|hashrate: &f64| *hashrate > 0.0)
+            .unwrap_or(100_000.0),
+        difficulty: env::var("DIFFICULTY")
+            .ok()
+            .and_then(|difficulty| difficulty.parse().ok()),
+        version_rolling: env::var("VERSION_ROLLING")
+            .map(|rolling| rolling != "false" && rolling != "0")
+            .unwrap_or(true),
+        latency: millis("LATENCY_MS"),
+        jitter: millis("JITTER_MS"),
+    });
+
+    let metrics = DeviceMetrics::register();
+    tokio::spawn(async move {
+        let metrics_route = warp::path("metrics").map(move || {
+            let encoder = TextEncoder::new();
+            let metric_families = prometheus::gather();
+            let mut buffer = Vec::new();
+            encoder.encode(&metric_families, &mut buffer).unwrap();
+            warp::http::Response::builder()
+                .header("Content-Type", encoder.format_type())
+                .body(buffer)
+        });
+        let addr: std::net::SocketAddr = prometheus_exporter_address
+            .parse()
+            .expect("Invalid address");
+        warp::serve(metrics_route).run(addr).await;
+    });
+
+    log::info!(
+        "Simulating {} devices of {} H/s mining on {}",
+        devices,
+        settings.hashrate,
+        settings.pool_address
+    );
+    let devices: Vec<_> = (0..devices)
+        .map(|index| tokio::spawn(device::run(index, settings.clone(), metrics.clone())))
+        .collect();
+    for device in devices {
+        device.await.expect("Device task panicked");
+    }
+}
@@ -0,0 +1,39 @@
+use crate::traffic::{message_type_name, Direction};
+use capture_files::CaptureFiles;
+use demand_easy_sv2::Frame_;
+use serde_json::json;
+
+/// Records every frame received by the proxy to the capture files, see [`CaptureFiles`].
+#[derive(Clone)]
+pub struct Capture {
+    files: CaptureFiles,
+}
+
+impl Capture {
+    pub fn from_env(proxy_type: &str) -> Option<Self> {
+        let files = CaptureFiles::from_env(&format!("sv2-{}", proxy_type))?;
+        Some(Self { files })
+    }
+
+    pub fn files(&self) -> CaptureFiles {
+        self.files.clone()
+    }
+
+    pub fn record(&self, connection: u64, direction: Direction, frame: &mut Frame_) {
+        let Frame_::Sv2(sv2_frame) = frame else {
+            return;
+        };
+        let Some(header) = sv2_frame.get_header() else {
+            return;
+        };
+        let payload = sv2_frame.payload();
+        let fields = json!({
+            "extension_type": header.ext_type(),
+            "channel_msg": header.channel_msg(),
+            "message_type": header.msg_type(),
+            "message_name": message_type_name(header.msg_type()),
+            "payload": hex::encode(payload),
+        });
+        self.files.record(connection, direction.as_str(), fields);
+    }
+}
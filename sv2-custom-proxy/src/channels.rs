@@ -0,0 +1,486 @@
+use demand_easy_sv2::const_sv2::{
+    MESSAGE_TYPE_CLOSE_CHANNEL, MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL,
+    MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCES, MESSAGE_TYPE_OPEN_MINING_CHANNEL_ERROR,
+    MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL, MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL_SUCCESS,
+    MESSAGE_TYPE_SETUP_CONNECTION, MESSAGE_TYPE_SETUP_CONNECTION_ERROR,
+    MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS, MESSAGE_TYPE_SUBMIT_SHARES_ERROR,
+    MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED, MESSAGE_TYPE_SUBMIT_SHARES_STANDARD,
+    MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS, MESSAGE_TYPE_UPDATE_CHANNEL,
+    MESSAGE_TYPE_UPDATE_CHANNEL_ERROR,
+};
+use demand_easy_sv2::roles_logic_sv2::parsers::{CommonMessages, Mining, PoolMessages};
+use demand_easy_sv2::{ProxyBuilder, Remote};
+use prometheus::{
+    register_counter_vec, register_gauge, register_gauge_vec, CounterVec, Gauge, GaugeVec,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const STANDARD: &str = "standard";
+const EXTENDED: &str = "extended";
+
+struct PendingChannel {
+    channel_type: &'static str,
+    user_identity: String,
+    nominal_hash_rate: f32,
+    requested_at: f64,
+}
+
+struct OpenChannel {
+    channel_type: &'static str,
+    user_identity: String,
+}
+
+#[derive(Clone)]
+pub struct ChannelMetrics {
+    setup_connection_rtt: Gauge,
+    setup_connection_errors: CounterVec,
+    open_channels: GaugeVec,
+    opened_channels: CounterVec,
+    closed_channels: CounterVec,
+    channel_open_latency: Gauge,
+    channel_nominal_hash_rate: GaugeVec,
+    channel_extranonce_size: GaugeVec,
+    channel_extranonce_prefix_size: GaugeVec,
+    channel_errors: CounterVec,
+    channel_submitted_shares: CounterVec,
+    channel_valid_shares: CounterVec,
+    channel_stale_shares: CounterVec,
+}
+
+impl ChannelMetrics {
+    pub fn register() -> Self {
+        Self {
+            setup_connection_rtt: register_gauge!(
+                "sv2_setup_connection_rtt",
+                "Time between SetupConnection and its reply in milliseconds"
+            )
+            .unwrap(),
+            setup_connection_errors: register_counter_vec!(
+                "sv2_setup_connection_errors",
+                "Total number of SetupConnection refused by the upstream",
+                &["error_code"]
+            )
+            .unwrap(),
+            open_channels: register_gauge_vec!(
+                "sv2_open_channels",
+                "Number of currently open SV2 mining channels",
+                &["channel_type"]
+            )
+            .unwrap(),
+            opened_channels: register_counter_vec!(
+                "sv2_opened_channels",
+                "Total number of SV2 mining channels opened",
+                &["channel_type"]
+            )
+            .unwrap(),
+            closed_channels: register_counter_vec!(
+                "sv2_closed_channels",
+                "Total number of SV2 mining channels closed",
+                &["reason_code"]
+            )
+            .unwrap(),
+            channel_open_latency: register_gauge!(
+                "sv2_channel_open_latency",
+                "Time between a channel open request and its success in milliseconds"
+            )
+            .unwrap(),
+            channel_nominal_hash_rate: register_gauge_vec!(
+                "sv2_channel_nominal_hash_rate",
+                "Nominal hash rate reported by the downstream for the channel in h/s",
+                &["channel_id", "user_identity"]
+            )
+            .unwrap(),
+            channel_extranonce_size: register_gauge_vec!(
+                "sv2_channel_extranonce_size",
+                "Extranonce bytes the downstream can roll on the channel",
+                &["channel_id"]
+            )
+            .unwrap(),
+            channel_extranonce_prefix_size: register_gauge_vec!(
+                "sv2_channel_extranonce_prefix_size",
+                "Extranonce prefix bytes assigned by the upstream to the channel",
+                &["channel_id"]
+            )
+            .unwrap(),
+            channel_errors: register_counter_vec!(
+                "sv2_channel_errors",
+                "Total number of channel errors sent by the upstream",
+                &["message", "error_code"]
+            )
+            .unwrap(),
+            channel_submitted_shares: register_counter_vec!(
+                "sv2_channel_submitted_shares",
+                "Total number of SV2 submitted shares per channel",
+                &["channel_id"]
+            )
+            .unwrap(),
+            channel_valid_shares: register_counter_vec!(
+                "sv2_channel_valid_shares",
+                "Total number of SV2 shares accepted by the upstream per channel",
+                &["channel_id"]
+            )
+            .unwrap(),
+            channel_stale_shares: register_counter_vec!(
+                "sv2_channel_stale_shares",
+                "Total number of SV2 stale shares per channel",
+                &["channel_id"]
+            )
+            .unwrap(),
+        }
+    }
+}
+
+fn now_millis() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as f64
+}
+
+pub async fn intercept_channels(builder: &mut ProxyBuilder, metrics: ChannelMetrics) {
+    // Open channel requests by request id, open channels by channel id
+    let pending: Arc<Mutex<HashMap<u32, PendingChannel>>> = Arc::new(Mutex::new(HashMap::new()));
+    let channels: Arc<Mutex<HashMap<u32, OpenChannel>>> = Arc::new(Mutex::new(HashMap::new()));
+
+    intercept_setup_connection(builder, metrics.clone()).await;
+    intercept_open_channel(builder, pending.clone()).await;
+    intercept_open_channel_success(builder, metrics.clone(), pending.clone(), channels.clone())
+        .await;
+    intercept_open_channel_error(builder, metrics.clone(), pending).await;
+    intercept_update_channel(builder, metrics.clone(), channels.clone()).await;
+    intercept_close_channel(builder, metrics.clone(), channels).await;
+    intercept_channel_shares(builder, metrics).await;
+}
+
+async fn intercept_setup_connection(builder: &mut ProxyBuilder, metrics: ChannelMetrics) {
+    let sent_at: Arc<Mutex<Option<f64>>> = Arc::new(Mutex::new(None));
+
+    let mut r = builder.add_handler(Remote::Client, MESSAGE_TYPE_SETUP_CONNECTION);
+    let sent_at_clone = sent_at.clone();
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Common(CommonMessages::SetupConnection(m))) = r.recv().await {
+            log::info!(
+                "SetupConnection from {} {} (device {})",
+                String::from_utf8_lossy(&m.vendor.to_vec()),
+                String::from_utf8_lossy(&m.firmware.to_vec()),
+                String::from_utf8_lossy(&m.device_id.to_vec())
+            );
+            *sent_at_clone.lock().unwrap() = Some(now_millis());
+        }
+    });
+
+    let mut r = builder.add_handler(Remote::Server, MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS);
+    let sent_at_clone = sent_at.clone();
+    let setup_connection_rtt = metrics.setup_connection_rtt.clone();
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Common(CommonMessages::SetupConnectionSuccess(_m))) =
+            r.recv().await
+        {
+            if let Some(sent_at) = sent_at_clone.lock().unwrap().take() {
+                measurements::recorder::set(&setup_connection_rtt, now_millis() - sent_at);
+            }
+        }
+    });
+
+    let mut r = builder.add_handler(Remote::Server, MESSAGE_TYPE_SETUP_CONNECTION_ERROR);
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Common(CommonMessages::SetupConnectionError(m))) =
+            r.recv().await
+        {
+            log::error!("SetupConnectionError received --> {:?}", m);
+            let error_code = String::from_utf8_lossy(&m.error_code.to_vec()).to_string();
+            metrics
+                .setup_connection_errors
+                .with_label_values(&[&error_code])
+                .inc();
+            if let Some(sent_at) = sent_at.lock().unwrap().take() {
+                measurements::recorder::set(&metrics.setup_connection_rtt, now_millis() - sent_at);
+            }
+        }
+    });
+}
+
+async fn intercept_open_channel(
+    builder: &mut ProxyBuilder,
+    pending: Arc<Mutex<HashMap<u32, PendingChannel>>>,
+) {
+    let mut r = builder.add_handler(Remote::Client, MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL);
+    let pending_clone = pending.clone();
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Mining(Mining::OpenStandardMiningChannel(m))) = r.recv().await
+        {
+            pending_clone.lock().unwrap().insert(
+                m.get_request_id_as_u32(),
+                PendingChannel {
+                    channel_type: STANDARD,
+                    user_identity: String::from_utf8_lossy(&m.user_identity.to_vec()).to_string(),
+                    nominal_hash_rate: m.nominal_hash_rate,
+                    requested_at: now_millis(),
+                },
+            );
+        }
+    });
+
+    let mut r = builder.add_handler(Remote::Client, MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL);
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Mining(Mining::OpenExtendedMiningChannel(m))) = r.recv().await
+        {
+            pending.lock().unwrap().insert(
+                m.request_id,
+                PendingChannel {
+                    channel_type: EXTENDED,
+                    user_identity: String::from_utf8_lossy(&m.user_identity.to_vec()).to_string(),
+                    nominal_hash_rate: m.nominal_hash_rate,
+                    requested_at: now_millis(),
+                },
+            );
+        }
+    });
+}
+
+async fn intercept_open_channel_success(
+    builder: &mut ProxyBuilder,
+    metrics: ChannelMetrics,
+    pending: Arc<Mutex<HashMap<u32, PendingChannel>>>,
+    channels: Arc<Mutex<HashMap<u32, OpenChannel>>>,
+) {
+    let mut r = builder.add_handler(
+        Remote::Server,
+        MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL_SUCCESS,
+    );
+    let metrics_clone = metrics.clone();
+    let pending_clone = pending.clone();
+    let channels_clone = channels.clone();
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Mining(Mining::OpenStandardMiningChannelSuccess(m))) =
+            r.recv().await
+        {
+            let channel_id = m.channel_id.to_string();
+            // Standard channels leave nothing to roll to the downstream
+            metrics_clone
+                .channel_extranonce_size
+                .with_label_values(&[&channel_id])
+                .set(0.0);
+            metrics_clone
+                .channel_extranonce_prefix_size
+                .with_label_values(&[&channel_id])
+                .set(m.extranonce_prefix.to_vec().len() as f64);
+            on_channel_opened(
+                &metrics_clone,
+                &pending_clone,
+                &channels_clone,
+                m.get_request_id_as_u32(),
+                m.channel_id,
+            );
+        }
+    });
+
+    let mut r = builder.add_handler(
+        Remote::Server,
+        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCES,
+    );
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Mining(Mining::OpenExtendedMiningChannelSuccess(m))) =
+            r.recv().await
+        {
+            let channel_id = m.channel_id.to_string();
+            metrics
+                .channel_extranonce_size
+                .with_label_values(&[&channel_id])
+                .set(m.extranonce_size as f64);
+            metrics
+                .channel_extranonce_prefix_size
+                .with_label_values(&[&channel_id])
+                .set(m.extranonce_prefix.to_vec().len() as f64);
+            on_channel_opened(&metrics, &pending, &channels, m.request_id, m.channel_id);
+        }
+    });
+}
+
+fn on_channel_opened(
+    metrics: &ChannelMetrics,
+    pending: &Mutex<HashMap<u32, PendingChannel>>,
+    channels: &Mutex<HashMap<u32, OpenChannel>>,
+    request_id: u32,
+    channel_id: u32,
+) {
+    let Some(request) = pending.lock().unwrap().remove(&request_id) else {
+        log::warn!(
+            "Channel {} opened for unknown request id {}",
+            channel_id,
+            request_id
+        );
+        return;
+    };
+    log::info!(
+        "Opened {} channel {} for {}",
+        request.channel_type,
+        channel_id,
+        request.user_identity
+    );
+    measurements::recorder::set(
+        &metrics.channel_open_latency,
+        now_millis() - request.requested_at,
+    );
+    metrics
+        .opened_channels
+        .with_label_values(&[request.channel_type])
+        .inc();
+    metrics
+        .open_channels
+        .with_label_values(&[request.channel_type])
+        .inc();
+    metrics
+        .channel_nominal_hash_rate
+        .with_label_values(&[&channel_id.to_string(), &request.user_identity])
+        .set(request.nominal_hash_rate as f64);
+    channels.lock().unwrap().insert(
+        channel_id,
+        OpenChannel {
+            channel_type: request.channel_type,
+            user_identity: request.user_identity,
+        },
+    );
+}
+
+async fn intercept_open_channel_error(
+    builder: &mut ProxyBuilder,
+    metrics: ChannelMetrics,
+    pending: Arc<Mutex<HashMap<u32, PendingChannel>>>,
+) {
+    let mut r = builder.add_handler(Remote::Server, MESSAGE_TYPE_OPEN_MINING_CHANNEL_ERROR);
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Mining(Mining::OpenMiningChannelError(m))) = r.recv().await {
+            log::error!("OpenMiningChannelError received --> {:?}", m);
+            pending.lock().unwrap().remove(&m.request_id);
+            let error_code = String::from_utf8_lossy(&m.error_code.to_vec()).to_string();
+            metrics
+                .channel_errors
+                .with_label_values(&["OpenMiningChannelError", &error_code])
+                .inc();
+        }
+    });
+}
+
+async fn intercept_update_channel(
+    builder: &mut ProxyBuilder,
+    metrics: ChannelMetrics,
+    channels: Arc<Mutex<HashMap<u32, OpenChannel>>>,
+) {
+    let mut r = builder.add_handler(Remote::Client, MESSAGE_TYPE_UPDATE_CHANNEL);
+    let channel_nominal_hash_rate = metrics.channel_nominal_hash_rate.clone();
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Mining(Mining::UpdateChannel(m))) = r.recv().await {
+            if let Some(channel) = channels.lock().unwrap().get(&m.channel_id) {
+                channel_nominal_hash_rate
+                    .with_label_values(&[&m.channel_id.to_string(), &channel.user_identity])
+                    .set(m.nominal_hash_rate as f64);
+            } else {
+                log::warn!("UpdateChannel for unknown channel {}", m.channel_id);
+            }
+        }
+    });
+
+    let mut r = builder.add_handler(Remote::Server, MESSAGE_TYPE_UPDATE_CHANNEL_ERROR);
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Mining(Mining::UpdateChannelError(m))) = r.recv().await {
+            log::error!("UpdateChannelError received --> {:?}", m);
+            let error_code = String::from_utf8_lossy(&m.error_code.to_vec()).to_string();
+            metrics
+                .channel_errors
+                .with_label_values(&["UpdateChannelError", &error_code])
+                .inc();
+        }
+    });
+}
+
+// CloseChannel can be sent by both ends of the connection
+async fn intercept_close_channel(
+    builder: &mut ProxyBuilder,
+    metrics: ChannelMetrics,
+    channels: Arc<Mutex<HashMap<u32, OpenChannel>>>,
+) {
+    for remote in [Remote::Client, Remote::Server] {
+        let mut r = builder.add_handler(remote, MESSAGE_TYPE_CLOSE_CHANNEL);
+        let metrics = metrics.clone();
+        let channels = channels.clone();
+        tokio::spawn(async move {
+            while let Some(PoolMessages::Mining(Mining::CloseChannel(m))) = r.recv().await {
+                let reason_code = String::from_utf8_lossy(&m.reason_code.to_vec()).to_string();
+                metrics
+                    .closed_channels
+                    .with_label_values(&[&reason_code])
+                    .inc();
+                let Some(channel) = channels.lock().unwrap().remove(&m.channel_id) else {
+                    log::warn!("CloseChannel for unknown channel {}", m.channel_id);
+                    continue;
+                };
+                log::info!(
+                    "Closed {} channel {} for {}: {}",
+                    channel.channel_type,
+                    m.channel_id,
+                    channel.user_identity,
+                    reason_code
+                );
+                let channel_id = m.channel_id.to_string();
+                metrics
+                    .open_channels
+                    .with_label_values(&[channel.channel_type])
+                    .dec();
+                let _ = metrics
+                    .channel_nominal_hash_rate
+                    .remove_label_values(&[&channel_id, &channel.user_identity]);
+                let _ = metrics
+                    .channel_extranonce_size
+                    .remove_label_values(&[&channel_id]);
+                let _ = metrics
+                    .channel_extranonce_prefix_size
+                    .remove_label_values(&[&channel_id]);
+            }
+        });
+    }
+}
+
+async fn intercept_channel_shares(builder: &mut ProxyBuilder, metrics: ChannelMetrics) {
+    let mut r = builder.add_handler(Remote::Client, MESSAGE_TYPE_SUBMIT_SHARES_STANDARD);
+    let channel_submitted_shares = metrics.channel_submitted_shares.clone();
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Mining(Mining::SubmitSharesStandard(m))) = r.recv().await {
+            channel_submitted_shares
+                .with_label_values(&[&m.channel_id.to_string()])
+                .inc();
+        }
+    });
+
+    let mut r = builder.add_handler(Remote::Client, MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED);
+    let channel_submitted_shares = metrics.channel_submitted_shares.clone();
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Mining(Mining::SubmitSharesExtended(m))) = r.recv().await {
+            channel_submitted_shares
+                .with_label_values(&[&m.channel_id.to_string()])
+                .inc();
+        }
+    });
+
+    // A single SubmitSharesSuccess can acknowledge several shares
+    let mut r = builder.add_handler(Remote::Server, MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS);
+    let channel_valid_shares = metrics.channel_valid_shares.clone();
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Mining(Mining::SubmitSharesSuccess(m))) = r.recv().await {
+            channel_valid_shares
+                .with_label_values(&[&m.channel_id.to_string()])
+                .inc_by(m.new_submits_accepted_count as f64);
+        }
+    });
+
+    let mut r = builder.add_handler(Remote::Server, MESSAGE_TYPE_SUBMIT_SHARES_ERROR);
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Mining(Mining::SubmitSharesError(m))) = r.recv().await {
+            metrics
+                .channel_stale_shares
+                .with_label_values(&[&m.channel_id.to_string()])
+                .inc();
+        }
+    });
+}
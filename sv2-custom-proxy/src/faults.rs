@@ -0,0 +1,185 @@
+use crate::traffic::{message_type_name, Direction};
+use bench_common::fault_schedule::{self, Conditions};
+use demand_easy_sv2::Frame_;
+use prometheus::{register_counter_vec, CounterVec};
+use rand::Rng;
+use serde::Deserialize;
+use std::future::pending;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::sleep_until;
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "fault", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Fault {
+    // Holds the frame back, the following frames in the same direction queue behind it
+    Delay {
+        ms: u64,
+        #[serde(default)]
+        jitter_ms: u64,
+    },
+    Drop {},
+    // Holds the frame until the next frame in the same direction has been forwarded, or
+    // `max_ms` passed
+    Reorder {
+        #[serde(default = "default_max_reorder_ms")]
+        max_ms: u64,
+    },
+    // Closes both connections, on the first matching frame or at `from_s` when no message
+    // is given
+    Sever {},
+}
+
+fn default_max_reorder_ms() -> u64 {
+    1000
+}
+
+impl Fault {
+    fn name(&self) -> &'static str {
+        match self {
+            Fault::Delay { .. } => "delay",
+            Fault::Drop {} => "drop",
+            Fault::Reorder { .. } => "reorder",
+            Fault::Sever {} => "sever",
+        }
+    }
+}
+
+/// An SV2 entry of the schedule: `fault` is applied to the frames of `message` (named as in the
+/// traffic metrics, every message when missing) under the [`Conditions`] of the entry. Unknown
+/// fields are rejected by `Fault`, serde can't deny them on a struct with flattened fields.
+#[derive(Deserialize, Debug)]
+pub struct Rule {
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(flatten)]
+    pub conditions: Conditions,
+    #[serde(flatten)]
+    pub fault: Fault,
+}
+
+impl Rule {
+    fn matches(&self, direction: Direction, message_type: &str) -> bool {
+        self.conditions.matches(direction.as_str())
+            && self.message.iter().all(|m| m == message_type)
+    }
+}
+
+pub enum Action {
+    Forward(Duration),
+    Drop,
+    Hold(Duration),
+    Sever,
+}
+
+/// Fault schedule read from the JSON file at `FAULT_SCHEDULE`. Without it no fault is injected.
+#[derive(Clone)]
+pub struct Schedule {
+    rules: Arc<Vec<Rule>>,
+    started: Instant,
+    injected: CounterVec,
+}
+
+impl Schedule {
+    pub fn from_env(proxy_type: &str) -> Self {
+        let rules: Vec<Rule> = fault_schedule::from_env(proxy_type);
+        for rule in &rules {
+            log::warn!("Fault scheduled: {:?}", rule);
+        }
+        Self {
+            rules: Arc::new(rules),
+            started: Instant::now(),
+            injected: register_counter_vec!(
+                "sv2_injected_faults",
+                "Total number of faults injected by the SV2 proxy",
+                &["fault", "direction", "message_type"]
+            )
+            .unwrap(),
+        }
+    }
+
+    fn count(&self, fault: &Fault, direction: Direction, message_type: &str) {
+        log::warn!(
+            "Injecting {} on {} {}",
+            fault.name(),
+            direction.as_str(),
+            message_type
+        );
+        self.injected
+            .with_label_values(&[fault.name(), direction.as_str(), message_type])
+            .inc();
+    }
+
+    /// Decides what happens to a frame received from `direction`.
+    pub fn apply(&self, direction: Direction, frame: &Frame_) -> Action {
+        let Frame_::Sv2(sv2_frame) = frame else {
+            return Action::Forward(Duration::ZERO);
+        };
+        let Some(header) = sv2_frame.get_header() else {
+            return Action::Forward(Duration::ZERO);
+        };
+        let message_type = message_type_name(header.msg_type());
+        let elapsed = self.started.elapsed();
+        let mut rng = rand::thread_rng();
+        let mut delay = Duration::ZERO;
+        let mut hold = None;
+        for rule in self.rules.iter() {
+            if !rule.conditions.active(elapsed) || !rule.matches(direction, message_type) {
+                continue;
+            }
+            // Time triggered severs are handled by `severed`
+            if matches!(rule.fault, Fault::Sever {}) && rule.message.is_none() {
+                continue;
+            }
+            if !rng.gen_bool(rule.conditions.probability.clamp(0.0, 1.0)) {
+                continue;
+            }
+            match &rule.fault {
+                Fault::Delay { ms, jitter_ms } => {
+                    delay += Duration::from_millis(ms + rng.gen_range(0..=*jitter_ms));
+                }
+                Fault::Drop {} => {
+                    self.count(&rule.fault, direction, message_type);
+                    return Action::Drop;
+                }
+                Fault::Reorder { max_ms } => hold = Some(Duration::from_millis(*max_ms)),
+                Fault::Sever {} => {
+                    self.count(&rule.fault, direction, message_type);
+                    return Action::Sever;
+                }
+            }
+            self.count(&rule.fault, direction, message_type);
+        }
+        // A held frame already arrives late, delays don't add up to it
+        match hold {
+            Some(max) => Action::Hold(max),
+            None => Action::Forward(delay),
+        }
+    }
+
+    /// Resolves when a sever without message is due for `direction`.
+    pub async fn severed(&self, direction: Direction) {
+        let due = self
+            .rules
+            .iter()
+            .filter(|rule| matches!(rule.fault, Fault::Sever {}) && rule.message.is_none())
+            .filter(|rule| rule.conditions.matches(direction.as_str()))
+            .map(|rule| self.started + Duration::from_secs(rule.conditions.from_s))
+            .min();
+        match due {
+            Some(due) => {
+                sleep_until(due.into()).await;
+                self.count(&Fault::Sever {}, direction, "timer");
+            }
+            None => pending().await,
+        }
+    }
+}
+
+/// Resolves when the frame held for reordering has to be forwarded anyway.
+pub async fn held_expired(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline.into()).await,
+        None => pending().await,
+    }
+}
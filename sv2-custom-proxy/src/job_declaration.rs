@@ -0,0 +1,277 @@
+use crate::template_history;
+use demand_easy_sv2::const_sv2::{
+    MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN, MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN_SUCCESS,
+    MESSAGE_TYPE_DECLARE_MINING_JOB, MESSAGE_TYPE_DECLARE_MINING_JOB_ERROR,
+    MESSAGE_TYPE_DECLARE_MINING_JOB_SUCCESS, MESSAGE_TYPE_PROVIDE_MISSING_TRANSACTIONS,
+    MESSAGE_TYPE_SUBMIT_SOLUTION_JD,
+};
+use demand_easy_sv2::roles_logic_sv2::parsers::{JobDeclaration, PoolMessages};
+use demand_easy_sv2::{ProxyBuilder, Remote};
+use prometheus::{
+    register_counter, register_counter_vec, register_gauge, Counter, CounterVec, Gauge,
+};
+use reqwest::Client;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+// Prev hashes the first declared job was timed for, enough to never time a job twice since
+// only the last one is looked up
+const MAX_SEEN_PREV_HASHES: usize = 16;
+
+#[derive(Clone)]
+pub struct JobDeclarationMetrics {
+    token_allocation_rtt: Gauge,
+    allocated_tokens: Counter,
+    declare_mining_job_rtt: Gauge,
+    declared_jobs: Counter,
+    declared_jobs_success: Counter,
+    declared_jobs_error: CounterVec,
+    provide_missing_transactions_requests: Counter,
+    missing_transactions: Counter,
+    last_missing_transactions: Gauge,
+    push_solutions: Counter,
+    prev_hash_to_declared_job: Gauge,
+}
+
+impl JobDeclarationMetrics {
+    pub fn register() -> Self {
+        Self {
+            token_allocation_rtt: register_gauge!(
+                "sv2_jd_token_allocation_rtt",
+                "Time between AllocateMiningJobToken and its success in milliseconds"
+            )
+            .unwrap(),
+            allocated_tokens: register_counter!(
+                "sv2_jd_allocated_tokens",
+                "Total number of mining job tokens allocated by the JDS"
+            )
+            .unwrap(),
+            declare_mining_job_rtt: register_gauge!(
+                "sv2_jd_declare_mining_job_rtt",
+                "Time between DeclareMiningJob and the JDS reply in milliseconds"
+            )
+            .unwrap(),
+            declared_jobs: register_counter!(
+                "sv2_jd_declared_jobs",
+                "Total number of jobs declared by the JDC"
+            )
+            .unwrap(),
+            declared_jobs_success: register_counter!(
+                "sv2_jd_declared_jobs_success",
+                "Total number of declared jobs accepted by the JDS"
+            )
+            .unwrap(),
+            declared_jobs_error: register_counter_vec!(
+                "sv2_jd_declared_jobs_error",
+                "Total number of declared jobs refused by the JDS",
+                &["error_code"]
+            )
+            .unwrap(),
+            provide_missing_transactions_requests: register_counter!(
+                "sv2_jd_provide_missing_transactions_requests",
+                "Total number of ProvideMissingTransactions sent by the JDS"
+            )
+            .unwrap(),
+            missing_transactions: register_counter!(
+                "sv2_jd_missing_transactions",
+                "Total number of transactions the JDS had to request to the JDC"
+            )
+            .unwrap(),
+            last_missing_transactions: register_gauge!(
+                "sv2_jd_last_missing_transactions",
+                "Number of transactions requested by the JDS in the last ProvideMissingTransactions"
+            )
+            .unwrap(),
+            push_solutions: register_counter!(
+                "sv2_jd_push_solutions",
+                "Total number of solutions pushed by the JDC to the JDS"
+            )
+            .unwrap(),
+            prev_hash_to_declared_job: register_gauge!(
+                "sv2_jd_prev_hash_to_declared_job",
+                "Time between the JDC getting a new prev hash and declaring the first job on top of it in milliseconds"
+            )
+            .unwrap(),
+        }
+    }
+}
+
+fn now_millis() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as f64
+}
+
+pub async fn intercept_job_declaration(builder: &mut ProxyBuilder, metrics: JobDeclarationMetrics) {
+    intercept_allocate_mining_job_token(builder, metrics.clone()).await;
+    intercept_declare_mining_job(builder, metrics.clone()).await;
+    intercept_provide_missing_transactions(builder, metrics.clone()).await;
+    intercept_push_solution(builder, metrics).await;
+}
+
+async fn intercept_allocate_mining_job_token(
+    builder: &mut ProxyBuilder,
+    metrics: JobDeclarationMetrics,
+) {
+    let pending: Arc<Mutex<HashMap<u32, f64>>> = Arc::new(Mutex::new(HashMap::new()));
+
+    let mut r = builder.add_handler(Remote::Client, MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN);
+    let pending_clone = pending.clone();
+    tokio::spawn(async move {
+        while let Some(PoolMessages::JobDeclaration(JobDeclaration::AllocateMiningJobToken(m))) =
+            r.recv().await
+        {
+            pending_clone
+                .lock()
+                .unwrap()
+                .insert(m.request_id, now_millis());
+        }
+    });
+
+    let mut r = builder.add_handler(
+        Remote::Server,
+        MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN_SUCCESS,
+    );
+    tokio::spawn(async move {
+        while let Some(PoolMessages::JobDeclaration(
+            JobDeclaration::AllocateMiningJobTokenSuccess(m),
+        )) = r.recv().await
+        {
+            metrics.allocated_tokens.inc();
+            if let Some(sent_at) = pending.lock().unwrap().remove(&m.request_id) {
+                measurements::recorder::set(&metrics.token_allocation_rtt, now_millis() - sent_at);
+            } else {
+                log::warn!(
+                    "AllocateMiningJobTokenSuccess for unknown request id {}",
+                    m.request_id
+                );
+            }
+        }
+    });
+}
+
+async fn intercept_declare_mining_job(builder: &mut ProxyBuilder, metrics: JobDeclarationMetrics) {
+    let pending: Arc<Mutex<HashMap<u32, f64>>> = Arc::new(Mutex::new(HashMap::new()));
+    let seen_prev_hashes: Arc<Mutex<VecDeque<String>>> = Arc::new(Mutex::new(VecDeque::new()));
+    let client = Client::new();
+
+    let mut r = builder.add_handler(Remote::Client, MESSAGE_TYPE_DECLARE_MINING_JOB);
+    let pending_clone = pending.clone();
+    let declared_jobs = metrics.declared_jobs.clone();
+    let prev_hash_to_declared_job = metrics.prev_hash_to_declared_job.clone();
+    tokio::spawn(async move {
+        while let Some(PoolMessages::JobDeclaration(JobDeclaration::DeclareMiningJob(m))) =
+            r.recv().await
+        {
+            let current_time = now_millis();
+            declared_jobs.inc();
+            pending_clone
+                .lock()
+                .unwrap()
+                .insert(m.request_id, current_time);
+
+            // Don't hold the proxy back while the TP proxy is queried
+            let client = client.clone();
+            let seen_prev_hashes = seen_prev_hashes.clone();
+            let prev_hash_to_declared_job = prev_hash_to_declared_job.clone();
+            tokio::spawn(async move {
+                if let Some((prev_hash, prev_hash_timestamp)) =
+                    fetch_last_prev_hash_timestamp(&client, current_time).await
+                {
+                    // Only the first job declared on top of a prev hash is the one that
+                    // tells how long the JDC took to react to it
+                    if remember(&seen_prev_hashes, prev_hash) {
+                        measurements::recorder::set(
+                            &prev_hash_to_declared_job,
+                            current_time - prev_hash_timestamp,
+                        );
+                    }
+                }
+            });
+        }
+    });
+
+    let mut r = builder.add_handler(Remote::Server, MESSAGE_TYPE_DECLARE_MINING_JOB_SUCCESS);
+    let pending_clone = pending.clone();
+    let declare_mining_job_rtt = metrics.declare_mining_job_rtt.clone();
+    let declared_jobs_success = metrics.declared_jobs_success.clone();
+    tokio::spawn(async move {
+        while let Some(PoolMessages::JobDeclaration(JobDeclaration::DeclareMiningJobSuccess(m))) =
+            r.recv().await
+        {
+            declared_jobs_success.inc();
+            if let Some(sent_at) = pending_clone.lock().unwrap().remove(&m.request_id) {
+                measurements::recorder::set(&declare_mining_job_rtt, now_millis() - sent_at);
+            }
+        }
+    });
+
+    let mut r = builder.add_handler(Remote::Server, MESSAGE_TYPE_DECLARE_MINING_JOB_ERROR);
+    tokio::spawn(async move {
+        while let Some(PoolMessages::JobDeclaration(JobDeclaration::DeclareMiningJobError(m))) =
+            r.recv().await
+        {
+            let error_code = String::from_utf8_lossy(&m.error_code.to_vec()).to_string();
+            log::error!("DeclareMiningJobError received --> {:?}", m);
+            metrics
+                .declared_jobs_error
+                .with_label_values(&[&error_code])
+                .inc();
+            if let Some(sent_at) = pending.lock().unwrap().remove(&m.request_id) {
+                measurements::recorder::set(
+                    &metrics.declare_mining_job_rtt,
+                    now_millis() - sent_at,
+                );
+            }
+        }
+    });
+}
+
+async fn intercept_provide_missing_transactions(
+    builder: &mut ProxyBuilder,
+    metrics: JobDeclarationMetrics,
+) {
+    let mut r = builder.add_handler(Remote::Server, MESSAGE_TYPE_PROVIDE_MISSING_TRANSACTIONS);
+    tokio::spawn(async move {
+        while let Some(PoolMessages::JobDeclaration(JobDeclaration::ProvideMissingTransactions(
+            m,
+        ))) = r.recv().await
+        {
+            let missing = m.unknown_tx_position_list.into_inner().len();
+            metrics.provide_missing_transactions_requests.inc();
+            metrics.missing_transactions.inc_by(missing as f64);
+            measurements::recorder::set(&metrics.last_missing_transactions, missing as f64);
+        }
+    });
+}
+
+async fn intercept_push_solution(builder: &mut ProxyBuilder, metrics: JobDeclarationMetrics) {
+    let mut r = builder.add_handler(Remote::Client, MESSAGE_TYPE_SUBMIT_SOLUTION_JD);
+    tokio::spawn(async move {
+        while let Some(PoolMessages::JobDeclaration(JobDeclaration::SubmitSolution(_m))) =
+            r.recv().await
+        {
+            metrics.push_solutions.inc();
+        }
+    });
+}
+
+// The JDC declares its jobs on top of the last prev hash it got before `before`
+async fn fetch_last_prev_hash_timestamp(client: &Client, before: f64) -> Option<(String, f64)> {
+    let last = template_history::fetch_prev_hashes(client).await.pop()?;
+    (last.timestamp <= before).then_some((last.prev_hash, last.timestamp))
+}
+
+// Whether the prev hash wasn't seen yet, only the last ones are remembered
+fn remember(seen: &Mutex<VecDeque<String>>, prev_hash: String) -> bool {
+    let mut seen = seen.lock().unwrap();
+    if seen.contains(&prev_hash) {
+        return false;
+    }
+    seen.push_back(prev_hash);
+    if seen.len() > MAX_SEEN_PREV_HASHES {
+        seen.pop_front();
+    }
+    true
+}
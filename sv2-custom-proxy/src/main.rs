@@ -1,3 +1,16 @@
+mod capture;
+mod channels;
+mod faults;
+mod job_declaration;
+mod mining_jobs;
+mod tap;
+mod template_history;
+mod tracker;
+mod traffic;
+
+use bench_common::rpc_auth::RpcCredentials;
+use capture::Capture;
+use channels::ChannelMetrics;
 use demand_easy_sv2::const_sv2::{
     MESSAGE_TYPE_NEW_TEMPLATE, MESSAGE_TYPE_SET_NEW_PREV_HASH, MESSAGE_TYPE_SUBMIT_SHARES_ERROR,
     MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED, MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS,
@@ -5,20 +18,35 @@ use demand_easy_sv2::const_sv2::{
 };
 use demand_easy_sv2::roles_logic_sv2::parsers::{Mining, PoolMessages, TemplateDistribution};
 use demand_easy_sv2::{ProxyBuilder, Remote};
+use faults::Schedule;
+use job_declaration::JobDeclarationMetrics;
+use mining_jobs::MiningJobMetrics;
 use prometheus::{
     register_counter, register_gauge, register_gauge_vec, Counter, Encoder, Gauge, GaugeVec,
     TextEncoder,
 };
 use reqwest::Client;
 use serde_json::Value;
+use share_store::{HeaderFields, ShareStore};
 use std::env;
 use std::fmt::Write;
 use std::net::ToSocketAddrs;
+use std::sync::OnceLock;
 use std::time::SystemTime;
+use tap::Tap;
 use tokio::net::TcpStream;
 use tokio::time::{sleep, Duration};
+use tracker::Tracker;
+use traffic::TrafficMetrics;
 use warp::Filter;
 
+// Node of the template provider on the pool side
+const DEFAULT_NODE_RPC_URL: &str = "http://10.5.0.2:18332";
+const NODE_RPC_TIMEOUT: Duration = Duration::from_secs(10);
+// The proxy between the translator and the pool (or JDC) stores every share
+const DEFAULT_SHARES_URL: &str = "http://10.5.0.17:3456/shares";
+const SHARES_TIMEOUT: Duration = Duration::from_secs(2);
+
 #[tokio::main]
 async fn main() {
     env_logger::Builder::from_env(
@@ -33,6 +61,7 @@ async fn main() {
     let proxy_type = env::var("PROXY_TYPE").expect("PROXY_TYPE environment variable not set");
     let prometheus_exporter_address =
         env::var("PROM_ADDRESS").expect("PROM_ADDRESS environment variable not set");
+    measurements::recorder::start(&proxy_type);
 
     let mut submitted_shares: Option<Counter> = None;
     let mut valid_shares: Option<Counter> = None;
@@ -48,6 +77,10 @@ async fn main() {
     let mut block_propagation_time_through_sv2_jdc: Option<Gauge> = None;
     let mut block_propagation_time_through_sv2_pool: Option<Gauge> = None;
     let mut mined_blocks: Option<Counter> = None;
+    let mut unmatched_block_solutions: Option<Counter> = None;
+    let mut job_declaration_metrics: Option<JobDeclarationMetrics> = None;
+    let mut mining_job_metrics: Option<MiningJobMetrics> = None;
+    let mut channel_metrics: Option<ChannelMetrics> = None;
 
     // Initialize metrics based on proxy_type
     match proxy_type.as_str() {
@@ -55,6 +88,13 @@ async fn main() {
             mined_blocks = Some(
                 register_counter!("sv2_mined_blocks", "Total number of SV2 blocks mined").unwrap(),
             );
+            unmatched_block_solutions = Some(
+                register_counter!(
+                    "sv2_unmatched_block_solutions",
+                    "Total number of SV2 block solutions not matching exactly one submitted share"
+                )
+                .unwrap(),
+            );
             block_propagation_time_through_sv2_jdc = Some(
                 register_gauge!(
                     "block_propagation_time_through_sv2_jdc",
@@ -103,6 +143,13 @@ async fn main() {
             mined_blocks = Some(
                 register_counter!("sv2_mined_blocks", "Total number of SV2 blocks mined").unwrap(),
             );
+            unmatched_block_solutions = Some(
+                register_counter!(
+                    "sv2_unmatched_block_solutions",
+                    "Total number of SV2 block solutions not matching exactly one submitted share"
+                )
+                .unwrap(),
+            );
             block_propagation_time_through_sv2_pool = Some(
                 register_gauge!(
                     "block_propagation_time_through_sv2_pool",
@@ -157,7 +204,11 @@ async fn main() {
                 .unwrap(),
             );
             valid_shares = Some(
-                register_counter!("sv2_valid_shares", "Total number of SV2 valid shares").unwrap(),
+                register_counter!(
+                    "sv2_valid_shares",
+                    "Total number of SV2 shares accepted by the upstream"
+                )
+                .unwrap(),
             );
             stale_shares = Some(
                 register_counter!("sv2_stale_shares", "Total number of SV2 stale shares").unwrap(),
@@ -170,11 +221,23 @@ async fn main() {
                 )
                 .unwrap(),
             );
+            mining_job_metrics = Some(MiningJobMetrics::register());
+            channel_metrics = Some(ChannelMetrics::register());
+        }
+        "jdc-jds" => {
+            job_declaration_metrics = Some(JobDeclarationMetrics::register());
         }
         _ => panic!("Invalid PROXY_TYPE"),
     }
 
+    let capture = Capture::from_env(&proxy_type);
+    let share_store = ShareStore::from_env();
+    let tracker = Tracker::default();
+
     // Spawn the metrics endpoint
+    let capture_routes = capture_files::routes(capture.as_ref().map(Capture::files));
+    let share_routes = share_store::routes(share_store.clone());
+    let template_routes = template_history::routes(tracker.templates());
     tokio::spawn(async move {
         let metrics_route = warp::path("metrics").map(move || {
             let encoder = TextEncoder::new();
@@ -188,16 +251,34 @@ async fn main() {
         let addr: std::net::SocketAddr = prometheus_exporter_address
             .parse()
             .expect("Invalid address");
-        warp::serve(metrics_route).run(addr).await;
+        warp::serve(
+            metrics_route
+                .or(capture_routes)
+                .or(share_routes)
+                .or(template_routes),
+        )
+        .run(addr)
+        .await;
     });
 
+    let tap = Tap::new(
+        TrafficMetrics::register(),
+        capture,
+        Schedule::from_env(&proxy_type),
+        tracker.clone(),
+    );
+    let (from_client, to_client) = tap
+        .accept_client(listen_for_client(&client_address).await)
+        .await;
+    let (from_server, to_server) = tap
+        .connect_server(connect_to_server(&server_address).await)
+        .await;
+
     let mut proxy_builder = ProxyBuilder::new();
     proxy_builder
-        .try_add_client(listen_for_client(&client_address).await)
-        .await
+        .try_with_client(from_client, to_client)
         .unwrap()
-        .try_add_server(connect_to_server(&server_address).await)
-        .await
+        .try_with_server(from_server, to_server)
         .unwrap();
 
     // Handle proxy type specific logic
@@ -213,11 +294,19 @@ async fn main() {
                     &mut proxy_builder,
                     shares.clone(),
                     timestamp.clone(),
+                    share_store,
+                    tracker.clone(),
                 )
                 .await;
                 intercept_submit_share_success(&mut proxy_builder, valid.clone()).await;
                 intercept_submit_share_error(&mut proxy_builder, stale.clone()).await;
             }
+            if let Some(metrics) = mining_job_metrics {
+                mining_jobs::intercept_mining_jobs(&mut proxy_builder, metrics, tracker).await;
+            }
+            if let Some(metrics) = channel_metrics {
+                channels::intercept_channels(&mut proxy_builder, metrics).await;
+            }
         }
         "tp-pool" => {
             if let (
@@ -237,10 +326,19 @@ async fn main() {
                 )
                 .await;
             }
-            if let (Some(pool_latency), Some(mined)) =
-                (block_propagation_time_through_sv2_pool, mined_blocks)
-            {
-                intercept_submit_solution(&mut proxy_builder, pool_latency, mined).await;
+            if let (Some(pool_latency), Some(mined), Some(unmatched)) = (
+                block_propagation_time_through_sv2_pool,
+                mined_blocks,
+                unmatched_block_solutions,
+            ) {
+                intercept_submit_solution(
+                    &mut proxy_builder,
+                    pool_latency,
+                    mined,
+                    unmatched,
+                    tracker.clone(),
+                )
+                .await;
             }
             if let (Some(new_job_pool), Some(sv2_block_template_value)) =
                 (sv2_new_job_timestamp_pool, sv2_block_template_value)
@@ -267,10 +365,19 @@ async fn main() {
                 )
                 .await;
             }
-            if let (Some(jdc_latency), Some(mined)) =
-                (block_propagation_time_through_sv2_jdc, mined_blocks)
-            {
-                intercept_submit_solution(&mut proxy_builder, jdc_latency, mined).await;
+            if let (Some(jdc_latency), Some(mined), Some(unmatched)) = (
+                block_propagation_time_through_sv2_jdc,
+                mined_blocks,
+                unmatched_block_solutions,
+            ) {
+                intercept_submit_solution(
+                    &mut proxy_builder,
+                    jdc_latency,
+                    mined,
+                    unmatched,
+                    tracker.clone(),
+                )
+                .await;
             }
             if let (Some(new_job_jdc), Some(sv2_block_template_value)) =
                 (sv2_new_job_timestamp_jdc, sv2_block_template_value)
@@ -279,6 +386,11 @@ async fn main() {
                     .await;
             }
         }
+        "jdc-jds" => {
+            if let Some(metrics) = job_declaration_metrics {
+                job_declaration::intercept_job_declaration(&mut proxy_builder, metrics).await;
+            }
+        }
         _ => {
             panic!("Invalid PROXY_TYPE");
         }
@@ -326,6 +438,9 @@ fn reverse_hash(hash: &str) -> String {
 
 async fn fetch_block_reward(hash: &str) -> Result<u64, String> {
     let network = env::var("NETWORK").unwrap_or_else(|_| "".to_string());
+    if network == "regtest" {
+        return fetch_block_reward_from_node(hash).await;
+    }
     let client = Client::new();
     let url = match network.as_str() {
         "" => format!("https://mempool.space/api/block/{}", hash),
@@ -378,6 +493,59 @@ async fn fetch_block_reward(hash: &str) -> Result<u64, String> {
     }
 }
 
+// Node of the template provider at `NODE_RPC_URL`, with the credentials of the SV1 node-pool
+// proxy. Only asked on regtest, so only set up on the first regtest block.
+struct NodeRpc {
+    client: Client,
+    url: String,
+    credentials: RpcCredentials,
+}
+
+fn node_rpc() -> &'static NodeRpc {
+    static NODE_RPC: OnceLock<NodeRpc> = OnceLock::new();
+    NODE_RPC.get_or_init(|| {
+        let url = env::var("NODE_RPC_URL").unwrap_or_else(|_| DEFAULT_NODE_RPC_URL.to_string());
+        NodeRpc {
+            client: Client::builder()
+                .timeout(NODE_RPC_TIMEOUT)
+                .build()
+                .expect("reqwest client"),
+            credentials: RpcCredentials::from_env(&url),
+            url,
+        }
+    })
+}
+
+// Regtest blocks are only known to the local nodes, asked through their RPC
+async fn fetch_block_reward_from_node(hash: &str) -> Result<u64, String> {
+    let node = node_rpc();
+    let request = serde_json::json!({
+        "jsonrpc": "1.0",
+        "id": "sv2-custom-proxy",
+        "method": "getblockstats",
+        "params": [hash, ["subsidy", "totalfee"]],
+    });
+
+    let mut request = node.client.post(&node.url).json(&request);
+    if let Some((authorization, _)) = node.credentials.authorization() {
+        request = request.header(reqwest::header::AUTHORIZATION, authorization);
+    }
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let body = response.text().await.map_err(|e| e.to_string())?;
+    let json: Value = serde_json::from_str(&body).map_err(|e| e.to_string())?;
+    if !json["error"].is_null() {
+        return Err(format!("getblockstats failed: {}", json["error"]));
+    }
+
+    let subsidy = json["result"]["subsidy"]
+        .as_u64()
+        .ok_or("Failed to parse subsidy from block stats")?;
+    let fees = json["result"]["totalfee"]
+        .as_u64()
+        .ok_or("Failed to parse totalfee from block stats")?;
+    Ok(subsidy + fees)
+}
+
 async fn fetch_last_block_reward_with_retries(
     hash: &str,
     retries: usize,
@@ -480,11 +648,11 @@ async fn intercept_prev_hash(
                 .await
                 {
                     let reward_as_f64 = reward as f64;
-                    last_block_mined_value_clone.set(reward_as_f64);
+                    measurements::recorder::set(&last_block_mined_value_clone, reward_as_f64);
 
                     // Set the fetched metric value for last_sv2_block_template_value
                     if let Ok(value) = fetch_metric_result {
-                        last_sv2_block_template_value_clone.set(value);
+                        measurements::recorder::set(&last_sv2_block_template_value_clone, value);
                     } else {
                         log::error!("Error fetching metric");
                     }
@@ -521,7 +689,7 @@ async fn intercept_new_template(
             // Take the coinbase value and set the block template value metric
             let sv2_block_template_value_clone = sv2_block_template_value.clone();
             let block_value = m.coinbase_tx_value_remaining;
-            sv2_block_template_value_clone.set(block_value as f64);
+            measurements::recorder::set(&sv2_block_template_value_clone, block_value as f64);
         }
     });
 }
@@ -530,11 +698,25 @@ async fn intercept_submit_share_extended(
     builder: &mut ProxyBuilder,
     submitted_shares: Counter,
     gauge: GaugeVec,
+    share_store: ShareStore,
+    tracker: Tracker,
 ) {
     let mut r = builder.add_handler(Remote::Client, MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED);
     tokio::spawn(async move {
         while let Some(PoolMessages::Mining(Mining::SubmitSharesExtended(m))) = r.recv().await {
             submitted_shares.inc();
+            match tracker.job_prev_hash(m.job_id) {
+                Some(prev_hash) => share_store.insert(
+                    m.job_id.to_string(),
+                    HeaderFields {
+                        prev_hash: reverse_hash(&prev_hash),
+                        nonce: m.nonce,
+                        ntime: m.ntime,
+                        version: m.version,
+                    },
+                ),
+                None => log::warn!("SubmitSharesExtended for unknown job {}", m.job_id),
+            }
 
             let id = m.nonce;
             let current_time = std::time::SystemTime::now()
@@ -559,8 +741,9 @@ async fn intercept_submit_share_extended(
 async fn intercept_submit_share_success(builder: &mut ProxyBuilder, valid_shares: Counter) {
     let mut r = builder.add_handler(Remote::Server, MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS);
     tokio::spawn(async move {
-        while let Some(PoolMessages::Mining(Mining::SubmitSharesSuccess(_m))) = r.recv().await {
-            valid_shares.inc();
+        // A single SubmitSharesSuccess can acknowledge several shares
+        while let Some(PoolMessages::Mining(Mining::SubmitSharesSuccess(m))) = r.recv().await {
+            valid_shares.inc_by(m.new_submits_accepted_count as f64);
         }
     });
 }
@@ -579,9 +762,15 @@ async fn intercept_submit_solution(
     builder: &mut ProxyBuilder,
     block_propagation_time: Gauge,
     mined_blocks: Counter,
+    unmatched_block_solutions: Counter,
+    tracker: Tracker,
 ) {
     let mut r = builder.add_handler(Remote::Client, MESSAGE_TYPE_SUBMIT_SOLUTION);
-    let client = Client::new();
+    let client = Client::builder()
+        .timeout(SHARES_TIMEOUT)
+        .build()
+        .expect("reqwest client");
+    let shares_url = env::var("SHARES_URL").unwrap_or_else(|_| DEFAULT_SHARES_URL.to_string());
 
     tokio::spawn(async move {
         while let Some(PoolMessages::TemplateDistribution(TemplateDistribution::SubmitSolution(
@@ -592,25 +781,37 @@ async fn intercept_submit_solution(
                 .duration_since(std::time::UNIX_EPOCH)
                 .expect("Time went backwards")
                 .as_millis() as f64;
-            let id = m.header_nonce;
-            let url = "http://10.5.0.17:3456/metrics".to_string();
-            if let Ok(response) = client.get(&url).send().await {
-                if let Ok(body) = response.text().await {
-                    // Simple parsing to find the metric for the specific nonce
-                    for line in body.lines() {
-                        if line.starts_with("share_submission_timestamp{nonce=\"")
-                            && line.contains(&id.to_string())
-                        {
-                            let parts: Vec<&str> = line.split_whitespace().collect();
-                            if let Some(timestamp_str) = parts.get(1) {
-                                if let Ok(previous_timestamp) = timestamp_str.parse::<f64>() {
-                                    let latency = current_timestamp - previous_timestamp;
-                                    block_propagation_time.set(latency);
-                                    mined_blocks.inc();
-                                }
-                            }
-                        }
-                    }
+            mined_blocks.inc();
+            let Some(prev_hash) = tracker.template_prev_hash(m.template_id) else {
+                log::warn!("Block solution for unknown template {}", m.template_id);
+                unmatched_block_solutions.inc();
+                continue;
+            };
+            let header = HeaderFields {
+                prev_hash: reverse_hash(&prev_hash),
+                nonce: m.header_nonce,
+                ntime: m.header_timestamp,
+                version: m.version,
+            };
+            match share_store::lookup(&client, &shares_url, &header).await {
+                Some(share) => {
+                    log::info!(
+                        "Block solution for template {} found with a share of job {}",
+                        m.template_id,
+                        share.job_id
+                    );
+                    measurements::recorder::set(
+                        &block_propagation_time,
+                        current_timestamp - share.timestamp,
+                    );
+                }
+                None => {
+                    log::warn!(
+                        "No share matches the block solution for template {}: {:?}",
+                        m.template_id,
+                        header
+                    );
+                    unmatched_block_solutions.inc();
                 }
             }
         }
@@ -0,0 +1,276 @@
+use crate::encode_hex;
+use crate::template_history;
+use crate::tracker::Tracker;
+use demand_easy_sv2::const_sv2::{
+    MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH, MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+    MESSAGE_TYPE_SET_CUSTOM_MINING_JOB,
+};
+use demand_easy_sv2::roles_logic_sv2::parsers::{Mining, PoolMessages};
+use demand_easy_sv2::{ProxyBuilder, Remote};
+use prometheus::{
+    register_counter, register_counter_vec, register_gauge, register_gauge_vec, Counter,
+    CounterVec, Gauge, GaugeVec,
+};
+use reqwest::Client;
+use tokio::time::{sleep, Duration};
+
+#[derive(Clone)]
+pub struct MiningJobMetrics {
+    new_extended_mining_jobs: CounterVec,
+    new_extended_mining_job_timestamp: GaugeVec,
+    mining_job_template_id: GaugeVec,
+    template_to_job_latency: Gauge,
+    mining_prev_hash_timestamp: GaugeVec,
+    prev_hash_to_job_latency: Gauge,
+    custom_mining_jobs: Counter,
+    prev_hash_to_custom_job_latency: Gauge,
+    uncorrelated_mining_messages: CounterVec,
+}
+
+impl MiningJobMetrics {
+    pub fn register() -> Self {
+        Self {
+            new_extended_mining_jobs: register_counter_vec!(
+                "sv2_new_extended_mining_jobs",
+                "Total number of NewExtendedMiningJob sent by the upstream",
+                &["future"]
+            )
+            .unwrap(),
+            new_extended_mining_job_timestamp: register_gauge_vec!(
+                "sv2_new_extended_mining_job_timestamp",
+                "Timestamp of the NewExtendedMiningJob",
+                &["job_id"]
+            )
+            .unwrap(),
+            mining_job_template_id: register_gauge_vec!(
+                "sv2_mining_job_template_id",
+                "Id of the template the NewExtendedMiningJob has been built on",
+                &["job_id"]
+            )
+            .unwrap(),
+            template_to_job_latency: register_gauge!(
+                "sv2_template_to_job_latency",
+                "Time between the NewTemplate reaching the upstream and the NewExtendedMiningJob built on it in milliseconds"
+            )
+            .unwrap(),
+            mining_prev_hash_timestamp: register_gauge_vec!(
+                "sv2_mining_prev_hash_timestamp",
+                "Timestamp of the mining SetNewPrevHash activating the job",
+                &["job_id"]
+            )
+            .unwrap(),
+            prev_hash_to_job_latency: register_gauge!(
+                "sv2_prev_hash_to_job_latency",
+                "Time between the template distribution SetNewPrevHash reaching the upstream and the mining SetNewPrevHash in milliseconds"
+            )
+            .unwrap(),
+            custom_mining_jobs: register_counter!(
+                "sv2_custom_mining_jobs",
+                "Total number of SetCustomMiningJob sent by the downstream"
+            )
+            .unwrap(),
+            prev_hash_to_custom_job_latency: register_gauge!(
+                "sv2_prev_hash_to_custom_job_latency",
+                "Time between the template distribution SetNewPrevHash reaching the upstream and the SetCustomMiningJob on top of it in milliseconds"
+            )
+            .unwrap(),
+            uncorrelated_mining_messages: register_counter_vec!(
+                "sv2_uncorrelated_mining_messages",
+                "Total number of mining messages that could not be matched with a template or prev hash",
+                &["message"]
+            )
+            .unwrap(),
+        }
+    }
+}
+
+fn now_millis() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as f64
+}
+
+pub async fn intercept_mining_jobs(
+    builder: &mut ProxyBuilder,
+    metrics: MiningJobMetrics,
+    tracker: Tracker,
+) {
+    intercept_jobs(builder, metrics.clone(), tracker).await;
+    intercept_mining_prev_hash(builder, metrics.clone()).await;
+    intercept_set_custom_mining_job(builder, metrics).await;
+}
+
+async fn intercept_jobs(builder: &mut ProxyBuilder, metrics: MiningJobMetrics, tracker: Tracker) {
+    let mut r = builder.add_handler(Remote::Server, MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB);
+    let client = Client::new();
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Mining(Mining::NewExtendedMiningJob(m))) = r.recv().await {
+            // The tracker followed the job before it reached the proxy. Future jobs are built
+            // on future templates, whose prev hash isn't known yet
+            let future = m.is_future();
+            let prev_hash = tracker.job_prev_hash(m.job_id).filter(|_| !future);
+            new_extended_mining_job(&client, &metrics, m.job_id, future, prev_hash);
+        }
+    });
+}
+
+async fn intercept_mining_prev_hash(builder: &mut ProxyBuilder, metrics: MiningJobMetrics) {
+    let mut r = builder.add_handler(Remote::Server, MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH);
+    let client = Client::new();
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Mining(Mining::SetNewPrevHash(m))) = r.recv().await {
+            let prev_hash_hex = encode_hex(m.prev_hash.inner_as_ref());
+            mining_prev_hash(&client, &metrics, m.job_id, prev_hash_hex);
+        }
+    });
+}
+
+fn new_extended_mining_job(
+    client: &Client,
+    metrics: &MiningJobMetrics,
+    job_id: u32,
+    future: bool,
+    prev_hash: Option<String>,
+) {
+    let current_time = now_millis();
+    let job_id = job_id.to_string();
+    metrics
+        .new_extended_mining_jobs
+        .with_label_values(&[&future.to_string()])
+        .inc();
+    // Set right away, the sv1 translator-miner proxy looks it up as soon as the translator
+    // sends the corresponding mining.notify
+    metrics
+        .new_extended_mining_job_timestamp
+        .with_label_values(&[&job_id])
+        .set(current_time);
+
+    let client = client.clone();
+    let metrics = metrics.clone();
+    tokio::spawn(async move {
+        // The job is built on the last template on its prev hash (or the last future one)
+        // forwarded before it
+        let template = template_history::fetch_templates(&client)
+            .await
+            .into_iter()
+            .rev()
+            .find(|template| {
+                template.timestamp <= current_time
+                    && match &prev_hash {
+                        Some(prev_hash) => {
+                            !template.future && template.prev_hash.as_ref() == Some(prev_hash)
+                        }
+                        None => template.future,
+                    }
+            });
+        match template {
+            Some(template) => {
+                log::debug!(
+                    "Job {} (future: {}) built on template {}",
+                    job_id,
+                    future,
+                    template.template_id
+                );
+                metrics
+                    .mining_job_template_id
+                    .with_label_values(&[&job_id])
+                    .set(template.template_id as f64);
+                measurements::recorder::set(
+                    &metrics.template_to_job_latency,
+                    current_time - template.timestamp,
+                );
+            }
+            None => {
+                metrics
+                    .uncorrelated_mining_messages
+                    .with_label_values(&["NewExtendedMiningJob"])
+                    .inc();
+            }
+        }
+
+        sleep(Duration::from_secs(10)).await;
+        // Remove the metrics from Prometheus
+        let _ = metrics
+            .new_extended_mining_job_timestamp
+            .remove_label_values(&[&job_id]);
+        let _ = metrics
+            .mining_job_template_id
+            .remove_label_values(&[&job_id]);
+    });
+}
+
+fn mining_prev_hash(
+    client: &Client,
+    metrics: &MiningJobMetrics,
+    job_id: u32,
+    prev_hash_hex: String,
+) {
+    let current_time = now_millis();
+    let job_id = job_id.to_string();
+    metrics
+        .mining_prev_hash_timestamp
+        .with_label_values(&[&job_id])
+        .set(current_time);
+
+    let client = client.clone();
+    let metrics = metrics.clone();
+    tokio::spawn(async move {
+        match fetch_prev_hash_timestamp(&client, &prev_hash_hex).await {
+            Some(prev_hash_timestamp) => measurements::recorder::set(
+                &metrics.prev_hash_to_job_latency,
+                current_time - prev_hash_timestamp,
+            ),
+            None => metrics
+                .uncorrelated_mining_messages
+                .with_label_values(&["SetNewPrevHash"])
+                .inc(),
+        }
+
+        sleep(Duration::from_secs(10)).await;
+        // Remove the metric from Prometheus
+        let _ = metrics
+            .mining_prev_hash_timestamp
+            .remove_label_values(&[&job_id]);
+    });
+}
+
+// Only seen when the proxy sits on a connection carrying custom jobs (JDC -> pool)
+async fn intercept_set_custom_mining_job(builder: &mut ProxyBuilder, metrics: MiningJobMetrics) {
+    let mut r = builder.add_handler(Remote::Client, MESSAGE_TYPE_SET_CUSTOM_MINING_JOB);
+    let client = Client::new();
+    tokio::spawn(async move {
+        while let Some(PoolMessages::Mining(Mining::SetCustomMiningJob(m))) = r.recv().await {
+            let current_time = now_millis();
+            metrics.custom_mining_jobs.inc();
+            let mut prev_hash = m.prev_hash;
+            let prev_hash_hex = encode_hex(prev_hash.inner_as_mut());
+
+            let client = client.clone();
+            let metrics = metrics.clone();
+            tokio::spawn(async move {
+                match fetch_prev_hash_timestamp(&client, &prev_hash_hex).await {
+                    Some(prev_hash_timestamp) => measurements::recorder::set(
+                        &metrics.prev_hash_to_custom_job_latency,
+                        current_time - prev_hash_timestamp,
+                    ),
+                    None => metrics
+                        .uncorrelated_mining_messages
+                        .with_label_values(&["SetCustomMiningJob"])
+                        .inc(),
+                }
+            });
+        }
+    });
+}
+
+// The TP proxy writes prev hashes with the same byte order used here, so they can be matched
+// exactly. The last one is taken, the same prev hash can come back after a reorg.
+async fn fetch_prev_hash_timestamp(client: &Client, prev_hash_hex: &str) -> Option<f64> {
+    template_history::fetch_prev_hashes(client)
+        .await
+        .into_iter()
+        .rev()
+        .find(|prev_hash| prev_hash.prev_hash == prev_hash_hex)
+        .map(|prev_hash| prev_hash.timestamp)
+}
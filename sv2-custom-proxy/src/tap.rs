@@ -0,0 +1,143 @@
+use crate::capture::Capture;
+use crate::faults::{held_expired, Action, Schedule};
+use crate::tracker::Tracker;
+use crate::traffic::{Direction, TrafficMetrics};
+use bench_common::keys;
+use codec_sv2::{HandshakeRole, Initiator, Responder};
+use demand_easy_sv2::{Frame_, PoolMessages};
+use demand_sv2_connection::noise_connection_tokio::Connection;
+use key_utils::{Secp256k1PublicKey, Secp256k1SecretKey};
+use std::time::Instant;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::sleep;
+
+// Every proxy instance forwards a single downstream connection
+const CONNECTION_ID: u64 = 0;
+
+/// Sits between the noise connections and the proxy, seeing every frame the proxy receives
+/// and deciding when, or whether, it's delivered.
+#[derive(Clone)]
+pub struct Tap {
+    traffic: TrafficMetrics,
+    capture: Option<Capture>,
+    faults: Schedule,
+    tracker: Tracker,
+}
+
+impl Tap {
+    pub fn new(
+        traffic: TrafficMetrics,
+        capture: Option<Capture>,
+        faults: Schedule,
+        tracker: Tracker,
+    ) -> Self {
+        Self {
+            traffic,
+            capture,
+            faults,
+            tracker,
+        }
+    }
+
+    /// Completes the noise handshake with the downstream and returns the channels to hand to
+    /// `ProxyBuilder::try_with_client`.
+    pub async fn accept_client(&self, stream: TcpStream) -> (Receiver<Frame_>, Sender<Frame_>) {
+        let public: Secp256k1PublicKey = keys::PUB_KEY.parse().expect("Invalid proxy pub key");
+        let secret: Secp256k1SecretKey = keys::SEC_KEY.parse().expect("Invalid proxy sec key");
+        let responder = Responder::from_authority_kp(
+            &public.into_bytes(),
+            &secret.into_bytes(),
+            keys::CERT_VALIDITY,
+        )
+        .expect("invalid key pair");
+        let (receiver, sender, _, _) = Connection::new::<'static, PoolMessages<'static>>(
+            stream,
+            HandshakeRole::Responder(responder),
+        )
+        .await
+        .expect("Impossible to complete handshake with downstream");
+        (self.spawn(receiver, Direction::Upstream), sender)
+    }
+
+    /// Completes the noise handshake with the upstream and returns the channels to hand to
+    /// `ProxyBuilder::try_with_server`.
+    pub async fn connect_server(&self, stream: TcpStream) -> (Receiver<Frame_>, Sender<Frame_>) {
+        let initiator = Initiator::without_pk().expect("This fn call can not fail");
+        let (receiver, sender, _, _) = Connection::new::<'static, PoolMessages<'static>>(
+            stream,
+            HandshakeRole::Initiator(initiator),
+        )
+        .await
+        .expect("Impossible to complete handshake with upstream");
+        (self.spawn(receiver, Direction::Downstream), sender)
+    }
+
+    fn spawn(&self, mut receiver: Receiver<Frame_>, direction: Direction) -> Receiver<Frame_> {
+        let (sender, tapped) = channel(10);
+        let tap = self.clone();
+        tokio::spawn(async move {
+            // Frame held back by a reorder fault
+            let mut held: Option<(Frame_, Instant)> = None;
+            loop {
+                let mut frame = tokio::select! {
+                    frame = receiver.recv() => match frame {
+                        Some(frame) => frame,
+                        None => break,
+                    },
+                    _ = held_expired(held.as_ref().map(|(_, deadline)| *deadline)) => {
+                        let Some((mut frame, _)) = held.take() else { continue };
+                        tap.track(direction, &mut frame);
+                        if sender.send(frame).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    // Dropping the sender closes the proxy, and with it both connections
+                    _ = tap.faults.severed(direction) => break,
+                };
+                tap.traffic.record(direction, &frame);
+                if let Some(capture) = &tap.capture {
+                    capture.record(CONNECTION_ID, direction, &mut frame);
+                }
+                let frames = match tap.faults.apply(direction, &frame) {
+                    Action::Forward(delay) => {
+                        if !delay.is_zero() {
+                            sleep(delay).await;
+                        }
+                        // The frame overtakes the held one
+                        std::iter::once(frame)
+                            .chain(held.take().map(|(frame, _)| frame))
+                            .collect()
+                    }
+                    Action::Drop => Vec::new(),
+                    Action::Hold(max) => {
+                        let previous = held.replace((frame, Instant::now() + max));
+                        previous.map(|(frame, _)| frame).into_iter().collect()
+                    }
+                    Action::Sever => break,
+                };
+                let mut closed = false;
+                for mut frame in frames {
+                    tap.track(direction, &mut frame);
+                    if sender.send(frame).await.is_err() {
+                        closed = true;
+                        break;
+                    }
+                }
+                if closed {
+                    break;
+                }
+            }
+        });
+        tapped
+    }
+
+    // The frames of the upstream are tracked in the order they're forwarded, which faults may
+    // change, and before the proxy handlers get them
+    fn track(&self, direction: Direction, frame: &mut Frame_) {
+        if let Direction::Downstream = direction {
+            self.tracker.record(frame);
+        }
+    }
+}
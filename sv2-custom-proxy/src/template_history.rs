@@ -0,0 +1,147 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::env;
+use std::sync::{Arc, Mutex};
+use warp::Filter;
+
+// Metrics endpoint of the proxy sitting between the TP and the pool (config C) or the JDC
+// (config A)
+const DEFAULT_TP_PROXY_URL: &str = "http://10.5.0.20:5678";
+// Messages remembered, the other proxies may look them up long after they were forwarded
+const MAX_MESSAGES: usize = 100;
+
+/// A SetNewPrevHash forwarded by the TP proxy.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PrevHash {
+    pub template_id: u64,
+    // Hex of the hash in the byte order of the message, as the mining messages carry it
+    pub prev_hash: String,
+    // Milliseconds since the epoch
+    pub timestamp: f64,
+}
+
+/// A NewTemplate forwarded by the TP proxy.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Template {
+    pub template_id: u64,
+    pub future: bool,
+    // The prev hash the template is built on, for a future template once its SetNewPrevHash
+    // has been forwarded
+    pub prev_hash: Option<String>,
+    // Milliseconds since the epoch
+    pub timestamp: f64,
+}
+
+/// The last template distribution messages forwarded by the TP proxy, served on its metrics
+/// port so that the proxies of the other connections can time their messages against them.
+/// Filled by the [`Tracker`](crate::tracker::Tracker) of the proxy.
+#[derive(Clone, Default)]
+pub struct TemplateHistory {
+    templates: Arc<Mutex<VecDeque<Template>>>,
+    prev_hashes: Arc<Mutex<VecDeque<PrevHash>>>,
+}
+
+impl TemplateHistory {
+    pub fn new_template(&self, template_id: u64, future: bool) {
+        // A template sent right after a SetNewPrevHash is built on top of it
+        let prev_hash = match future {
+            true => None,
+            false => self
+                .prev_hashes
+                .lock()
+                .unwrap()
+                .back()
+                .map(|prev_hash| prev_hash.prev_hash.clone()),
+        };
+        push(
+            &mut self.templates.lock().unwrap(),
+            Template {
+                template_id,
+                future,
+                prev_hash,
+                timestamp: now_millis(),
+            },
+        );
+    }
+
+    pub fn new_prev_hash(&self, template_id: u64, prev_hash: String) {
+        let prev_hash = PrevHash {
+            template_id,
+            prev_hash,
+            timestamp: now_millis(),
+        };
+        for template in self.templates.lock().unwrap().iter_mut() {
+            if template.template_id == prev_hash.template_id && template.prev_hash.is_none() {
+                template.prev_hash = Some(prev_hash.prev_hash.clone());
+            }
+        }
+        push(&mut self.prev_hashes.lock().unwrap(), prev_hash);
+    }
+
+    pub fn templates(&self) -> Vec<Template> {
+        self.templates.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn prev_hashes(&self) -> Vec<PrevHash> {
+        self.prev_hashes.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+fn push<T>(messages: &mut VecDeque<T>, message: T) {
+    messages.push_back(message);
+    while messages.len() > MAX_MESSAGES {
+        messages.pop_front();
+    }
+}
+
+fn now_millis() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as f64
+}
+
+/// `GET /templates` and `GET /prev-hashes` return the last NewTemplate and SetNewPrevHash
+/// forwarded by the proxy, oldest first.
+pub fn routes(
+    history: TemplateHistory,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let templates = {
+        let history = history.clone();
+        warp::path("templates")
+            .and(warp::path::end())
+            .and(warp::get())
+            .map(move || warp::reply::json(&history.templates()))
+    };
+    let prev_hashes = warp::path("prev-hashes")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || warp::reply::json(&history.prev_hashes()));
+    templates.or(prev_hashes)
+}
+
+/// The last NewTemplate forwarded by the TP proxy at `TP_PROXY_URL`, oldest first.
+pub async fn fetch_templates(client: &Client) -> Vec<Template> {
+    fetch(client, "templates").await
+}
+
+/// The last SetNewPrevHash forwarded by the TP proxy at `TP_PROXY_URL`, oldest first.
+pub async fn fetch_prev_hashes(client: &Client) -> Vec<PrevHash> {
+    fetch(client, "prev-hashes").await
+}
+
+async fn fetch<T: serde::de::DeserializeOwned>(client: &Client, path: &str) -> Vec<T> {
+    let url = env::var("TP_PROXY_URL").unwrap_or_else(|_| DEFAULT_TP_PROXY_URL.to_string());
+    let response = match client.get(format!("{}/{}", url, path)).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            log::error!("Failed to get the {} of the TP proxy: {}", path, e);
+            return Vec::new();
+        }
+    };
+    response.json().await.unwrap_or_else(|e| {
+        log::error!("Invalid {} from the TP proxy: {}", path, e);
+        Vec::new()
+    })
+}
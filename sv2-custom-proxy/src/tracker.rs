@@ -0,0 +1,125 @@
+use crate::encode_hex;
+use crate::template_history::TemplateHistory;
+use demand_easy_sv2::const_sv2::{
+    MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH, MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB,
+    MESSAGE_TYPE_NEW_TEMPLATE, MESSAGE_TYPE_SET_NEW_PREV_HASH,
+};
+use demand_easy_sv2::roles_logic_sv2::parsers::{Mining, TemplateDistribution};
+use demand_easy_sv2::Frame_;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+
+// Jobs remembered to find the previous block hash of the shares
+const MAX_JOBS: usize = 1000;
+
+#[derive(Default)]
+struct Jobs {
+    prev_hashes: HashMap<u32, String>,
+    order: VecDeque<u32>,
+    // Previous block hash of the last mining SetNewPrevHash
+    current: Option<String>,
+}
+
+impl Jobs {
+    fn insert(&mut self, job_id: u32, prev_hash: String) {
+        if self.prev_hashes.insert(job_id, prev_hash).is_none() {
+            self.order.push_back(job_id);
+        }
+        while self.order.len() > MAX_JOBS {
+            if let Some(job_id) = self.order.pop_front() {
+                self.prev_hashes.remove(&job_id);
+            }
+        }
+    }
+}
+
+/// Follows the jobs (mining protocol) and templates (template distribution protocol) sent by the
+/// upstream, to know the previous block hash each one is built on: the one of its
+/// SetNewPrevHash for a future job or template, the last one for the others. Shares only carry
+/// the job id and solutions the template id.
+///
+/// The order of the messages matters, so the tracker is fed by the tap with every frame the
+/// upstream sends, in the order it's forwarded and before the proxy handlers get it. Hashes are
+/// in the byte order of the messages.
+#[derive(Clone, Default)]
+pub struct Tracker {
+    jobs: Arc<Mutex<Jobs>>,
+    templates: TemplateHistory,
+}
+
+impl Tracker {
+    pub fn templates(&self) -> TemplateHistory {
+        self.templates.clone()
+    }
+
+    pub fn job_prev_hash(&self, job_id: u32) -> Option<String> {
+        self.jobs.lock().unwrap().prev_hashes.get(&job_id).cloned()
+    }
+
+    pub fn template_prev_hash(&self, template_id: u64) -> Option<String> {
+        self.templates
+            .templates()
+            .into_iter()
+            .rev()
+            .find(|template| template.template_id == template_id)
+            .and_then(|template| template.prev_hash)
+    }
+
+    /// Follows a frame sent by the upstream.
+    pub fn record(&self, frame: &mut Frame_) {
+        let Frame_::Sv2(sv2_frame) = frame else {
+            return;
+        };
+        let Some(header) = sv2_frame.get_header() else {
+            return;
+        };
+        let message_type = header.msg_type();
+        let mut payload = sv2_frame.payload().to_vec();
+        match message_type {
+            MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB | MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH => {
+                match (message_type, payload.as_mut_slice()).try_into() {
+                    Ok(message) => self.mining(message),
+                    Err(e) => log::warn!("Invalid mining message {}: {:?}", message_type, e),
+                }
+            }
+            MESSAGE_TYPE_NEW_TEMPLATE | MESSAGE_TYPE_SET_NEW_PREV_HASH => {
+                match (message_type, payload.as_mut_slice()).try_into() {
+                    Ok(message) => self.template_distribution(message),
+                    Err(e) => log::warn!("Invalid template message {}: {:?}", message_type, e),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn mining(&self, message: Mining) {
+        let mut jobs = self.jobs.lock().unwrap();
+        match message {
+            Mining::NewExtendedMiningJob(m) => {
+                // Future jobs wait for their SetNewPrevHash
+                if let Some(prev_hash) = jobs.current.clone().filter(|_| !m.is_future()) {
+                    jobs.insert(m.job_id, prev_hash);
+                }
+            }
+            Mining::SetNewPrevHash(m) => {
+                let prev_hash = encode_hex(m.prev_hash.inner_as_ref());
+                jobs.current = Some(prev_hash.clone());
+                jobs.insert(m.job_id, prev_hash);
+            }
+            _ => {}
+        }
+    }
+
+    fn template_distribution(&self, message: TemplateDistribution) {
+        match message {
+            TemplateDistribution::NewTemplate(m) => self
+                .templates
+                .new_template(m.template_id, m.future_template),
+            TemplateDistribution::SetNewPrevHash(m) => self
+                .templates
+                .new_prev_hash(m.template_id, encode_hex(m.prev_hash.inner_as_ref())),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use demand_easy_sv2::const_sv2::*;
+use demand_easy_sv2::Frame_;
+use prometheus::{register_counter_vec, CounterVec};
+
+#[derive(Clone, Copy)]
+pub enum Direction {
+    // Frames sent by the client (downstream) to the server (upstream)
+    Upstream,
+    // Frames sent by the server (upstream) to the client (downstream)
+    Downstream,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Upstream => "upstream",
+            Direction::Downstream => "downstream",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TrafficMetrics {
+    messages: CounterVec,
+    bytes: CounterVec,
+}
+
+impl TrafficMetrics {
+    pub fn register() -> Self {
+        Self {
+            messages: register_counter_vec!(
+                "sv2_traffic_messages",
+                "Total number of SV2 frames by direction and message type",
+                &["direction", "message_type"]
+            )
+            .unwrap(),
+            bytes: register_counter_vec!(
+                "sv2_traffic_bytes",
+                "Total number of SV2 bytes on the wire by direction, message type and part of the frame (payload, framing or encryption)",
+                &["direction", "message_type", "part"]
+            )
+            .unwrap(),
+        }
+    }
+
+    pub fn record(&self, direction: Direction, frame: &Frame_) {
+        let Frame_::Sv2(sv2_frame) = frame else {
+            // Handshake frames are exchanged before the proxy gets the connection
+            return;
+        };
+        let Some(header) = sv2_frame.get_header() else {
+            return;
+        };
+        let message_type = message_type_name(header.msg_type());
+        let payload = (frame.encoded_length() - SV2_FRAME_HEADER_SIZE) as f64;
+        let (framing, encryption) = frame_overhead(frame.encoded_length() - SV2_FRAME_HEADER_SIZE);
+        let direction = direction.as_str();
+
+        self.messages
+            .with_label_values(&[direction, message_type])
+            .inc();
+        for (part, bytes) in [
+            ("payload", payload),
+            ("framing", framing as f64),
+            ("encryption", encryption as f64),
+        ] {
+            self.bytes
+                .with_label_values(&[direction, message_type, part])
+                .inc_by(bytes);
+        }
+    }
+}
+
+// Returns the framing and encryption bytes added on the wire to a payload of `payload_len`
+// bytes. Noise encrypts the header on its own and splits the payload in chunks, each one
+// carrying its own MAC.
+pub fn frame_overhead(payload_len: usize) -> (usize, usize) {
+    let chunks = payload_len.div_ceil(SV2_FRAME_CHUNK_SIZE - AEAD_MAC_LEN);
+    (SV2_FRAME_HEADER_SIZE, AEAD_MAC_LEN * (chunks + 1))
+}
+
+pub fn message_type_name(message_type: u8) -> &'static str {
+    match message_type {
+        MESSAGE_TYPE_SETUP_CONNECTION => "SetupConnection",
+        MESSAGE_TYPE_SETUP_CONNECTION_SUCCESS => "SetupConnectionSuccess",
+        MESSAGE_TYPE_SETUP_CONNECTION_ERROR => "SetupConnectionError",
+        MESSAGE_TYPE_CHANNEL_ENDPOINT_CHANGED => "ChannelEndpointChanged",
+        MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL => "OpenStandardMiningChannel",
+        MESSAGE_TYPE_OPEN_STANDARD_MINING_CHANNEL_SUCCESS => "OpenStandardMiningChannelSuccess",
+        MESSAGE_TYPE_OPEN_MINING_CHANNEL_ERROR => "OpenMiningChannelError",
+        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL => "OpenExtendedMiningChannel",
+        MESSAGE_TYPE_OPEN_EXTENDED_MINING_CHANNEL_SUCCES => "OpenExtendedMiningChannelSuccess",
+        MESSAGE_TYPE_NEW_MINING_JOB => "NewMiningJob",
+        MESSAGE_TYPE_UPDATE_CHANNEL => "UpdateChannel",
+        MESSAGE_TYPE_UPDATE_CHANNEL_ERROR => "UpdateChannelError",
+        MESSAGE_TYPE_CLOSE_CHANNEL => "CloseChannel",
+        MESSAGE_TYPE_SET_EXTRANONCE_PREFIX => "SetExtranoncePrefix",
+        MESSAGE_TYPE_SUBMIT_SHARES_STANDARD => "SubmitSharesStandard",
+        MESSAGE_TYPE_SUBMIT_SHARES_EXTENDED => "SubmitSharesExtended",
+        MESSAGE_TYPE_SUBMIT_SHARES_SUCCESS => "SubmitSharesSuccess",
+        MESSAGE_TYPE_SUBMIT_SHARES_ERROR => "SubmitSharesError",
+        MESSAGE_TYPE_NEW_EXTENDED_MINING_JOB => "NewExtendedMiningJob",
+        MESSAGE_TYPE_MINING_SET_NEW_PREV_HASH => "SetNewPrevHash",
+        MESSAGE_TYPE_SET_TARGET => "SetTarget",
+        MESSAGE_TYPE_SET_CUSTOM_MINING_JOB => "SetCustomMiningJob",
+        MESSAGE_TYPE_SET_CUSTOM_MINING_JOB_SUCCESS => "SetCustomMiningJobSuccess",
+        MESSAGE_TYPE_SET_CUSTOM_MINING_JOB_ERROR => "SetCustomMiningJobError",
+        MESSAGE_TYPE_RECONNECT => "Reconnect",
+        MESSAGE_TYPE_SET_GROUP_CHANNEL => "SetGroupChannel",
+        MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN => "AllocateMiningJobToken",
+        MESSAGE_TYPE_ALLOCATE_MINING_JOB_TOKEN_SUCCESS => "AllocateMiningJobTokenSuccess",
+        MESSAGE_TYPE_IDENTIFY_TRANSACTIONS => "IdentifyTransactions",
+        MESSAGE_TYPE_IDENTIFY_TRANSACTIONS_SUCCESS => "IdentifyTransactionsSuccess",
+        MESSAGE_TYPE_PROVIDE_MISSING_TRANSACTIONS => "ProvideMissingTransactions",
+        MESSAGE_TYPE_PROVIDE_MISSING_TRANSACTIONS_SUCCESS => "ProvideMissingTransactionsSuccess",
+        MESSAGE_TYPE_DECLARE_MINING_JOB => "DeclareMiningJob",
+        MESSAGE_TYPE_DECLARE_MINING_JOB_SUCCESS => "DeclareMiningJobSuccess",
+        MESSAGE_TYPE_DECLARE_MINING_JOB_ERROR => "DeclareMiningJobError",
+        MESSAGE_TYPE_SUBMIT_SOLUTION_JD => "PushSolution",
+        MESSAGE_TYPE_COINBASE_OUTPUT_DATA_SIZE => "CoinbaseOutputDataSize",
+        MESSAGE_TYPE_NEW_TEMPLATE => "NewTemplate",
+        MESSAGE_TYPE_SET_NEW_PREV_HASH => "SetNewPrevHash",
+        MESSAGE_TYPE_REQUEST_TRANSACTION_DATA => "RequestTransactionData",
+        MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_SUCCESS => "RequestTransactionDataSuccess",
+        MESSAGE_TYPE_REQUEST_TRANSACTION_DATA_ERROR => "RequestTransactionDataError",
+        MESSAGE_TYPE_SUBMIT_SOLUTION => "SubmitSolution",
+        _ => "Unknown",
+    }
+}
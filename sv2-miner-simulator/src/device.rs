@@ -0,0 +1,646 @@
+use bench_common::hash;
+use bench_common::hasher::{self, Merkle};
+use codec_sv2::{HandshakeRole, Initiator};
+use demand_easy_sv2::roles_logic_sv2::common_messages_sv2::{Protocol, SetupConnection};
+use demand_easy_sv2::roles_logic_sv2::mining_sv2::{
+    OpenExtendedMiningChannel, OpenStandardMiningChannel, SubmitSharesExtended,
+    SubmitSharesStandard,
+};
+use demand_easy_sv2::roles_logic_sv2::parsers::{CommonMessages, Mining};
+use demand_easy_sv2::{Frame_, PoolMessages, StdFrame};
+use demand_sv2_connection::noise_connection_tokio::Connection;
+use key_utils::Secp256k1PublicKey;
+use log::{error, info, warn};
+use prometheus::{register_counter_vec, register_gauge_vec, CounterVec, GaugeVec};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::watch;
+use tokio::time::{sleep, Duration, Instant};
+
+// Shares are submitted for the id of the job they were found on
+type Work = hasher::Work<u32>;
+type Share = hasher::Share<u32>;
+
+// Wait before connecting again after the upstream refused or closed the connection
+const RETRY: Duration = Duration::from_secs(5);
+// SetupConnection flag of the mining protocol asking for NewMiningJob on standard channels
+const REQUIRES_STANDARD_JOBS: u32 = 0b001;
+// Version bits a device may roll (BIP 320)
+const VERSION_ROLLING_MASK: u32 = 0x1fff_e000;
+// Extranonce bytes asked for on extended channels
+const MIN_EXTRANONCE_SIZE: u16 = 4;
+// The upstream switches to transport mode only after sending the last handshake message, a
+// frame sent right away on a local link can reach it before and end the connection
+const HANDSHAKE_SETTLE: Duration = Duration::from_millis(100);
+// Only one channel is opened per connection
+const REQUEST_ID: u32 = 0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ChannelType {
+    Standard,
+    Extended,
+}
+
+/// What every simulated device is configured with.
+pub struct Settings {
+    pub upstream_address: String,
+    // Authority key of the upstream, the certificate of the upstream isn't checked without it
+    pub authority_pubkey: Option<Secp256k1PublicKey>,
+    pub channel_type: ChannelType,
+    pub user: String,
+    // Hashes per second of each device
+    pub hashrate: f64,
+}
+
+#[derive(Clone)]
+pub struct DeviceMetrics {
+    hashes: CounterVec,
+    shares: CounterVec,
+    jobs: CounterVec,
+    blocks: CounterVec,
+    difficulty: GaugeVec,
+    job_first_share_latency: GaugeVec,
+}
+
+impl DeviceMetrics {
+    pub fn register() -> Self {
+        Self {
+            hashes: register_counter_vec!(
+                "sv2_simulated_hashes",
+                "Total number of headers hashed by the simulated devices",
+                &["device"]
+            )
+            .unwrap(),
+            shares: register_counter_vec!(
+                "sv2_simulated_shares",
+                "Total number of shares of the simulated devices by result (submitted, accepted, rejected)",
+                &["device", "result"]
+            )
+            .unwrap(),
+            jobs: register_counter_vec!(
+                "sv2_simulated_jobs",
+                "Total number of jobs the simulated devices started hashing",
+                &["device"]
+            )
+            .unwrap(),
+            blocks: register_counter_vec!(
+                "sv2_simulated_blocks",
+                "Total number of shares of the simulated devices meeting the network target",
+                &["device"]
+            )
+            .unwrap(),
+            difficulty: register_gauge_vec!(
+                "sv2_simulated_difficulty",
+                "Difficulty of the target of the channel of the simulated devices",
+                &["device"]
+            )
+            .unwrap(),
+            job_first_share_latency: register_gauge_vec!(
+                "sv2_simulated_job_first_share_latency",
+                "Time between a job becoming active on a simulated device and its first share in milliseconds",
+                &["device"]
+            )
+            .unwrap(),
+        }
+    }
+}
+
+// A job of the channel, active or waiting for its SetNewPrevHash
+struct Job {
+    version: u32,
+    version_rolling: bool,
+    min_ntime: Option<u32>,
+    kind: JobKind,
+}
+
+enum JobKind {
+    Standard {
+        merkle_root: [u8; 32],
+    },
+    Extended {
+        coinbase_prefix: Vec<u8>,
+        coinbase_suffix: Vec<u8>,
+        merkle_path: Vec<[u8; 32]>,
+    },
+}
+
+struct PrevHash {
+    prev_hash: [u8; 32],
+    min_ntime: u32,
+    bits: u32,
+}
+
+struct OpenChannel {
+    id: u32,
+    group_id: Option<u32>,
+    extranonce_prefix: Vec<u8>,
+    extranonce_size: usize,
+    target: [u8; 32],
+}
+
+// How a session ends
+enum Ending {
+    Disconnected,
+    Reconnect(String),
+}
+
+/// A simulated device: a hashing thread fed with the jobs of the channel of one SV2 connection,
+/// reconnecting whenever the connection ends.
+pub async fn run(index: usize, settings: Arc<Settings>, metrics: DeviceMetrics) {
+    let (work_sender, work) = watch::channel(None);
+    let (share_sender, mut shares) = mpsc::unbounded_channel();
+    let hashrate = settings.hashrate;
+    let hashes = metrics.hashes.with_label_values(&[&index.to_string()]);
+    std::thread::spawn(move || hasher::run(work, share_sender, hashrate, hashes));
+
+    let mut upstream_address = settings.upstream_address.clone();
+    loop {
+        let connection = match connect(&upstream_address, &settings).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!(
+                    "Device {} can't connect to {}: {}",
+                    index, upstream_address, e
+                );
+                sleep(RETRY).await;
+                continue;
+            }
+        };
+        info!("Device {} connected to {}", index, upstream_address);
+        // Shares of the previous connection are not submitted
+        while shares.try_recv().is_ok() {}
+        let mut session = Session::new(index, &upstream_address, &settings, &metrics, &work_sender);
+        let (receiver, sender) = connection;
+        match session.run(receiver, sender, &mut shares).await {
+            Ending::Reconnect(address) => {
+                info!("Device {} asked to reconnect to {}", index, address);
+                upstream_address = address;
+            }
+            Ending::Disconnected => {
+                warn!("Device {} disconnected from {}", index, upstream_address);
+                sleep(RETRY).await;
+            }
+        }
+        work_sender.send_replace(None);
+    }
+}
+
+// Opens the connection and completes the noise handshake
+async fn connect(
+    address: &str,
+    settings: &Settings,
+) -> Result<(Receiver<Frame_>, Sender<Frame_>), String> {
+    let stream = TcpStream::connect(address)
+        .await
+        .map_err(|e| e.to_string())?;
+    let initiator = match settings.authority_pubkey {
+        Some(key) => Initiator::from_raw_k(key.into_bytes()).map_err(|e| format!("{:?}", e))?,
+        None => Initiator::without_pk().expect("This fn call can not fail"),
+    };
+    let (receiver, sender, _, _) = Connection::new::<'static, PoolMessages<'static>>(
+        stream,
+        HandshakeRole::Initiator(initiator),
+    )
+    .await
+    .map_err(|e| format!("noise handshake failed: {:?}", e))?;
+    Ok((receiver, sender))
+}
+
+struct Session<'a> {
+    device: String,
+    upstream_address: String,
+    settings: &'a Settings,
+    metrics: &'a DeviceMetrics,
+    work: &'a watch::Sender<Option<Work>>,
+    channel: Option<OpenChannel>,
+    jobs: HashMap<u32, Job>,
+    prev_hash: Option<PrevHash>,
+    // The job being hashed, and when it became active until its first share
+    active_job: Option<u32>,
+    activated_at: Option<Instant>,
+    sequence_number: u32,
+}
+
+impl<'a> Session<'a> {
+    fn new(
+        index: usize,
+        upstream_address: &str,
+        settings: &'a Settings,
+        metrics: &'a DeviceMetrics,
+        work: &'a watch::Sender<Option<Work>>,
+    ) -> Self {
+        Self {
+            device: index.to_string(),
+            upstream_address: upstream_address.to_string(),
+            settings,
+            metrics,
+            work,
+            channel: None,
+            jobs: HashMap::new(),
+            prev_hash: None,
+            active_job: None,
+            activated_at: None,
+            sequence_number: 0,
+        }
+    }
+
+    async fn run(
+        &mut self,
+        mut receiver: Receiver<Frame_>,
+        sender: Sender<Frame_>,
+        shares: &mut mpsc::UnboundedReceiver<Share>,
+    ) -> Ending {
+        let (host, port) = self
+            .upstream_address
+            .rsplit_once(':')
+            .unwrap_or((self.upstream_address.as_str(), "0"));
+        let flags = match self.settings.channel_type {
+            ChannelType::Standard => REQUIRES_STANDARD_JOBS,
+            ChannelType::Extended => 0,
+        };
+        let setup_connection = SetupConnection {
+            protocol: Protocol::MiningProtocol,
+            min_version: 2,
+            max_version: 2,
+            flags,
+            endpoint_host: host.to_string().try_into().expect("Host too long"),
+            endpoint_port: port.parse().unwrap_or(0),
+            vendor: "sv2-miner-simulator".to_string().try_into().unwrap(),
+            hardware_version: "cpu".to_string().try_into().unwrap(),
+            firmware: env!("CARGO_PKG_VERSION").to_string().try_into().unwrap(),
+            device_id: self.worker().try_into().expect("User name too long"),
+        };
+        sleep(HANDSHAKE_SETTLE).await;
+        let message = PoolMessages::Common(CommonMessages::SetupConnection(setup_connection));
+        if send(&sender, message).await.is_err() {
+            return Ending::Disconnected;
+        }
+
+        loop {
+            tokio::select! {
+                frame = receiver.recv() => {
+                    let Some(frame) = frame else {
+                        return Ending::Disconnected;
+                    };
+                    if let Some(ending) = self.receive(frame, &sender).await {
+                        return ending;
+                    }
+                }
+                Some(share) = shares.recv() => {
+                    if self.submit(share, &sender).await.is_err() {
+                        return Ending::Disconnected;
+                    }
+                }
+            }
+        }
+    }
+
+    fn worker(&self) -> String {
+        format!("{}.{}", self.settings.user, self.device)
+    }
+
+    // Handles a frame of the upstream, returning how the session ends when it does
+    async fn receive(&mut self, frame: Frame_, sender: &Sender<Frame_>) -> Option<Ending> {
+        let Frame_::Sv2(mut frame) = frame else {
+            return None;
+        };
+        let message_type = frame.get_header()?.msg_type();
+        let mut payload = frame.payload().to_vec();
+        let message: PoolMessages<'_> = match (message_type, payload.as_mut_slice()).try_into() {
+            Ok(message) => message,
+            Err(e) => {
+                warn!(
+                    "Device {} received an invalid message {}: {:?}",
+                    self.device, message_type, e
+                );
+                return None;
+            }
+        };
+        match message {
+            PoolMessages::Common(CommonMessages::SetupConnectionSuccess(_)) => {
+                let opened = self.open_channel(sender).await;
+                return opened.err().map(|_| Ending::Disconnected);
+            }
+            PoolMessages::Common(CommonMessages::SetupConnectionError(m)) => {
+                error!(
+                    "Device {} connection refused: {}",
+                    self.device,
+                    String::from_utf8_lossy(&m.error_code.to_vec())
+                );
+                return Some(Ending::Disconnected);
+            }
+            PoolMessages::Mining(message) => return self.mining(message),
+            _ => {}
+        }
+        None
+    }
+
+    async fn open_channel(&mut self, sender: &Sender<Frame_>) -> Result<(), ()> {
+        let user_identity = self.worker().try_into().expect("User name too long");
+        let max_target = [0xff; 32].into();
+        let nominal_hash_rate = self.settings.hashrate as f32;
+        let message = match self.settings.channel_type {
+            ChannelType::Standard => Mining::OpenStandardMiningChannel(OpenStandardMiningChannel {
+                request_id: REQUEST_ID.into(),
+                user_identity,
+                nominal_hash_rate,
+                max_target,
+            }),
+            ChannelType::Extended => Mining::OpenExtendedMiningChannel(OpenExtendedMiningChannel {
+                request_id: REQUEST_ID,
+                user_identity,
+                nominal_hash_rate,
+                max_target,
+                min_extranonce_size: MIN_EXTRANONCE_SIZE,
+            }),
+        };
+        send(sender, PoolMessages::Mining(message)).await
+    }
+
+    fn mining(&mut self, message: Mining<'_>) -> Option<Ending> {
+        match message {
+            Mining::OpenStandardMiningChannelSuccess(m) => {
+                self.opened(OpenChannel {
+                    id: m.channel_id,
+                    group_id: Some(m.group_channel_id),
+                    extranonce_prefix: m.extranonce_prefix.to_vec(),
+                    extranonce_size: 0,
+                    target: bytes(m.target.inner_as_ref()),
+                });
+            }
+            Mining::OpenExtendedMiningChannelSuccess(m) => {
+                self.opened(OpenChannel {
+                    id: m.channel_id,
+                    group_id: None,
+                    extranonce_prefix: m.extranonce_prefix.to_vec(),
+                    extranonce_size: m.extranonce_size as usize,
+                    target: bytes(m.target.inner_as_ref()),
+                });
+            }
+            Mining::OpenMiningChannelError(m) => {
+                error!(
+                    "Device {} channel refused: {}",
+                    self.device,
+                    String::from_utf8_lossy(&m.error_code.to_vec())
+                );
+                return Some(Ending::Disconnected);
+            }
+            Mining::NewMiningJob(m) if self.is_channel(m.channel_id) => {
+                let job = Job {
+                    version: m.version,
+                    // The general purpose bits of standard jobs can always be rolled
+                    version_rolling: true,
+                    min_ntime: m.min_ntime.clone().into_inner(),
+                    kind: JobKind::Standard {
+                        merkle_root: bytes(m.merkle_root.inner_as_ref()),
+                    },
+                };
+                self.new_job(m.job_id, job);
+            }
+            Mining::NewExtendedMiningJob(m) if self.is_channel(m.channel_id) => {
+                let job = Job {
+                    version: m.version,
+                    version_rolling: m.version_rolling_allowed,
+                    min_ntime: m.min_ntime.clone().into_inner(),
+                    kind: JobKind::Extended {
+                        coinbase_prefix: m.coinbase_tx_prefix.to_vec(),
+                        coinbase_suffix: m.coinbase_tx_suffix.to_vec(),
+                        merkle_path: m
+                            .merkle_path
+                            .to_vec()
+                            .iter()
+                            .map(|hash| bytes(hash))
+                            .collect(),
+                    },
+                };
+                self.new_job(m.job_id, job);
+            }
+            Mining::SetNewPrevHash(m) if self.is_channel(m.channel_id) => {
+                self.prev_hash = Some(PrevHash {
+                    prev_hash: bytes(m.prev_hash.inner_as_ref()),
+                    min_ntime: m.min_ntime,
+                    bits: m.nbits,
+                });
+                // The jobs other than the one activated are no longer valid
+                self.jobs.retain(|job_id, _| *job_id == m.job_id);
+                self.activate(m.job_id);
+            }
+            Mining::SetTarget(m) if self.is_channel(m.channel_id) => {
+                if let Some(channel) = &mut self.channel {
+                    channel.target = bytes(m.maximum_target.inner_as_ref());
+                }
+                self.publish();
+            }
+            Mining::SetExtranoncePrefix(m) if self.is_channel(m.channel_id) => {
+                if let Some(channel) = &mut self.channel {
+                    channel.extranonce_prefix = m.extranonce_prefix.to_vec();
+                }
+                self.publish();
+            }
+            Mining::SubmitSharesSuccess(m) => {
+                self.metrics
+                    .shares
+                    .with_label_values(&[&self.device, "accepted"])
+                    .inc_by(m.new_submits_accepted_count as f64);
+            }
+            Mining::SubmitSharesError(m) => {
+                warn!(
+                    "Device {} share {} rejected: {}",
+                    self.device,
+                    m.sequence_number,
+                    String::from_utf8_lossy(&m.error_code.to_vec())
+                );
+                self.metrics
+                    .shares
+                    .with_label_values(&[&self.device, "rejected"])
+                    .inc();
+            }
+            Mining::CloseChannel(m) if self.is_channel(m.channel_id) => {
+                warn!(
+                    "Device {} channel closed: {}",
+                    self.device,
+                    String::from_utf8_lossy(&m.reason_code.to_vec())
+                );
+                return Some(Ending::Disconnected);
+            }
+            Mining::Reconnect(m) => {
+                let host = String::from_utf8_lossy(&m.new_host.to_vec()).to_string();
+                let (current_host, current_port) = self
+                    .upstream_address
+                    .rsplit_once(':')
+                    .unwrap_or((self.upstream_address.as_str(), "0"));
+                let host = if host.is_empty() {
+                    current_host.to_string()
+                } else {
+                    host
+                };
+                let port = if m.new_port == 0 {
+                    current_port.to_string()
+                } else {
+                    m.new_port.to_string()
+                };
+                return Some(Ending::Reconnect(format!("{}:{}", host, port)));
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn opened(&mut self, channel: OpenChannel) {
+        info!(
+            "Device {} opened channel {} as {}",
+            self.device,
+            channel.id,
+            self.worker()
+        );
+        self.metrics
+            .difficulty
+            .with_label_values(&[&self.device])
+            .set(hash::difficulty(&channel.target));
+        self.channel = Some(channel);
+    }
+
+    // Jobs may be sent to the group of a standard channel
+    fn is_channel(&self, channel_id: u32) -> bool {
+        self.channel
+            .as_ref()
+            .is_some_and(|channel| channel.id == channel_id || channel.group_id == Some(channel_id))
+    }
+
+    fn new_job(&mut self, job_id: u32, job: Job) {
+        let future = job.min_ntime.is_none();
+        self.jobs.insert(job_id, job);
+        if !future {
+            self.activate(job_id);
+        }
+    }
+
+    // Starts hashing a job, timing it until its first share
+    fn activate(&mut self, job_id: u32) {
+        if !self.jobs.contains_key(&job_id) || self.prev_hash.is_none() {
+            return;
+        }
+        self.active_job = Some(job_id);
+        self.activated_at = Some(Instant::now());
+        self.metrics.jobs.with_label_values(&[&self.device]).inc();
+        self.publish();
+    }
+
+    // Sends the active job, with the current state of the channel, to the hashing thread
+    fn publish(&mut self) {
+        let (Some(channel), Some(prev_hash), Some(job)) = (
+            &self.channel,
+            &self.prev_hash,
+            self.active_job.and_then(|job_id| self.jobs.get(&job_id)),
+        ) else {
+            return;
+        };
+        let merkle = match &job.kind {
+            JobKind::Standard { merkle_root } => Merkle::Root(*merkle_root),
+            JobKind::Extended {
+                coinbase_prefix,
+                coinbase_suffix,
+                merkle_path,
+            } => {
+                let mut prefix = coinbase_prefix.clone();
+                prefix.extend_from_slice(&channel.extranonce_prefix);
+                Merkle::Coinbase {
+                    prefix,
+                    suffix: coinbase_suffix.clone(),
+                    path: merkle_path.clone(),
+                    extranonce_size: channel.extranonce_size,
+                }
+            }
+        };
+        self.metrics
+            .difficulty
+            .with_label_values(&[&self.device])
+            .set(hash::difficulty(&channel.target));
+        self.work.send_replace(Some(Work {
+            job: self.active_job.unwrap_or_default(),
+            version: job.version,
+            version_mask: if job.version_rolling {
+                VERSION_ROLLING_MASK
+            } else {
+                0
+            },
+            prev_hash: prev_hash.prev_hash,
+            merkle,
+            time: job
+                .min_ntime
+                .unwrap_or(prev_hash.min_ntime)
+                .max(prev_hash.min_ntime),
+            bits: prev_hash.bits,
+            target: channel.target,
+        }));
+    }
+
+    async fn submit(&mut self, share: Share, sender: &Sender<Frame_>) -> Result<(), ()> {
+        let Some(channel) = &self.channel else {
+            return Ok(());
+        };
+        // Shares of replaced jobs may still be on their way from the hashing thread
+        if !self.jobs.contains_key(&share.job) {
+            return Ok(());
+        }
+        if self.active_job == Some(share.job) {
+            if let Some(activated_at) = self.activated_at.take() {
+                self.metrics
+                    .job_first_share_latency
+                    .with_label_values(&[&self.device])
+                    .set(activated_at.elapsed().as_millis() as f64);
+            }
+        }
+        if share.block {
+            info!(
+                "Device {} found a block with job {}",
+                self.device, share.job
+            );
+            self.metrics.blocks.with_label_values(&[&self.device]).inc();
+        }
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        let message = match self.settings.channel_type {
+            ChannelType::Standard => Mining::SubmitSharesStandard(SubmitSharesStandard {
+                channel_id: channel.id,
+                sequence_number: self.sequence_number,
+                job_id: share.job,
+                nonce: share.nonce,
+                ntime: share.time,
+                version: share.version,
+            }),
+            ChannelType::Extended => Mining::SubmitSharesExtended(SubmitSharesExtended {
+                channel_id: channel.id,
+                sequence_number: self.sequence_number,
+                job_id: share.job,
+                nonce: share.nonce,
+                ntime: share.time,
+                version: share.version,
+                extranonce: share
+                    .extranonce
+                    .try_into()
+                    .expect("The extranonce is at most 32 bytes"),
+            }),
+        };
+        self.metrics
+            .shares
+            .with_label_values(&[&self.device, "submitted"])
+            .inc();
+        send(sender, PoolMessages::Mining(message)).await
+    }
+}
+
+async fn send(sender: &Sender<Frame_>, message: PoolMessages<'static>) -> Result<(), ()> {
+    let frame: StdFrame = message
+        .try_into()
+        .expect("A message can always be converted in a frame");
+    sender.send(frame.into()).await.map_err(|_| ())
+}
+
+fn bytes(slice: &[u8]) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    let copied = slice.len().min(32);
+    bytes[..copied].copy_from_slice(&slice[..copied]);
+    bytes
+}
@@ -0,0 +1,76 @@
+use device::{ChannelType, DeviceMetrics, Settings};
+use prometheus::{Encoder, TextEncoder};
+use std::env;
+use std::sync::Arc;
+use warp::Filter;
+mod device;
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(
+        env_logger::Env::default()
+            .default_filter_or("info")
+            .default_write_style_or("always"),
+    )
+    .init();
+    let upstream_address =
+        env::var("UPSTREAM_ADDRESS").expect("UPSTREAM_ADDRESS environment variable not set");
+    let prometheus_exporter_address =
+        env::var("PROM_ADDRESS").unwrap_or_else(|_| "0.0.0.0:9100".to_string());
+    let devices: usize = env::var("DEVICES")
+        .ok()
+        .and_then(|devices| devices.parse().ok())
+        .unwrap_or(1);
+    let channel_type = match env::var("CHANNEL_TYPE").as_deref() {
+        Ok("extended") => ChannelType::Extended,
+        Ok("standard") | Err(_) => ChannelType::Standard,
+        Ok(other) => panic!(
+            "Unknown CHANNEL_TYPE {}, expected standard or extended",
+            other
+        ),
+    };
+    let settings = Arc::new(Settings {
+        upstream_address,
+        authority_pubkey: env::var("AUTHORITY_PUBKEY")
+            .ok()
+            .filter(|key| !key.is_empty())
+            .map(|key| key.parse().expect("Invalid AUTHORITY_PUBKEY")),
+        channel_type,
+        user: env::var("USER_NAME").unwrap_or_else(|_| "sv2-miner-simulator".to_string()),
+        hashrate: env::var("HASHRATE")
+            .ok()
+            .and_then(|hashrate| hashrate.parse().ok())
+            .filter(|hashrate: &f64| *hashrate > 0.0)
+            .unwrap_or(100_000.0),
+    });
+
+    let metrics = DeviceMetrics::register();
+    tokio::spawn(async move {
+        let metrics_route = warp::path("metrics").map(move || {
+            let encoder = TextEncoder::new();
+            let metric_families = prometheus::gather();
+            let mut buffer = Vec::new();
+            encoder.encode(&metric_families, &mut buffer).unwrap();
+            warp::http::Response::builder()
+                .header("Content-Type", encoder.format_type())
+                .body(buffer)
+        });
+        let addr: std::net::SocketAddr = prometheus_exporter_address
+            .parse()
+            .expect("Invalid address");
+        warp::serve(metrics_route).run(addr).await;
+    });
+
+    log::info!(
+        "Simulating {} devices of {} H/s mining on {}",
+        devices,
+        settings.hashrate,
+        settings.upstream_address
+    );
+    let devices: Vec<_> = (0..devices)
+        .map(|index| tokio::spawn(device::run(index, settings.clone(), metrics.clone())))
+        .collect();
+    for device in devices {
+        device.await.expect("Device task panicked");
+    }
+}